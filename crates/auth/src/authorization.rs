@@ -1,12 +1,43 @@
 use async_trait::async_trait;
+use std::time::{Duration, SystemTime};
 
 use crate::GoogleAuth;
 
 use super::error::GCloudAuthError;
 
+/// Tokens that expire within this margin are refreshed by `get_token`.
+pub const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// Whether a token that expires at `expires_at` is due for a refresh at `now`.
+pub(crate) fn needs_refresh(expires_at: SystemTime, now: SystemTime) -> bool {
+    expires_at <= now + TOKEN_EXPIRY_MARGIN
+}
+
 #[async_trait]
 pub trait Authorization: Send + Sync + std::fmt::Debug {
     fn project_id(&self) -> &str;
     async fn get_token(&self) -> Result<Option<String>, GCloudAuthError>;
     fn box_clone(&self) -> GoogleAuth;
+
+    /// When the token last returned by `get_token` expires, if known.
+    async fn token_expires_at(&self) -> Option<SystemTime> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_refreshed_within_the_margin() {
+        let now = SystemTime::now();
+        assert!(needs_refresh(now, now));
+        assert!(needs_refresh(now - Duration::from_secs(1), now));
+        assert!(needs_refresh(now + TOKEN_EXPIRY_MARGIN, now));
+        assert!(!needs_refresh(
+            now + TOKEN_EXPIRY_MARGIN + Duration::from_secs(1),
+            now
+        ));
+    }
 }
@@ -1,8 +1,10 @@
 use async_trait::async_trait;
 use chrono::prelude::*;
 use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::sync::Mutex;
 
+use crate::authorization::needs_refresh;
 use crate::{Authorization, GoogleAuth};
 
 use super::error::GCloudAuthError;
@@ -52,6 +54,10 @@ impl Authorization for CliAuthorization {
     fn box_clone(&self) -> GoogleAuth {
         Box::new(self.clone())
     }
+
+    async fn token_expires_at(&self) -> Option<SystemTime> {
+        Some(self.info.lock().await.expiry.into())
+    }
 }
 
 // -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
@@ -195,7 +201,7 @@ impl AuthInfo {
     }
 
     fn is_expired(&self) -> bool {
-        Utc::now() > self.expiry - chrono::Duration::seconds(30)
+        needs_refresh(self.expiry.into(), SystemTime::now())
     }
 }
 
@@ -249,6 +255,7 @@ fn fetch_auth_info() -> Result<(String, String), GCloudAuthError> {
 #[cfg(test)]
 mod tests {
     use super::AuthInfo;
+    use chrono::Utc;
 
     #[test]
     fn parse_auth_info_test() {
@@ -278,8 +285,12 @@ type: authorized_user
 valid: true
 ";
 
-        let info = AuthInfo::parse_auth_info("test", input).expect("parse auth info");
+        let mut info = AuthInfo::parse_auth_info("test", input).expect("parse auth info");
         assert!(info.is_expired());
+        info.expiry = Utc::now() + chrono::Duration::minutes(4);
+        assert!(info.is_expired(), "expires within the margin");
+        info.expiry = Utc::now() + chrono::Duration::minutes(10);
+        assert!(!info.is_expired());
         assert_eq!(info.access_token, "ya29.a0ARrdaM_3U5vY1fN9My33Z2QZCz7cVD6wRoTth6ztR48Y1sohrV0Sj9EKRDQVkiROjdi4VOpUxiWf22Nyrte1VVXi1NhGeFittxDNEGq-0ZzoMWUSdRk0DkzmktF2tFGcu_NCKjrPeiJTnoDgMqOP1SW2sLMVrXmkvozALQ");
     }
 }
@@ -13,6 +13,7 @@ use std::{
 use tokio::sync::RwLock;
 
 use self::refresh::{RefreshTokenRequest, RefreshTokenResponse};
+use crate::authorization::needs_refresh;
 
 /// Can be used before a user has logged in to make API requests
 #[derive(Clone, Debug)]
@@ -39,6 +40,11 @@ impl crate::Authorization for WebUserAnonAuth {
     async fn get_token(&self) -> Result<Option<String>, crate::error::GCloudAuthError> {
         Ok(None)
     }
+
+    /// Anonymous requests carry no token, so there is nothing that expires.
+    async fn token_expires_at(&self) -> Option<SystemTime> {
+        None
+    }
 }
 
 // -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
@@ -70,7 +76,8 @@ impl WebUserAuth {
     }
 
     async fn refresh_if_necessary(&self) -> Result<(), crate::error::GCloudAuthError> {
-        if self.login.read().await.expires_at() < SystemTime::now() {
+        let expires_at = self.login.read().await.expires_at();
+        if needs_refresh(expires_at, SystemTime::now()) {
             self.refresh().await?;
         }
         Ok(())
@@ -91,6 +98,10 @@ impl crate::Authorization for WebUserAuth {
         self.refresh_if_necessary().await?;
         Ok(Some(self.login.read().await.id_token.clone()))
     }
+
+    async fn token_expires_at(&self) -> Option<SystemTime> {
+        Some(self.login.read().await.expires_at())
+    }
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
@@ -131,3 +142,34 @@ impl WebLoginResult {
         self.refresh_token = refresh.refresh_token;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn login(expires_in: &str, requested_at: SystemTime) -> WebLoginResult {
+        WebLoginResult {
+            uid: "uid".to_string(),
+            email: "user@example.com".to_string(),
+            display_name: String::new(),
+            expires_in: expires_in.to_string(),
+            requested_at,
+            id_token: "id-token".to_string(),
+            kind: String::new(),
+            refresh_token: "refresh-token".to_string(),
+            registered: true,
+        }
+    }
+
+    #[test]
+    fn logins_expire_after_expires_in() {
+        let now = SystemTime::now();
+        let login = login("3600", now);
+        assert_eq!(login.expires_at(), now + Duration::from_secs(3600));
+        assert!(!needs_refresh(login.expires_at(), now));
+        assert!(needs_refresh(
+            login.expires_at(),
+            now + Duration::from_secs(3600) - crate::TOKEN_EXPIRY_MARGIN
+        ));
+    }
+}
@@ -38,14 +38,8 @@ impl RefreshTokenRequest {
 pub struct RefreshTokenResponse {
     /// The number of seconds in which the ID token expires.
     pub expires_in: String,
-    /// The type of the refresh token, always "Bearer".
-    pub token_type: String,
     /// The Identity Platform refresh token provided in the request or a new refresh token.
     pub refresh_token: String,
     /// An Identity Platform ID token.
     pub id_token: String,
-    /// The uid corresponding to the provided ID token.
-    pub user_id: String,
-    /// Your GCP project ID.
-    pub project_id: String,
 }
@@ -20,7 +20,7 @@ mod end_user;
 mod service_account;
 mod token;
 
-pub use authorization::Authorization;
+pub use authorization::{Authorization, TOKEN_EXPIRY_MARGIN};
 pub use cli::CliAuthorization;
 pub use end_user::{EmailSignin, WebClientConfig, WebLoginResult, WebUserAnonAuth, WebUserAuth};
 pub use service_account::{GoogleServiceAccount, ServiceAccountAuthorization};
@@ -2,6 +2,7 @@ use async_trait::async_trait;
 use serde::Deserialize;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::sync::Mutex;
 
 use super::error::GCloudAuthError;
@@ -98,4 +99,8 @@ impl Authorization for ServiceAccountAuthorization {
     fn box_clone(&self) -> GoogleAuth {
         Box::new(self.clone())
     }
+
+    async fn token_expires_at(&self) -> Option<SystemTime> {
+        self.token.lock().await.expires_at()
+    }
 }
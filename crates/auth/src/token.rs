@@ -8,6 +8,7 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use super::error::GCloudAuthError;
 use super::service_account::GoogleServiceAccount;
+use crate::authorization::needs_refresh;
 
 const GOOGLE_TOKEN_URL: &str = "https://www.googleapis.com/oauth2/v4/token";
 
@@ -176,12 +177,15 @@ impl GToken {
             })
     }
 
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.token.as_ref().map(|token| token.expires_at())
+    }
+
     pub async fn refresh_if_necessary(&mut self) -> Result<String, GCloudAuthError> {
         match &self.token {
             Some(token) => {
-                let now = SystemTime::now();
-                if token.expires_at() <= now {
-                    tracing::debug!("Google token is expired, refreshing");
+                if needs_refresh(token.expires_at(), SystemTime::now()) {
+                    tracing::debug!("Google token is about to expire, refreshing");
                     return self.refresh().await;
                 }
             }
@@ -1,12 +1,11 @@
 use clap::{Parser, ValueEnum};
 use firebase_client::{
     firestore::{
-        collection::CachedCollection, conversion::convert_document_fields_to_obj_with_id,
-        types::Document, FirebaseClient, FromFirestoreDocument,
+        conversion::convert_document_fields_to_obj_with_id, store::JsonFileStore,
+        synced::SyncedCollection, types::Document, FirebaseClient,
     },
-    FirestoreError,
+    CachedCollectionError, FirestoreError,
 };
-use futures::StreamExt;
 use serde_json::Value;
 use std::path::{Path, PathBuf};
 
@@ -58,7 +57,7 @@ enum Method {
 }
 
 #[tokio::main]
-async fn main() -> Result<(), FirestoreError> {
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt::init();
 
     let Options {
@@ -110,8 +109,7 @@ async fn main() -> Result<(), FirestoreError> {
             let json_list = res
                 .into_iter()
                 .map(convert_document_fields_to_obj_with_id::<Value>)
-                .map(|res| res.map_err(|err| err.into()))
-                .collect::<Result<Vec<_>, FirestoreError>>()
+                .collect::<Result<Vec<_>, _>>()
                 .expect("convert to json");
             let json = serde_json::to_string_pretty(&json_list)?;
 
@@ -202,75 +200,50 @@ async fn stream_collection(
     client: FirebaseClient,
     data_dir: impl AsRef<Path>,
     collection: impl ToString,
-) -> Result<(), FirestoreError> {
+) -> Result<(), CachedCollectionError> {
     let collection = collection.to_string();
-    let cache_file = format!("{}-collection.json", collection);
-    let mut builder = client.stream_builder(collection.to_string());
-
-    let cache_file = data_dir.as_ref().join(cache_file);
-
-    let mut collection = if cache_file.exists() {
-        let file = std::fs::OpenOptions::new()
-            .read(true)
-            .open(cache_file)
-            .expect("open file");
-        serde_json::from_reader(file).expect("deserialize cache")
-    } else {
-        CachedCollection::<Value>::new(collection.to_string())
-    };
-
-    if let Some(resume_token) = &collection.resume_token {
-        println!("got resume token, resuming stream");
-        builder = builder.resume_token(resume_token.clone());
-    }
-
-    let (mut stream, ctrl) = builder.build_retry(3).await?;
-
-    // -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
-
-    if false {
-        tokio::spawn(async move {
-            tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-            println!("STOPPING");
-            ctrl.stop().await;
-        });
-    }
-
-    while let Some(update) = stream.next().await {
-        let update = match update {
-            Err(err) => {
-                eprintln!("error in stream update: {}", err);
-                continue;
-            }
-            Ok(update) => update,
+    let cache_file = data_dir
+        .as_ref()
+        .join(format!("{}-collection.json", collection));
+
+    let synced = SyncedCollection::<Value>::builder(client, &collection)
+        .store(JsonFileStore::new(cache_file))
+        .start()
+        .await?;
+    println!("Have {} documents", synced.read().await.len());
+
+    let mut changes = synced.subscribe();
+
+    loop {
+        let changes = tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            changes = changes.recv() => match changes {
+                Ok(changes) => changes,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    eprintln!("missed {} updates", n);
+                    continue;
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            },
         };
 
-        for change in &update.changes {
+        let collection = synced.read().await;
+        for change in changes.iter() {
             use firebase_client::firestore::collection::CollectionChange::*;
             match change {
                 Change { id, time } => {
                     println!("changed doc={} time={}", id, time);
+                    if let Some(doc) = collection.documents.get(id) {
+                        println!("{}\n{}\n", id, serde_json::to_string_pretty(doc)?);
+                    }
                 }
                 Delete { id, time, .. } => {
                     println!("deleted doc={} time={}", id, time);
                 }
             }
         }
-
-        for (id, doc) in &update.documents {
-            let json = serde_json::Value::convert_doc(doc.clone())?;
-            println!("{}\n{}\n", id, serde_json::to_string_pretty(&json)?);
-        }
-
-        println!("Got {} new/changed documents", update.documents.len());
-        collection.update_from(update);
-        collection.save().expect("save cache");
-        println!("Have {} documents in total", collection.documents.len());
-
-        for (id, doc) in &collection.documents {
-            println!("{}\n{}\n", id, serde_json::to_string_pretty(doc)?);
-        }
+        println!("Have {} documents in total", collection.len());
     }
 
-    Ok(())
+    synced.shutdown().await
 }
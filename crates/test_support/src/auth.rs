@@ -0,0 +1,33 @@
+use async_trait::async_trait;
+use firebase_client_auth::{error::GCloudAuthError, Authorization, GoogleAuth};
+
+/// Hands out a fixed token, the fake server accepts any token.
+#[derive(Debug, Clone)]
+pub struct TestAuthorization {
+    project_id: String,
+    token: String,
+}
+
+impl TestAuthorization {
+    pub fn new<S: ToString>(project_id: S, token: S) -> Self {
+        Self {
+            project_id: project_id.to_string(),
+            token: token.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Authorization for TestAuthorization {
+    fn project_id(&self) -> &str {
+        &self.project_id
+    }
+
+    async fn get_token(&self) -> Result<Option<String>, GCloudAuthError> {
+        Ok(Some(self.token.clone()))
+    }
+
+    fn box_clone(&self) -> GoogleAuth {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,20 @@
+//! A fake of the Firestore gRPC service that runs in-process on a local port,
+//! so that the real transport of `firebase-client` (interceptors, streaming,
+//! resume tokens, retries) can be tested without Google.
+//!
+//! ```ignore
+//! let server = FakeFirestoreServer::start().await?;
+//! let client = server.client();
+//! server.fail_next(Rpc::GetDocument, Code::Unavailable);
+//! assert!(client.get_document("items/a").fetch().await.is_err());
+//! ```
+
+#![allow(clippy::result_large_err)]
+
+mod auth;
+mod server;
+mod service;
+
+pub use auth::TestAuthorization;
+pub use firestore_grpc::tonic::Code;
+pub use server::{FakeFirestoreServer, Rpc};
@@ -0,0 +1,551 @@
+use firebase_client::firestore::{memory::InMemoryFirestore, FirebaseClient, FirestoreBackend};
+use firestore_grpc::tonic::{
+    metadata::MetadataMap,
+    transport::{Endpoint, Server},
+    Code, Status,
+};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+
+use super::auth::TestAuthorization;
+use super::service::FirestoreService;
+
+/// The RPCs the fake server implements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rpc {
+    GetDocument,
+    ListDocuments,
+    ListCollectionIds,
+    BatchGetDocuments,
+    RunQuery,
+    UpdateDocument,
+    DeleteDocument,
+    BatchWrite,
+    Commit,
+    Listen,
+}
+
+impl Rpc {
+    /// The RPC of a request path like `/google.firestore.v1.Firestore/Listen`.
+    pub(crate) fn from_path(path: &str) -> Option<Self> {
+        let rpc = match path.strip_prefix("/google.firestore.v1.Firestore/")? {
+            "GetDocument" => Rpc::GetDocument,
+            "ListDocuments" => Rpc::ListDocuments,
+            "ListCollectionIds" => Rpc::ListCollectionIds,
+            "BatchGetDocuments" => Rpc::BatchGetDocuments,
+            "RunQuery" => Rpc::RunQuery,
+            "UpdateDocument" => Rpc::UpdateDocument,
+            "DeleteDocument" => Rpc::DeleteDocument,
+            "BatchWrite" => Rpc::BatchWrite,
+            "Commit" => Rpc::Commit,
+            "Listen" => Rpc::Listen,
+            _ => return None,
+        };
+        Some(rpc)
+    }
+}
+
+/// Faults injected into open listen streams.
+#[derive(Debug)]
+pub(crate) enum ListenFault {
+    /// Ends the stream without an error.
+    Drop,
+    /// Ends the stream with the status.
+    Fail(Status),
+    ExistenceFilter {
+        target_id: i32,
+        count: i32,
+    },
+}
+
+#[derive(Debug, Default)]
+struct ServerState {
+    failures: HashMap<Rpc, VecDeque<Status>>,
+    requests: HashMap<Rpc, usize>,
+    authorization: Option<String>,
+    metadata: MetadataMap,
+    listeners: Vec<mpsc::UnboundedSender<ListenFault>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Shared {
+    pub(crate) store: InMemoryFirestore,
+    state: Mutex<ServerState>,
+}
+
+impl Shared {
+    /// Records a call of `rpc` and returns the failure injected for it, if any.
+    pub(crate) fn begin(&self, rpc: Rpc, metadata: &MetadataMap) -> Result<(), Status> {
+        let mut state = self.state.lock().unwrap();
+        *state.requests.entry(rpc).or_default() += 1;
+        if let Some(authorization) = metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+        {
+            state.authorization = Some(authorization.to_string());
+        }
+        state.metadata = metadata.clone();
+        match state.failures.get_mut(&rpc).and_then(VecDeque::pop_front) {
+            Some(status) => {
+                tracing::debug!("injecting {:?} into {:?}", status.code(), rpc);
+                Err(status)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Registers an open listen stream.
+    pub(crate) fn add_listener(&self) -> mpsc::UnboundedReceiver<ListenFault> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.state.lock().unwrap().listeners.push(tx);
+        rx
+    }
+
+    fn send_listen_fault(&self, fault: impl Fn() -> ListenFault) {
+        self.state
+            .lock()
+            .unwrap()
+            .listeners
+            .retain(|listener| listener.send(fault()).is_ok());
+    }
+}
+
+/// A fake Firestore gRPC server listening on a local port, backed by an
+/// [`InMemoryFirestore`]. It stops when dropped.
+pub struct FakeFirestoreServer {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl FakeFirestoreServer {
+    pub async fn start() -> std::io::Result<Self> {
+        Self::start_with(InMemoryFirestore::with_project_id("test-project")).await
+    }
+
+    /// Starts a server that serves the documents of `store`.
+    pub async fn start_with(store: InMemoryFirestore) -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            store,
+            state: Mutex::new(ServerState::default()),
+        });
+
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let service = FirestoreService::new(shared.clone());
+        let incoming = Box::pin(async_stream::stream! {
+            loop {
+                yield listener.accept().await.map(|(stream, _)| stream);
+            }
+        });
+        tokio::spawn(async move {
+            let result = Server::builder()
+                .add_service(service)
+                .serve_with_incoming_shutdown(incoming, async {
+                    let _ = shutdown_rx.await;
+                })
+                .await;
+            if let Err(err) = result {
+                tracing::error!("fake firestore server failed: {}", err);
+            }
+        });
+
+        tracing::debug!("fake firestore server listening on {}", addr);
+
+        Ok(Self {
+            addr,
+            shared,
+            shutdown: Some(shutdown),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn endpoint(&self) -> Endpoint {
+        Endpoint::from_shared(format!("http://{}", self.addr)).expect("valid endpoint")
+    }
+
+    /// The documents the server serves.
+    pub fn store(&self) -> &InMemoryFirestore {
+        &self.shared.store
+    }
+
+    /// A client connected to the server, authorized with a
+    /// [`TestAuthorization`].
+    pub fn client(&self) -> FirebaseClient {
+        let project_id = self.shared.store.project_id().unwrap_or_default();
+        let auth = TestAuthorization::new(project_id.as_str(), "test-token");
+        FirebaseClient::with_endpoint(Box::new(auth), self.endpoint())
+    }
+
+    /// The next call of `rpc` fails with `code`. Calls queue up, i.e. calling
+    /// this twice fails the next two calls.
+    pub fn fail_next(&self, rpc: Rpc, code: Code) {
+        self.fail_next_with(rpc, Status::new(code, "injected fault"));
+    }
+
+    pub fn fail_next_with(&self, rpc: Rpc, status: Status) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.failures.entry(rpc).or_default().push_back(status);
+    }
+
+    /// How often `rpc` was called.
+    pub fn request_count(&self, rpc: Rpc) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        state.requests.get(&rpc).copied().unwrap_or_default()
+    }
+
+    /// The `authorization` header of the last request that had one.
+    pub fn last_authorization(&self) -> Option<String> {
+        self.shared.state.lock().unwrap().authorization.clone()
+    }
+
+    /// The metadata value `key` of the last request.
+    pub fn last_metadata(&self, key: &str) -> Option<String> {
+        let state = self.shared.state.lock().unwrap();
+        let value = state.metadata.get(key)?.to_str().ok()?;
+        Some(value.to_string())
+    }
+
+    /// The number of listen streams that are currently open.
+    pub fn listen_stream_count(&self) -> usize {
+        let mut state = self.shared.state.lock().unwrap();
+        state.listeners.retain(|listener| !listener.is_closed());
+        state.listeners.len()
+    }
+
+    /// Ends all open listen streams as if the connection was dropped.
+    pub fn drop_listen_streams(&self) {
+        self.shared.send_listen_fault(|| ListenFault::Drop);
+    }
+
+    /// Ends all open listen streams with `code`, e.g. `UNAVAILABLE` or
+    /// `ABORTED`.
+    pub fn fail_listen_streams(&self, code: Code) {
+        self.shared
+            .send_listen_fault(|| ListenFault::Fail(Status::new(code, "injected fault")));
+    }
+
+    /// Sends an `ExistenceFilter` claiming that `count` documents match
+    /// `target_id` to all open listen streams.
+    pub fn send_existence_filter(&self, target_id: i32, count: i32) {
+        self.shared
+            .send_listen_fault(|| ListenFault::ExistenceFilter { target_id, count });
+    }
+}
+
+impl Drop for FakeFirestoreServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use firebase_client::interceptor::{
+        HeaderValue, InterceptedRequest, InterceptedResponse, InterceptorError, RequestInterceptor,
+    };
+    use futures::StreamExt;
+    use pretty_assertions::assert_eq;
+
+    // the target id of `ListenRequestBuilder` streams
+    const TARGET_ID: i32 = 0x52757374;
+
+    fn ids(update: &firebase_client::firestore::collection::CollectionUpdate) -> Vec<&str> {
+        let mut ids = update
+            .documents
+            .keys()
+            .map(|name| name.rsplit('/').next().unwrap())
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn serves_documents_and_injects_failures() {
+        let server = FakeFirestoreServer::start().await.unwrap();
+        let client = server.client();
+
+        client
+            .update_document("items/a")
+            .field("rank", 1)
+            .update()
+            .await
+            .unwrap();
+        assert_eq!(
+            server.last_authorization().as_deref(),
+            Some("Bearer test-token")
+        );
+
+        server.fail_next(Rpc::GetDocument, Code::Unavailable);
+        let err = client.get_document("items/a").fetch().await.unwrap_err();
+        assert!(matches!(
+            err,
+            firebase_client::FirestoreError::GrpcError(status) if status.code() == Code::Unavailable
+        ));
+
+        let doc = client.get_document("items/a").fetch().await.unwrap();
+        assert!(doc.fields.contains_key("rank"));
+        assert_eq!(server.request_count(Rpc::GetDocument), 2);
+
+        let docs = client.run_query().from("items").fetch().await.unwrap();
+        assert_eq!(docs.len(), 1);
+    }
+
+    #[derive(Debug, Default)]
+    struct UserProject {
+        statuses: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl RequestInterceptor for UserProject {
+        async fn on_request(
+            &self,
+            request: &mut InterceptedRequest,
+        ) -> Result<(), InterceptorError> {
+            let params = format!("name={}", request.resource);
+            request.headers.insert(
+                "x-goog-user-project",
+                HeaderValue::from_static("billing-project"),
+            );
+            request
+                .headers
+                .insert("x-goog-request-params", HeaderValue::try_from(params)?);
+            Ok(())
+        }
+
+        fn on_response(&self, request: &InterceptedRequest, response: &InterceptedResponse) {
+            let status = format!("{} {}", request.method, response.status);
+            self.statuses.lock().unwrap().push(status);
+        }
+    }
+
+    #[tokio::test]
+    async fn interceptors_add_metadata_and_see_responses() {
+        let server = FakeFirestoreServer::start().await.unwrap();
+        let interceptor = UserProject::default();
+        let statuses = interceptor.statuses.clone();
+        let client = server.client();
+        client.add_interceptor(interceptor);
+
+        server.fail_next(Rpc::GetDocument, Code::NotFound);
+        assert!(client.get_document("items/a").fetch().await.is_err());
+        assert_eq!(
+            server.last_metadata("x-goog-user-project").as_deref(),
+            Some("billing-project")
+        );
+        assert_eq!(
+            server.last_metadata("x-goog-request-params").as_deref(),
+            Some("name=projects/test-project/databases/(default)/documents/items/a")
+        );
+        assert_eq!(
+            server.last_authorization().as_deref(),
+            Some("Bearer test-token")
+        );
+        assert_eq!(
+            *statuses.lock().unwrap(),
+            vec!["google.firestore.v1.Firestore/GetDocument NotFound"]
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_resume_after_dropped_connections() {
+        let server = FakeFirestoreServer::start().await.unwrap();
+        let client = server.client();
+        client
+            .update_document("items/a")
+            .field("rank", 1)
+            .update()
+            .await
+            .unwrap();
+
+        let (mut stream, controller) = client.stream_builder("items").build_retry(3).await.unwrap();
+        let update = stream.next().await.unwrap().unwrap();
+        assert_eq!(ids(&update), vec!["a"]);
+
+        server.drop_listen_streams();
+        client
+            .update_document("items/b")
+            .field("rank", 2)
+            .update()
+            .await
+            .unwrap();
+
+        let update = stream.next().await.unwrap().unwrap();
+        assert_eq!(ids(&update), vec!["b"]);
+        assert_eq!(server.request_count(Rpc::Listen), 2);
+
+        controller.stop().await;
+    }
+
+    #[tokio::test]
+    async fn existence_filter_mismatches_reset_streams() {
+        let server = FakeFirestoreServer::start().await.unwrap();
+        let client = server.client();
+        client
+            .update_document("items/a")
+            .field("rank", 1)
+            .update()
+            .await
+            .unwrap();
+
+        let (mut stream, controller) = client.stream_builder("items").build_retry(3).await.unwrap();
+        let update = stream.next().await.unwrap().unwrap();
+        assert_eq!(ids(&update), vec!["a"]);
+        assert_eq!(server.listen_stream_count(), 1);
+
+        // the server claims a second document exists, the client listens again
+        // and gets all documents
+        server.send_existence_filter(TARGET_ID, 2);
+        let update = stream.next().await.unwrap().unwrap();
+        assert_eq!(ids(&update), vec!["a"]);
+
+        controller.stop().await;
+    }
+
+    /// Hands out a new token on every call that expires `ttl` later.
+    #[derive(Debug, Clone)]
+    struct ExpiringAuthorization {
+        ttl: std::time::Duration,
+        issued: Arc<Mutex<(usize, std::time::SystemTime)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl firebase_client_auth::Authorization for ExpiringAuthorization {
+        fn project_id(&self) -> &str {
+            "test-project"
+        }
+
+        async fn get_token(
+            &self,
+        ) -> Result<Option<String>, firebase_client_auth::error::GCloudAuthError> {
+            let mut issued = self.issued.lock().unwrap();
+            *issued = (issued.0 + 1, std::time::SystemTime::now() + self.ttl);
+            Ok(Some(format!("token-{}", issued.0)))
+        }
+
+        fn box_clone(&self) -> firebase_client_auth::GoogleAuth {
+            Box::new(self.clone())
+        }
+
+        async fn token_expires_at(&self) -> Option<std::time::SystemTime> {
+            Some(self.issued.lock().unwrap().1)
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_reconnect_with_fresh_tokens_before_they_expire() {
+        let server = FakeFirestoreServer::start().await.unwrap();
+        let issued = Arc::new(Mutex::new((0, std::time::SystemTime::now())));
+        let auth = ExpiringAuthorization {
+            // the stream reconnects half the margin before the expiry
+            ttl: firebase_client_auth::TOKEN_EXPIRY_MARGIN / 2 + std::time::Duration::from_secs(1),
+            issued: issued.clone(),
+        };
+        let client = FirebaseClient::with_endpoint(Box::new(auth), server.endpoint());
+        client
+            .update_document("items/a")
+            .field("rank", 1)
+            .update()
+            .await
+            .unwrap();
+
+        let (mut stream, controller) = client.stream_builder("items").build_retry(3).await.unwrap();
+        let update = stream.next().await.unwrap().unwrap();
+        assert_eq!(ids(&update), vec!["a"]);
+        assert_eq!(
+            server.last_authorization().as_deref(),
+            Some("Bearer token-2")
+        );
+
+        client
+            .update_document("items/b")
+            .field("rank", 2)
+            .update()
+            .await
+            .unwrap();
+        let update = stream.next().await.unwrap().unwrap();
+        assert_eq!(ids(&update), vec!["b"]);
+
+        // the stream reconnects on its own while it waits for the next update
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(5);
+        while server.request_count(Rpc::Listen) < 2 {
+            assert!(tokio::time::Instant::now() < deadline, "no reconnect");
+            let _ =
+                tokio::time::timeout(std::time::Duration::from_millis(100), stream.next()).await;
+        }
+        let latest = format!("Bearer token-{}", issued.lock().unwrap().0);
+        assert_eq!(server.last_authorization(), Some(latest));
+
+        controller.stop().await;
+    }
+
+    #[tokio::test]
+    async fn offline_writes_are_replayed_acknowledged_and_rejected() {
+        use firebase_client::firestore::offline::{OfflineWriteQueue, WriteEvent};
+        use firebase_client::firestore::types::Document;
+
+        let server = FakeFirestoreServer::start().await.unwrap();
+        let client = server.client();
+        let name = |id: &str| format!("{}/items/{}", client.documents_path(), id);
+        let queue = Arc::new(OfflineWriteQueue::new(client.clone()));
+        let mut events = queue.subscribe();
+
+        // offline, the write stays queued
+        server.fail_next(Rpc::Commit, Code::Unavailable);
+        queue
+            .update(Document {
+                name: name("a"),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(queue.flush().await.is_err());
+        assert_eq!(queue.pending().len(), 1);
+
+        // a rejected write is dropped and does not block the writes behind it
+        server.fail_next(Rpc::Commit, Code::Unavailable);
+        server.fail_next(Rpc::Commit, Code::PermissionDenied);
+        queue.delete(name("b")).unwrap();
+        let replay = queue.spawn_replay(std::time::Duration::from_millis(20));
+        let mut acknowledged = Vec::new();
+        let mut rejected = Vec::new();
+        while acknowledged.len() + rejected.len() < 2 {
+            match events.recv().await.unwrap() {
+                WriteEvent::Pending { .. } => {}
+                WriteEvent::Acknowledged { id, .. } => acknowledged.push(id),
+                WriteEvent::Rejected { id, .. } => rejected.push(id),
+            }
+        }
+        replay.abort();
+        assert_eq!(acknowledged, vec![2]);
+        assert_eq!(rejected, vec![1]);
+        assert!(queue.pending().is_empty());
+        assert!(server.store().document(&name("a")).is_none());
+
+        // an unauthenticated write is retried with a fresh token and, if that
+        // fails too, stays queued
+        server.fail_next(Rpc::Commit, Code::Unauthenticated);
+        queue.delete(name("c")).unwrap();
+        let result = queue.flush().await.unwrap();
+        assert_eq!(result.acknowledged, vec![3]);
+        assert!(result.rejected.is_empty());
+        assert!(queue.pending().is_empty());
+
+        server.fail_next(Rpc::Commit, Code::Unauthenticated);
+        server.fail_next(Rpc::Commit, Code::Unauthenticated);
+        queue.delete(name("d")).unwrap();
+        assert!(queue.flush().await.is_err());
+        assert_eq!(queue.pending().len(), 1);
+        let result = queue.flush().await.unwrap();
+        assert_eq!(result.acknowledged, vec![4]);
+    }
+}
@@ -0,0 +1,329 @@
+use firebase_client::firestore::backend::{ListenRequestStream, ListenResponseStream};
+use firebase_client::firestore::{memory::InMemoryFirestore, FirestoreBackend};
+use firebase_client::FirestoreError;
+use firestore_grpc::tonic::{
+    body::BoxBody,
+    codec::ProstCodec,
+    codegen::{empty_body, http, Body, BoxFuture, Context, Never, Poll, Service, StdError},
+    server::Grpc,
+    transport::NamedService,
+    Request, Response, Status, Streaming,
+};
+use firestore_grpc::v1::{self as firestore, listen_response::ResponseType};
+use futures::{future, Stream, StreamExt};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use super::server::{ListenFault, Rpc, Shared};
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+fn into_status(err: FirestoreError) -> Status {
+    match err {
+        FirestoreError::GrpcError(status) => *status,
+        err => Status::internal(err.to_string()),
+    }
+}
+
+/// Adapts a closure to the service traits [`Grpc`] expects. Every request gets
+/// its own handler.
+struct Handler<F>(Option<F>);
+
+impl<R, T, F, Fut> Service<Request<R>> for Handler<F>
+where
+    F: FnOnce(Request<R>) -> Fut,
+    Fut: Future<Output = Result<Response<T>, Status>> + Send + 'static,
+{
+    type Response = Response<T>;
+    type Error = Status;
+    type Future = BoxFuture<Response<T>, Status>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Status>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<R>) -> Self::Future {
+        let handler = self.0.take().expect("handlers are called once");
+        Box::pin(handler(req))
+    }
+}
+
+/// Serves the `google.firestore.v1.Firestore` service. `firestore_grpc` only
+/// contains the client, so this is what `tonic-build` would generate for the
+/// RPCs the client uses.
+#[derive(Clone)]
+pub(crate) struct FirestoreService {
+    shared: Arc<Shared>,
+}
+
+impl FirestoreService {
+    pub(crate) fn new(shared: Arc<Shared>) -> Self {
+        Self { shared }
+    }
+}
+
+impl NamedService for FirestoreService {
+    const NAME: &'static str = "google.firestore.v1.Firestore";
+}
+
+impl<B> Service<http::Request<B>> for FirestoreService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Never;
+    type Future = BoxFuture<Self::Response, Never>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Never>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let shared = self.shared.clone();
+
+        Box::pin(async move {
+            let rpc = match Rpc::from_path(req.uri().path()) {
+                Some(rpc) => rpc,
+                None => return Ok(unimplemented()),
+            };
+
+            let response = match rpc {
+                Rpc::GetDocument => {
+                    unary(
+                        shared,
+                        rpc,
+                        req,
+                        |store, req: firestore::GetDocumentRequest| async move {
+                            store.get_document(req).await
+                        },
+                    )
+                    .await
+                }
+                Rpc::ListDocuments => {
+                    unary(
+                        shared,
+                        rpc,
+                        req,
+                        |store, req: firestore::ListDocumentsRequest| async move {
+                            store.list_documents(req).await
+                        },
+                    )
+                    .await
+                }
+                Rpc::ListCollectionIds => {
+                    unary(
+                        shared,
+                        rpc,
+                        req,
+                        |store, req: firestore::ListCollectionIdsRequest| async move {
+                            store.list_collection_ids(req).await
+                        },
+                    )
+                    .await
+                }
+                Rpc::UpdateDocument => {
+                    unary(
+                        shared,
+                        rpc,
+                        req,
+                        |store, req: firestore::UpdateDocumentRequest| async move {
+                            store.update_document(req).await
+                        },
+                    )
+                    .await
+                }
+                Rpc::DeleteDocument => {
+                    unary(
+                        shared,
+                        rpc,
+                        req,
+                        |store, req: firestore::DeleteDocumentRequest| async move {
+                            store.delete_document(req).await
+                        },
+                    )
+                    .await
+                }
+                Rpc::BatchWrite => {
+                    unary(
+                        shared,
+                        rpc,
+                        req,
+                        |store, req: firestore::BatchWriteRequest| async move {
+                            store.batch_write(req).await
+                        },
+                    )
+                    .await
+                }
+                Rpc::Commit => unary(
+                    shared,
+                    rpc,
+                    req,
+                    |store, req: firestore::CommitRequest| async move { store.commit(req).await },
+                )
+                .await,
+                Rpc::BatchGetDocuments => {
+                    server_streaming(
+                        shared,
+                        rpc,
+                        req,
+                        |store, req: firestore::BatchGetDocumentsRequest| async move {
+                            store.batch_get_documents(req).await
+                        },
+                    )
+                    .await
+                }
+                Rpc::RunQuery => {
+                    server_streaming(
+                        shared,
+                        rpc,
+                        req,
+                        |store, req: firestore::RunQueryRequest| async move {
+                            store.run_query(req).await
+                        },
+                    )
+                    .await
+                }
+                Rpc::Listen => listen(shared, req).await,
+            };
+
+            Ok(response)
+        })
+    }
+}
+
+fn unimplemented() -> http::Response<BoxBody> {
+    http::Response::builder()
+        .status(200)
+        .header("grpc-status", "12")
+        .header("content-type", "application/grpc")
+        .body(empty_body())
+        .unwrap()
+}
+
+async fn unary<B, M1, M2, F, Fut>(
+    shared: Arc<Shared>,
+    rpc: Rpc,
+    req: http::Request<B>,
+    call: F,
+) -> http::Response<BoxBody>
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send,
+    M1: prost::Message + Default + Send + 'static,
+    M2: prost::Message + Send + 'static,
+    F: FnOnce(InMemoryFirestore, M1) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<M2, FirestoreError>> + Send + 'static,
+{
+    let handler = move |req: Request<M1>| async move {
+        shared.begin(rpc, req.metadata())?;
+        let res = call(shared.store.clone(), req.into_inner()).await;
+        res.map(Response::new).map_err(into_status)
+    };
+    Grpc::new(ProstCodec::<M2, M1>::default())
+        .unary(Handler(Some(handler)), req)
+        .await
+}
+
+/// The backend collects streamed responses, they are streamed again here.
+async fn server_streaming<B, M1, M2, F, Fut>(
+    shared: Arc<Shared>,
+    rpc: Rpc,
+    req: http::Request<B>,
+    call: F,
+) -> http::Response<BoxBody>
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send,
+    M1: prost::Message + Default + Send + 'static,
+    M2: prost::Message + Send + 'static,
+    F: FnOnce(InMemoryFirestore, M1) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Vec<Result<M2, Status>>, FirestoreError>> + Send + 'static,
+{
+    let handler = move |req: Request<M1>| async move {
+        shared.begin(rpc, req.metadata())?;
+        let responses = call(shared.store.clone(), req.into_inner())
+            .await
+            .map_err(into_status)?;
+        let stream: ResponseStream<M2> = Box::pin(futures::stream::iter(responses));
+        Ok(Response::new(stream))
+    };
+    Grpc::new(ProstCodec::<M2, M1>::default())
+        .server_streaming(Handler(Some(handler)), req)
+        .await
+}
+
+async fn listen<B>(shared: Arc<Shared>, req: http::Request<B>) -> http::Response<BoxBody>
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send,
+{
+    let handler = move |req: Request<Streaming<firestore::ListenRequest>>| async move {
+        shared.begin(Rpc::Listen, req.metadata())?;
+        let database = req
+            .metadata()
+            .get("google-cloud-resource-prefix")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+
+        // the stream of requests ends with the first error
+        let requests: ListenRequestStream = Box::pin(
+            req.into_inner()
+                .take_while(|req| future::ready(req.is_ok()))
+                .filter_map(|req| future::ready(req.ok())),
+        );
+        let responses = shared
+            .store
+            .listen(database, requests)
+            .await
+            .map_err(into_status)?;
+        Ok(Response::new(with_faults(responses, shared.add_listener())))
+    };
+    Grpc::new(ProstCodec::<
+        firestore::ListenResponse,
+        firestore::ListenRequest,
+    >::default())
+    .streaming(Handler(Some(handler)), req)
+    .await
+}
+
+/// Forwards `responses` until a fault ends the stream.
+fn with_faults(
+    mut responses: ListenResponseStream,
+    mut faults: mpsc::UnboundedReceiver<ListenFault>,
+) -> ResponseStream<firestore::ListenResponse> {
+    Box::pin(async_stream::stream! {
+        loop {
+            let next = tokio::select! {
+                res = responses.next() => match res {
+                    None => break,
+                    Some(res) => res,
+                },
+
+                fault = faults.recv() => match fault {
+                    None | Some(ListenFault::Drop) => break,
+                    Some(ListenFault::Fail(status)) => Err(status),
+                    Some(ListenFault::ExistenceFilter { target_id, count }) => {
+                        Ok(firestore::ListenResponse {
+                            response_type: Some(ResponseType::Filter(firestore::ExistenceFilter {
+                                target_id,
+                                count,
+                                ..Default::default()
+                            })),
+                        })
+                    }
+                },
+            };
+
+            let failed = next.is_err();
+            yield next;
+            if failed {
+                break;
+            }
+        }
+        tracing::debug!("fake listen stream ended");
+    })
+}
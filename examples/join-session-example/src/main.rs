@@ -43,7 +43,8 @@ async fn main() {
 
     // the firestore client
     let fb_client = firebase_client::firestore::FirebaseClient::new(auth.box_clone());
-    let rdb_client = firebase_client::rdb::RdbClient::new(auth);
+    // not used until `CoScreenSessionConnection::join_waiting` is implemented
+    let _rdb_client = firebase_client::rdb::RdbClient::new(auth);
 
     // list all coscreens of that user
     let coscreens = fb_client
@@ -64,11 +65,14 @@ async fn main() {
     println!("{:#?}", coscreen);
 }
 
+// a sketch of joining a session, `join_waiting` is not implemented yet
+#[allow(dead_code)]
 struct CoScreenSessionConnection {
     id: String,
     rdb_client: RdbClient,
 }
 
+#[allow(dead_code)]
 impl CoScreenSessionConnection {
     pub fn new(id: String, rdb_client: RdbClient) -> Self {
         Self { id, rdb_client }
@@ -0,0 +1,283 @@
+//! [App Check](https://firebase.google.com/docs/app-check) tokens for clients
+//! of Firestore and realtime DB instances that enforce App Check. The token of
+//! an [`AppCheckProvider`] is attached to every request as
+//! `X-Firebase-AppCheck`, see
+//! [`FirebaseClient::add_app_check`](crate::firestore::FirebaseClient::add_app_check)
+//! and [`RdbClient::add_app_check`](crate::rdb::RdbClient::add_app_check).
+
+use async_trait::async_trait;
+use firebase_client_auth::{WebClientConfig, TOKEN_EXPIRY_MARGIN};
+use reqwest::header::HeaderValue;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+use url::Url;
+
+use crate::interceptor::{InterceptedRequest, InterceptorError, RequestInterceptor};
+use crate::AppCheckError;
+
+const URL: &str = "https://firebaseappcheck.googleapis.com";
+pub const APP_CHECK_HEADER: &str = "x-firebase-appcheck";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppCheckToken {
+    pub token: String,
+    pub expires_at: SystemTime,
+}
+
+#[async_trait]
+pub trait AppCheckProvider: Send + Sync + fmt::Debug {
+    /// A new token, called whenever the cached one is about to expire.
+    async fn get_token(&self) -> Result<AppCheckToken, AppCheckError>;
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// exchange
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum ExchangeRequest<'a> {
+    DebugToken(&'a str),
+    CustomToken(&'a str),
+}
+
+#[derive(Debug, Deserialize)]
+struct ExchangeResponse {
+    token: String,
+    /// A duration like `"3600s"`.
+    ttl: String,
+}
+
+/// The app of the exchange endpoints, `projects/{project}/apps/{app_id}`.
+#[derive(Debug, Clone)]
+struct App {
+    base_url: Url,
+    project_id: String,
+    app_id: String,
+    api_key: String,
+}
+
+impl App {
+    fn new(config: &WebClientConfig) -> Self {
+        Self {
+            base_url: Url::parse(URL).expect("valid url"),
+            project_id: config.project_id.clone(),
+            app_id: config.app_id.clone(),
+            api_key: config.api_key.clone(),
+        }
+    }
+
+    async fn exchange(
+        &self,
+        method: &str,
+        req: ExchangeRequest<'_>,
+    ) -> Result<AppCheckToken, AppCheckError> {
+        let url = self.base_url.join(&format!(
+            "v1/projects/{}/apps/{}:{}",
+            self.project_id, self.app_id, method
+        ))?;
+        let url = Url::parse_with_params(url.as_str(), [("key", self.api_key.as_str())])?;
+        tracing::debug!("app check {} for app {}", method, self.app_id);
+
+        let requested_at = SystemTime::now();
+        let res = reqwest::Client::new().post(url).json(&req).send().await?;
+        let status = res.status();
+        if !status.is_success() {
+            let message = res.text().await.unwrap_or_default();
+            return Err(AppCheckError::RequestFailure(status, message));
+        }
+        let ExchangeResponse { token, ttl } = serde_json::from_str(&res.text().await?)?;
+        Ok(AppCheckToken {
+            token,
+            expires_at: requested_at + parse_ttl(&ttl)?,
+        })
+    }
+}
+
+/// Parses proto3 JSON durations like `"3600s"` or `"1.5s"`.
+fn parse_ttl(ttl: &str) -> Result<Duration, AppCheckError> {
+    ttl.strip_suffix('s')
+        .and_then(|secs| secs.parse::<f64>().ok())
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| AppCheckError::InvalidTtl(ttl.to_string()))
+}
+
+/// Exchanges a debug token, registered in the App Check section of the
+/// Firebase console, for App Check tokens. For development and CI only.
+#[derive(Debug, Clone)]
+pub struct DebugTokenProvider {
+    app: App,
+    debug_token: String,
+}
+
+impl DebugTokenProvider {
+    pub fn new(config: &WebClientConfig, debug_token: impl ToString) -> Self {
+        Self {
+            app: App::new(config),
+            debug_token: debug_token.to_string(),
+        }
+    }
+
+    /// Sends requests to `base_url` instead of
+    /// `https://firebaseappcheck.googleapis.com`.
+    #[must_use]
+    pub fn with_base_url(mut self, base_url: Url) -> Self {
+        self.app.base_url = base_url;
+        self
+    }
+}
+
+#[async_trait]
+impl AppCheckProvider for DebugTokenProvider {
+    async fn get_token(&self) -> Result<AppCheckToken, AppCheckError> {
+        let req = ExchangeRequest::DebugToken(&self.debug_token);
+        self.app.exchange("exchangeDebugToken", req).await
+    }
+}
+
+/// Mints the custom tokens of a [`CustomTokenProvider`], usually by asking a
+/// backend that attests the app and signs tokens with the Admin SDK.
+#[async_trait]
+pub trait CustomTokenSource: Send + Sync + fmt::Debug {
+    async fn custom_token(&self) -> Result<String, AppCheckError>;
+}
+
+/// Exchanges the tokens of a custom App Check provider for App Check tokens.
+#[derive(Debug)]
+pub struct CustomTokenProvider<S> {
+    app: App,
+    source: S,
+}
+
+impl<S: CustomTokenSource> CustomTokenProvider<S> {
+    pub fn new(config: &WebClientConfig, source: S) -> Self {
+        Self {
+            app: App::new(config),
+            source,
+        }
+    }
+
+    /// Sends requests to `base_url` instead of
+    /// `https://firebaseappcheck.googleapis.com`.
+    #[must_use]
+    pub fn with_base_url(mut self, base_url: Url) -> Self {
+        self.app.base_url = base_url;
+        self
+    }
+}
+
+#[async_trait]
+impl<S: CustomTokenSource> AppCheckProvider for CustomTokenProvider<S> {
+    async fn get_token(&self) -> Result<AppCheckToken, AppCheckError> {
+        let custom_token = self.source.custom_token().await?;
+        let req = ExchangeRequest::CustomToken(&custom_token);
+        self.app.exchange("exchangeCustomToken", req).await
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// interceptor
+
+/// Caches the token of a provider and attaches it to requests. A new token is
+/// fetched when the cached one expires within [`TOKEN_EXPIRY_MARGIN`].
+#[derive(Debug)]
+pub struct AppCheckInterceptor {
+    provider: Box<dyn AppCheckProvider>,
+    token: Mutex<Option<AppCheckToken>>,
+}
+
+impl AppCheckInterceptor {
+    pub fn new<P: AppCheckProvider + 'static>(provider: P) -> Self {
+        Self {
+            provider: Box::new(provider),
+            token: Mutex::new(None),
+        }
+    }
+
+    /// The cached token or a new one.
+    pub async fn token(&self) -> Result<String, AppCheckError> {
+        let mut cached = self.token.lock().await;
+        let refresh_at = SystemTime::now() + TOKEN_EXPIRY_MARGIN;
+        match &*cached {
+            Some(token) if token.expires_at > refresh_at => Ok(token.token.clone()),
+            _ => {
+                let token = self.provider.get_token().await?;
+                tracing::debug!("fetched app check token");
+                let value = token.token.clone();
+                *cached = Some(token);
+                Ok(value)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl RequestInterceptor for AppCheckInterceptor {
+    async fn on_request(&self, request: &mut InterceptedRequest) -> Result<(), InterceptorError> {
+        let token = self.token().await?;
+        request
+            .headers
+            .insert(APP_CHECK_HEADER, HeaderValue::try_from(token)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug)]
+    struct Counting {
+        calls: AtomicUsize,
+        ttl: Duration,
+    }
+
+    #[async_trait]
+    impl AppCheckProvider for Counting {
+        async fn get_token(&self) -> Result<AppCheckToken, AppCheckError> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(AppCheckToken {
+                token: format!("token-{call}"),
+                expires_at: SystemTime::now() + self.ttl,
+            })
+        }
+    }
+
+    async fn header(interceptor: &AppCheckInterceptor) -> String {
+        let mut request = InterceptedRequest::new("firestore", "GET".to_string(), String::new());
+        interceptor.on_request(&mut request).await.unwrap();
+        request.headers[APP_CHECK_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn caches_tokens_until_they_are_about_to_expire() {
+        let interceptor = AppCheckInterceptor::new(Counting {
+            calls: AtomicUsize::new(0),
+            ttl: Duration::from_secs(3600),
+        });
+        assert_eq!(header(&interceptor).await, "token-0");
+        assert_eq!(header(&interceptor).await, "token-0");
+
+        let interceptor = AppCheckInterceptor::new(Counting {
+            calls: AtomicUsize::new(0),
+            ttl: TOKEN_EXPIRY_MARGIN / 2,
+        });
+        assert_eq!(header(&interceptor).await, "token-0");
+        assert_eq!(header(&interceptor).await, "token-1");
+
+        assert_eq!(parse_ttl("3600s").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_ttl("1.5s").unwrap(), Duration::from_millis(1500));
+        assert!(parse_ttl("3600").is_err());
+        assert_eq!(
+            serde_json::to_value(ExchangeRequest::DebugToken("abc")).unwrap(),
+            serde_json::json!({ "debugToken": "abc" })
+        );
+    }
+}
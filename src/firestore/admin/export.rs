@@ -0,0 +1,304 @@
+use reqwest::Method;
+use serde::Serialize;
+use std::time::Duration;
+
+use super::{AdminClient, Operation};
+use crate::FirestoreAdminError;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+const TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The request of both exports and imports, only one of the prefixes is set.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TransferDocumentsRequest<'a> {
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    collection_ids: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output_uri_prefix: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input_uri_prefix: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    namespace_ids: &'a [String],
+}
+
+/// Logs the progress of an export or import operation.
+fn log_progress(operation: &Operation) {
+    if let Some(progress) = operation.progress() {
+        tracing::info!(
+            "operation {} {}: {}/{} documents, {}/{} bytes",
+            operation.name,
+            progress.operation_state,
+            progress.progress_documents.completed_work,
+            progress.progress_documents.estimated_work,
+            progress.progress_bytes.completed_work,
+            progress.progress_bytes.estimated_work,
+        );
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// export and import
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferDirection {
+    /// From the database to Cloud Storage.
+    Export,
+    /// From Cloud Storage to the database.
+    Import,
+}
+
+#[derive(Debug)]
+pub struct TransferDocumentsOptions<'a> {
+    pub admin: &'a AdminClient,
+    pub direction: TransferDirection,
+    /// The output prefix of exports, the input prefix of imports.
+    pub uri_prefix: String,
+    pub collection_ids: Vec<String>,
+    pub namespace_ids: Vec<String>,
+    pub poll_interval: Duration,
+    pub timeout: Duration,
+}
+
+impl<'a> TransferDocumentsOptions<'a> {
+    fn new(
+        admin: &'a AdminClient,
+        direction: TransferDirection,
+        uri_prefix: String,
+        collection_ids: Vec<String>,
+    ) -> Self {
+        Self {
+            admin,
+            direction,
+            uri_prefix,
+            collection_ids,
+            namespace_ids: Vec::new(),
+            poll_interval: POLL_INTERVAL,
+            timeout: TIMEOUT,
+        }
+    }
+
+    #[must_use]
+    pub fn namespace_ids(mut self, namespace_ids: &[impl ToString]) -> Self {
+        self.namespace_ids = namespace_ids.iter().map(ToString::to_string).collect();
+        self
+    }
+
+    /// How often [`Self::run`] polls the operation, 10s by default.
+    #[must_use]
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// How long [`Self::run`] waits for the operation, 24h by default.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Starts the export or import and returns the running operation.
+    pub async fn start(&self) -> Result<Operation, FirestoreAdminError> {
+        let mut req = TransferDocumentsRequest {
+            collection_ids: &self.collection_ids,
+            output_uri_prefix: None,
+            input_uri_prefix: None,
+            namespace_ids: &self.namespace_ids,
+        };
+        let method = match self.direction {
+            TransferDirection::Export => {
+                tracing::info!("exporting documents to {}", self.uri_prefix);
+                req.output_uri_prefix = Some(&self.uri_prefix);
+                "exportDocuments"
+            }
+            TransferDirection::Import => {
+                tracing::info!("importing documents from {}", self.uri_prefix);
+                req.input_uri_prefix = Some(&self.uri_prefix);
+                "importDocuments"
+            }
+        };
+        let resource = format!("{}:{}", self.admin.database_path(), method);
+        self.admin
+            .request(Method::POST, &resource, &[], Some(&req))
+            .await
+    }
+
+    /// Starts the export or import and waits until it is done. `on_progress`
+    /// is called with every polled state, see [`Operation::progress`].
+    pub async fn run<F>(self, mut on_progress: F) -> Result<Operation, FirestoreAdminError>
+    where
+        F: FnMut(&Operation),
+    {
+        let operation = self.start().await?;
+        self.admin
+            .wait_for_operation_with_progress(
+                operation,
+                self.poll_interval,
+                self.timeout,
+                |operation| {
+                    log_progress(operation);
+                    on_progress(operation);
+                },
+            )
+            .await
+    }
+}
+
+impl AdminClient {
+    /// Exports `collection_ids`, or all collections when empty, to a Cloud
+    /// Storage prefix like `gs://bucket/backups/2023-01-01`.
+    pub fn export_documents(
+        &self,
+        output_uri_prefix: impl ToString,
+        collection_ids: &[impl ToString],
+    ) -> TransferDocumentsOptions<'_> {
+        TransferDocumentsOptions::new(
+            self,
+            TransferDirection::Export,
+            output_uri_prefix.to_string(),
+            collection_ids.iter().map(ToString::to_string).collect(),
+        )
+    }
+
+    /// Imports `collection_ids`, or all collections when empty, of an export at
+    /// `input_uri_prefix`. Existing documents with the same ids are
+    /// overwritten.
+    pub fn import_documents(
+        &self,
+        input_uri_prefix: impl ToString,
+        collection_ids: &[impl ToString],
+    ) -> TransferDocumentsOptions<'_> {
+        TransferDocumentsOptions::new(
+            self,
+            TransferDirection::Import,
+            input_uri_prefix.to_string(),
+            collection_ids.iter().map(ToString::to_string).collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firestore::admin::{OperationProgress, Progress};
+    use crate::firestore::client::BackendAuthorization;
+    use crate::http_stub::HttpStub;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    const OPERATION: &str = "projects/p/databases/(default)/operations/abc";
+
+    fn admin(stub: &HttpStub) -> AdminClient {
+        let auth = Box::new(BackendAuthorization {
+            project_id: "p".to_string(),
+        });
+        AdminClient::new(auth).base_url(stub.base_url.clone())
+    }
+
+    #[test]
+    fn reads_progress_from_operation_metadata() {
+        let operation: Operation = serde_json::from_value(json!({
+            "name": "projects/p/databases/(default)/operations/abc",
+            "metadata": {
+                "@type": "type.googleapis.com/google.firestore.admin.v1.ExportDocumentsMetadata",
+                "operationState": "PROCESSING",
+                "progressDocuments": { "completedWork": "120", "estimatedWork": "1000" },
+                "progressBytes": { "completedWork": 2048 },
+                "outputUriPrefix": "gs://bucket/backup",
+            },
+        }))
+        .unwrap();
+
+        assert_eq!(
+            operation.progress(),
+            Some(OperationProgress {
+                operation_state: "PROCESSING".to_string(),
+                progress_documents: Progress {
+                    completed_work: 120,
+                    estimated_work: 1000,
+                },
+                progress_bytes: Progress {
+                    completed_work: 2048,
+                    estimated_work: 0,
+                },
+            })
+        );
+
+        let req = TransferDocumentsRequest {
+            collection_ids: &["items".to_string()],
+            output_uri_prefix: Some("gs://bucket/backup"),
+            input_uri_prefix: None,
+            namespace_ids: &[],
+        };
+        assert_eq!(
+            serde_json::to_value(req).unwrap(),
+            json!({ "collectionIds": ["items"], "outputUriPrefix": "gs://bucket/backup" })
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_polling_when_the_server_is_unavailable() {
+        let stub = HttpStub::start(vec![
+            (200, json!({ "name": OPERATION })),
+            (
+                503,
+                json!({ "error": { "code": 503, "status": "UNAVAILABLE" } }),
+            ),
+            (200, json!({ "name": OPERATION, "done": true })),
+        ])
+        .await;
+
+        let mut polled = 0;
+        let operation = admin(&stub)
+            .export_documents("gs://bucket/backup", &["items"])
+            .poll_interval(Duration::from_millis(10))
+            .run(|_| polled += 1)
+            .await
+            .unwrap();
+
+        assert!(operation.done);
+        assert_eq!(polled, 2);
+        let requests = stub.requests();
+        assert_eq!(
+            requests[0],
+            (
+                "POST /v1/projects/p/databases/(default):exportDocuments".to_string(),
+                json!({ "collectionIds": ["items"], "outputUriPrefix": "gs://bucket/backup" })
+            )
+        );
+        assert_eq!(requests[1].0, format!("GET /v1/{}", OPERATION));
+        assert_eq!(requests[2].0, format!("GET /v1/{}", OPERATION));
+    }
+
+    #[tokio::test]
+    async fn fails_on_client_errors_while_polling() {
+        let stub = HttpStub::start(vec![
+            (200, json!({ "name": OPERATION })),
+            (
+                403,
+                json!({ "error": { "code": 403, "status": "PERMISSION_DENIED" } }),
+            ),
+        ])
+        .await;
+
+        let err = admin(&stub)
+            .import_documents("gs://bucket/backup", &[] as &[&str])
+            .poll_interval(Duration::from_millis(10))
+            .run(|_| {})
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            FirestoreAdminError::RequestFailure(reqwest::StatusCode::FORBIDDEN, _)
+        ));
+        assert_eq!(
+            stub.requests()[0],
+            (
+                "POST /v1/projects/p/databases/(default):importDocuments".to_string(),
+                json!({ "inputUriPrefix": "gs://bucket/backup" })
+            )
+        );
+    }
+}
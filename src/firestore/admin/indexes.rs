@@ -0,0 +1,566 @@
+use firestore_grpc::tonic::Status;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use super::{AdminClient, Operation};
+use crate::firestore::field_path::IntoFieldPath;
+use crate::FirestoreAdminError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum QueryScope {
+    #[default]
+    Collection,
+    CollectionGroup,
+    #[serde(other)]
+    QueryScopeUnspecified,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum IndexState {
+    Creating,
+    Ready,
+    NeedsRepair,
+    #[serde(other)]
+    StateUnspecified,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum IndexOrder {
+    Ascending,
+    Descending,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ArrayConfig {
+    Contains,
+}
+
+/// A field of an index, either ordered or `array-contains`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexField {
+    pub field_path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<IndexOrder>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub array_config: Option<ArrayConfig>,
+}
+
+impl IndexField {
+    pub fn ascending(field: impl IntoFieldPath) -> Self {
+        Self::ordered(field, IndexOrder::Ascending)
+    }
+
+    pub fn descending(field: impl IntoFieldPath) -> Self {
+        Self::ordered(field, IndexOrder::Descending)
+    }
+
+    pub fn array_contains(field: impl IntoFieldPath) -> Self {
+        Self {
+            field_path: field.into_field_path().to_string(),
+            order: None,
+            array_config: Some(ArrayConfig::Contains),
+        }
+    }
+
+    fn ordered(field: impl IntoFieldPath, order: IndexOrder) -> Self {
+        Self {
+            field_path: field.into_field_path().to_string(),
+            order: Some(order),
+            array_config: None,
+        }
+    }
+}
+
+/// An index as the Admin API returns it. `name` and `state` are set by the
+/// server.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Index {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    #[serde(default)]
+    pub query_scope: QueryScope,
+    #[serde(default)]
+    pub fields: Vec<IndexField>,
+    #[serde(default, skip_serializing)]
+    pub state: Option<IndexState>,
+}
+
+impl Index {
+    /// The collection group from the name
+    /// `projects/{p}/databases/{d}/collectionGroups/{group}/indexes/{id}`.
+    pub fn collection_group(&self) -> Option<&str> {
+        let mut parts = self.name.split('/');
+        parts.find(|part| *part == "collectionGroups")?;
+        parts.next()
+    }
+}
+
+/// A composite index declared in code, see [`AdminClient::reconcile_indexes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexSpec {
+    pub collection_group: String,
+    pub query_scope: QueryScope,
+    pub fields: Vec<IndexField>,
+}
+
+impl IndexSpec {
+    pub fn new<S: ToString>(collection_group: S) -> Self {
+        Self {
+            collection_group: collection_group.to_string(),
+            query_scope: QueryScope::Collection,
+            fields: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn query_scope(mut self, query_scope: QueryScope) -> Self {
+        self.query_scope = query_scope;
+        self
+    }
+
+    #[must_use]
+    pub fn field(mut self, field: IndexField) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    #[must_use]
+    pub fn ascending(self, field: impl IntoFieldPath) -> Self {
+        self.field(IndexField::ascending(field))
+    }
+
+    #[must_use]
+    pub fn descending(self, field: impl IntoFieldPath) -> Self {
+        self.field(IndexField::descending(field))
+    }
+
+    #[must_use]
+    pub fn array_contains(self, field: impl IntoFieldPath) -> Self {
+        self.field(IndexField::array_contains(field))
+    }
+
+    /// Whether `index` is this index. The server appends a `__name__` field to
+    /// indexes, it is ignored.
+    pub fn matches(&self, index: &Index) -> bool {
+        let mut fields = index.fields.as_slice();
+        if let Some((last, rest)) = fields.split_last() {
+            if last.field_path == "__name__" && self.fields.len() == rest.len() {
+                fields = rest;
+            }
+        }
+        index.collection_group() == Some(self.collection_group.as_str())
+            && index.query_scope == self.query_scope
+            && fields == self.fields.as_slice()
+    }
+
+    fn to_index(&self) -> Index {
+        Index {
+            query_scope: self.query_scope,
+            fields: self.fields.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// What [`AdminClient::reconcile_indexes`] has to change.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct IndexPlan {
+    pub create: Vec<IndexSpec>,
+    pub delete: Vec<Index>,
+}
+
+impl IndexPlan {
+    /// Compares the `existing` indexes with the `desired` ones. Undeclared
+    /// indexes are only deleted with `delete_undeclared`.
+    pub fn new(existing: &[Index], desired: &[IndexSpec], delete_undeclared: bool) -> Self {
+        let create = desired
+            .iter()
+            .filter(|spec| !existing.iter().any(|index| spec.matches(index)))
+            .cloned()
+            .collect();
+        let delete = if delete_undeclared {
+            existing
+                .iter()
+                .filter(|index| !desired.iter().any(|spec| spec.matches(index)))
+                .cloned()
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Self { create, delete }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.create.is_empty() && self.delete.is_empty()
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// field overrides
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexConfig {
+    #[serde(default)]
+    pub indexes: Vec<Index>,
+    #[serde(default, skip_serializing)]
+    pub uses_ancestor_config: bool,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub ancestor_field: String,
+    #[serde(default, skip_serializing)]
+    pub reverting: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TtlState {
+    Creating,
+    Active,
+    NeedsRepair,
+    #[serde(other)]
+    StateUnspecified,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TtlConfig {
+    #[serde(default, skip_serializing)]
+    pub state: Option<TtlState>,
+}
+
+/// The configuration of a single field: its single field indexes and TTL
+/// policy.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldConfig {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_config: Option<IndexConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_config: Option<TtlConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListIndexesResponse {
+    #[serde(default)]
+    indexes: Vec<Index>,
+    #[serde(default)]
+    next_page_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListFieldsResponse {
+    #[serde(default)]
+    fields: Vec<FieldConfig>,
+    #[serde(default)]
+    next_page_token: String,
+}
+
+/// Result of [`AdminClient::reconcile_indexes`]. The index builds are still
+/// running, see [`AdminClient::wait_for_operation`].
+#[derive(Debug, Clone, Default)]
+pub struct IndexReconciliation {
+    pub plan: IndexPlan,
+    pub operations: Vec<Operation>,
+}
+
+/// The console link to create the missing index that a query failed with
+/// (`FAILED_PRECONDITION: The query requires an index. You can create it
+/// here: https://console.firebase.google.com/...`).
+pub fn missing_index_url(status: &Status) -> Option<&str> {
+    let message = status.message();
+    let start = message.find("https://console.firebase.google.com")?;
+    message[start..].split_whitespace().next()
+}
+
+impl AdminClient {
+    /// The composite indexes of `collection_group`, or of all collection groups
+    /// when `None`.
+    pub async fn list_indexes(
+        &self,
+        collection_group: Option<&str>,
+    ) -> Result<Vec<Index>, FirestoreAdminError> {
+        let parent = format!(
+            "{}/indexes",
+            self.collection_group_path(collection_group.unwrap_or("-"))
+        );
+        let mut indexes = Vec::new();
+        let mut page_token = String::new();
+        loop {
+            let mut query = Vec::new();
+            if !page_token.is_empty() {
+                query.push(("pageToken", page_token));
+            }
+            let res: ListIndexesResponse = self
+                .request::<_, ()>(Method::GET, &parent, &query, None)
+                .await?;
+            indexes.extend(res.indexes);
+            if res.next_page_token.is_empty() {
+                return Ok(indexes);
+            }
+            page_token = res.next_page_token;
+        }
+    }
+
+    /// Starts building the index.
+    pub async fn create_index(&self, spec: &IndexSpec) -> Result<Operation, FirestoreAdminError> {
+        let parent = format!(
+            "{}/indexes",
+            self.collection_group_path(&spec.collection_group)
+        );
+        self.request(Method::POST, &parent, &[], Some(&spec.to_index()))
+            .await
+    }
+
+    /// `name` is the full name of the index, see [`Index::name`].
+    pub async fn delete_index(&self, name: &str) -> Result<(), FirestoreAdminError> {
+        self.request::<serde_json::Value, ()>(Method::DELETE, name, &[], None)
+            .await?;
+        Ok(())
+    }
+
+    /// Creates the `desired` indexes that don't exist yet and, with
+    /// `delete_undeclared`, deletes the ones that are not declared.
+    pub async fn reconcile_indexes(
+        &self,
+        desired: &[IndexSpec],
+        delete_undeclared: bool,
+    ) -> Result<IndexReconciliation, FirestoreAdminError> {
+        let existing = self.list_indexes(None).await?;
+        let plan = IndexPlan::new(&existing, desired, delete_undeclared);
+
+        let mut operations = Vec::new();
+        for spec in &plan.create {
+            tracing::info!("creating index {:?}", spec);
+            operations.push(self.create_index(spec).await?);
+        }
+        for index in &plan.delete {
+            tracing::info!("deleting index {}", index.name);
+            self.delete_index(&index.name).await?;
+        }
+
+        Ok(IndexReconciliation { plan, operations })
+    }
+
+    fn field_path(&self, collection_group: &str, field: impl IntoFieldPath) -> String {
+        format!(
+            "{}/fields/{}",
+            self.collection_group_path(collection_group),
+            field.into_field_path()
+        )
+    }
+
+    pub async fn get_field(
+        &self,
+        collection_group: &str,
+        field: impl IntoFieldPath,
+    ) -> Result<FieldConfig, FirestoreAdminError> {
+        let name = self.field_path(collection_group, field);
+        self.request::<_, ()>(Method::GET, &name, &[], None).await
+    }
+
+    /// Fields whose configuration differs from the database defaults, i.e.
+    /// index exemptions and TTL policies, of `collection_group` or all
+    /// collection groups.
+    pub async fn list_field_overrides(
+        &self,
+        collection_group: Option<&str>,
+    ) -> Result<Vec<FieldConfig>, FirestoreAdminError> {
+        self.list_fields(collection_group, "indexConfig.usesAncestorConfig:false")
+            .await
+    }
+
+    /// Fields with a TTL policy.
+    pub async fn list_ttl_policies(&self) -> Result<Vec<FieldConfig>, FirestoreAdminError> {
+        self.list_fields(None, "ttlConfig:*").await
+    }
+
+    async fn list_fields(
+        &self,
+        collection_group: Option<&str>,
+        filter: &str,
+    ) -> Result<Vec<FieldConfig>, FirestoreAdminError> {
+        let parent = format!(
+            "{}/fields",
+            self.collection_group_path(collection_group.unwrap_or("-"))
+        );
+        let mut fields = Vec::new();
+        let mut page_token = String::new();
+        loop {
+            let mut query = vec![("filter", filter.to_string())];
+            if !page_token.is_empty() {
+                query.push(("pageToken", page_token));
+            }
+            let res: ListFieldsResponse = self
+                .request::<_, ()>(Method::GET, &parent, &query, None)
+                .await?;
+            fields.extend(res.fields);
+            if res.next_page_token.is_empty() {
+                return Ok(fields);
+            }
+            page_token = res.next_page_token;
+        }
+    }
+
+    async fn update_field(
+        &self,
+        collection_group: &str,
+        field: impl IntoFieldPath,
+        update_mask: &str,
+        config: FieldConfig,
+    ) -> Result<Operation, FirestoreAdminError> {
+        let name = self.field_path(collection_group, field);
+        let query = [("updateMask", update_mask.to_string())];
+        self.request(Method::PATCH, &name, &query, Some(&config))
+            .await
+    }
+
+    /// Replaces the single field indexes of `field` with `indexes`, an empty
+    /// list exempts the field from indexing.
+    pub async fn set_field_indexes(
+        &self,
+        collection_group: &str,
+        field: impl IntoFieldPath,
+        indexes: Vec<Index>,
+    ) -> Result<Operation, FirestoreAdminError> {
+        let config = FieldConfig {
+            index_config: Some(IndexConfig {
+                indexes,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        self.update_field(collection_group, field, "indexConfig", config)
+            .await
+    }
+
+    /// Exempts `field` from single field indexing, e.g. for large values.
+    pub async fn exempt_field(
+        &self,
+        collection_group: &str,
+        field: impl IntoFieldPath,
+    ) -> Result<Operation, FirestoreAdminError> {
+        self.set_field_indexes(collection_group, field, Vec::new())
+            .await
+    }
+
+    /// Removes an index exemption, the field uses the database defaults again.
+    pub async fn clear_field_indexes(
+        &self,
+        collection_group: &str,
+        field: impl IntoFieldPath,
+    ) -> Result<Operation, FirestoreAdminError> {
+        self.update_field(
+            collection_group,
+            field,
+            "indexConfig",
+            FieldConfig::default(),
+        )
+        .await
+    }
+
+    /// Documents are deleted after the timestamp in `field` has passed.
+    pub async fn enable_ttl(
+        &self,
+        collection_group: &str,
+        field: impl IntoFieldPath,
+    ) -> Result<Operation, FirestoreAdminError> {
+        let config = FieldConfig {
+            ttl_config: Some(TtlConfig::default()),
+            ..Default::default()
+        };
+        self.update_field(collection_group, field, "ttlConfig", config)
+            .await
+    }
+
+    pub async fn disable_ttl(
+        &self,
+        collection_group: &str,
+        field: impl IntoFieldPath,
+    ) -> Result<Operation, FirestoreAdminError> {
+        self.update_field(collection_group, field, "ttlConfig", FieldConfig::default())
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    fn index(json: serde_json::Value) -> Index {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn plans_index_changes() {
+        let existing = vec![
+            index(json!({
+                "name": "projects/p/databases/(default)/collectionGroups/items/indexes/a",
+                "queryScope": "COLLECTION",
+                "fields": [
+                    { "fieldPath": "owner", "order": "ASCENDING" },
+                    { "fieldPath": "rank", "order": "DESCENDING" },
+                    { "fieldPath": "__name__", "order": "DESCENDING" },
+                ],
+                "state": "READY",
+            })),
+            index(json!({
+                "name": "projects/p/databases/(default)/collectionGroups/items/indexes/b",
+                "queryScope": "COLLECTION",
+                "fields": [{ "fieldPath": "tags", "arrayConfig": "CONTAINS" }],
+            })),
+        ];
+        let desired = vec![
+            IndexSpec::new("items")
+                .ascending("owner")
+                .descending("rank"),
+            IndexSpec::new("items")
+                .query_scope(QueryScope::CollectionGroup)
+                .ascending("user-name")
+                .ascending("rank"),
+        ];
+
+        let plan = IndexPlan::new(&existing, &desired, true);
+        assert_eq!(plan.create, vec![desired[1].clone()]);
+        assert_eq!(plan.delete, vec![existing[1].clone()]);
+        assert_eq!(plan.create[0].fields[0].field_path, "`user-name`");
+
+        assert!(IndexPlan::new(&existing, &desired[..1], false).is_empty());
+    }
+
+    #[test]
+    fn serializes_requests_like_the_admin_api() {
+        let index = IndexSpec::new("items")
+            .ascending("owner")
+            .array_contains("tags")
+            .to_index();
+        assert_eq!(
+            serde_json::to_value(index).unwrap(),
+            json!({
+                "queryScope": "COLLECTION",
+                "fields": [
+                    { "fieldPath": "owner", "order": "ASCENDING" },
+                    { "fieldPath": "tags", "arrayConfig": "CONTAINS" },
+                ],
+            })
+        );
+
+        let status = Status::failed_precondition(
+            "The query requires an index. You can create it here: https://console.firebase.google.com/v1/r/project/p/firestore/indexes?create_composite=abc",
+        );
+        assert_eq!(
+            missing_index_url(&status),
+            Some("https://console.firebase.google.com/v1/r/project/p/firestore/indexes?create_composite=abc")
+        );
+    }
+}
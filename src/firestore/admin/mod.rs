@@ -0,0 +1,159 @@
+//! A client for the Firestore Admin API: composite indexes, field overrides
+//! (single field index exemptions and TTL policies) and the long-running
+//! operations they start. It uses the REST API, `firestore_grpc` only contains
+//! the data API.
+
+use firebase_client_auth::{scopes, GoogleAuth, GoogleServiceAccount, ServiceAccountAuthorization};
+use reqwest::Method;
+use serde::{de::DeserializeOwned, Serialize};
+use std::time::Duration;
+use url::Url;
+
+use super::FirebaseClient;
+use crate::timeout::{run_with_timeout, DEFAULT_TIMEOUT};
+use crate::FirestoreAdminError;
+
+mod export;
+mod indexes;
+mod operation;
+
+pub use export::*;
+pub use indexes::*;
+pub use operation::*;
+
+const URL: &str = "https://firestore.googleapis.com";
+
+#[derive(Debug)]
+pub struct AdminClient {
+    auth: GoogleAuth,
+    project_id: String,
+    base_url: Url,
+    http: reqwest::Client,
+    timeout: Option<Duration>,
+}
+
+impl Clone for AdminClient {
+    fn clone(&self) -> Self {
+        Self {
+            auth: self.auth.box_clone(),
+            project_id: self.project_id.clone(),
+            base_url: self.base_url.clone(),
+            http: self.http.clone(),
+            timeout: self.timeout,
+        }
+    }
+}
+
+impl AdminClient {
+    pub fn for_account(account: GoogleServiceAccount) -> Self {
+        AdminClient::new(Box::new(
+            ServiceAccountAuthorization::with_account_and_scope(
+                account,
+                &[scopes::AUTH_DATASTORE, scopes::AUTH_CLOUD_PLATFORM],
+            ),
+        ))
+    }
+
+    pub fn new(auth: GoogleAuth) -> Self {
+        Self {
+            project_id: auth.project_id().to_string(),
+            auth,
+            base_url: Url::parse(URL).expect("valid url"),
+            http: reqwest::Client::new(),
+            timeout: Some(DEFAULT_TIMEOUT),
+        }
+    }
+
+    /// Sends requests to `base_url` instead of `https://firestore.googleapis.com`.
+    #[must_use]
+    pub fn base_url(mut self, base_url: Url) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    /// The timeout of each request, [`DEFAULT_TIMEOUT`] by default. `None`
+    /// waits forever. Waiting for operations has its own timeout.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn project_id(&self) -> &str {
+        &self.project_id
+    }
+
+    pub fn database_path(&self) -> String {
+        format!("projects/{}/databases/(default)", self.project_id)
+    }
+
+    pub(crate) fn collection_group_path(&self, collection_group: &str) -> String {
+        format!(
+            "{}/collectionGroups/{}",
+            self.database_path(),
+            collection_group
+        )
+    }
+
+    /// Sends a request to `resource`, a resource name optionally followed by a
+    /// custom method like `:exportDocuments`.
+    pub(crate) async fn request<T, B>(
+        &self,
+        method: Method,
+        resource: &str,
+        query: &[(&str, String)],
+        body: Option<&B>,
+    ) -> Result<T, FirestoreAdminError>
+    where
+        T: DeserializeOwned,
+        B: Serialize + ?Sized,
+    {
+        run_with_timeout(self.timeout, self.send(method, resource, query, body)).await
+    }
+
+    async fn send<T, B>(
+        &self,
+        method: Method,
+        resource: &str,
+        query: &[(&str, String)],
+        body: Option<&B>,
+    ) -> Result<T, FirestoreAdminError>
+    where
+        T: DeserializeOwned,
+        B: Serialize + ?Sized,
+    {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| url::ParseError::RelativeUrlWithCannotBeABaseBase)?
+            .push("v1")
+            .extend(resource.split('/'));
+
+        tracing::debug!("firestore admin {} {}", method, url);
+
+        let mut req = self.http.request(method, url).query(query);
+        if let Some(token) = self.auth.get_token().await? {
+            req = req.bearer_auth(token);
+        }
+        if let Some(body) = body {
+            req = req.json(body);
+        }
+
+        let res = req.send().await?;
+        let status = res.status();
+        let text = res.text().await?;
+        if !status.is_success() {
+            tracing::error!("firestore admin request failed: {} {}", status, text);
+            return Err(FirestoreAdminError::RequestFailure(status, text));
+        }
+        let text = if text.trim().is_empty() { "{}" } else { &text };
+        Ok(serde_json::from_str(text)?)
+    }
+}
+
+impl FirebaseClient {
+    /// An admin client for the project of this client that uses the same
+    /// authorization and timeout.
+    pub fn admin(&self) -> AdminClient {
+        AdminClient::new(self.auth.box_clone()).with_timeout(self.timeout)
+    }
+}
@@ -0,0 +1,150 @@
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+use super::AdminClient;
+use crate::FirestoreAdminError;
+
+/// A long-running operation, e.g. an index build.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Operation {
+    pub name: String,
+    #[serde(default)]
+    pub done: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<OperationError>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+}
+
+impl Operation {
+    /// The progress of export and import operations from their metadata.
+    pub fn progress(&self) -> Option<OperationProgress> {
+        serde_json::from_value(self.metadata.clone()?).ok()
+    }
+}
+
+/// Work done by an operation, e.g. documents or bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Progress {
+    #[serde(default, deserialize_with = "int64")]
+    pub completed_work: i64,
+    #[serde(default, deserialize_with = "int64")]
+    pub estimated_work: i64,
+}
+
+/// The metadata of export and import operations.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationProgress {
+    /// E.g. `PROCESSING` or `SUCCESSFUL`.
+    #[serde(default)]
+    pub operation_state: String,
+    #[serde(default)]
+    pub progress_documents: Progress,
+    #[serde(default)]
+    pub progress_bytes: Progress,
+}
+
+/// int64 values are strings in JSON.
+fn int64<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::String(s) => s.parse().map_err(serde::de::Error::custom),
+        Value::Number(n) => n
+            .as_i64()
+            .ok_or_else(|| serde::de::Error::custom("not an int64")),
+        _ => Ok(0),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct OperationError {
+    #[serde(default)]
+    pub code: i32,
+    #[serde(default)]
+    pub message: String,
+}
+
+impl AdminClient {
+    pub async fn get_operation(&self, name: &str) -> Result<Operation, FirestoreAdminError> {
+        self.request::<_, ()>(Method::GET, name, &[], None).await
+    }
+
+    /// Polls `operation` every `interval` until it is done. Fails with
+    /// [`FirestoreAdminError::OperationFailed`] if the operation failed and with
+    /// [`FirestoreAdminError::OperationTimeout`] if it is not done after
+    /// `timeout`.
+    pub async fn wait_for_operation(
+        &self,
+        operation: Operation,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Result<Operation, FirestoreAdminError> {
+        self.wait_for_operation_with_progress(operation, interval, timeout, |_| {})
+            .await
+    }
+
+    /// Like [`Self::wait_for_operation`], `on_progress` is called with every
+    /// polled state of the operation.
+    pub async fn wait_for_operation_with_progress<F>(
+        &self,
+        operation: Operation,
+        interval: Duration,
+        timeout: Duration,
+        mut on_progress: F,
+    ) -> Result<Operation, FirestoreAdminError>
+    where
+        F: FnMut(&Operation),
+    {
+        let started = Instant::now();
+        let mut operation = operation;
+        on_progress(&operation);
+        loop {
+            if operation.done {
+                return match operation.error {
+                    Some(error) => Err(FirestoreAdminError::OperationFailed {
+                        name: operation.name,
+                        code: error.code,
+                        message: error.message,
+                    }),
+                    None => Ok(operation),
+                };
+            }
+            if started.elapsed() + interval > timeout {
+                return Err(FirestoreAdminError::OperationTimeout(operation.name));
+            }
+
+            tokio::time::sleep(interval).await;
+            tracing::debug!("polling operation {}", operation.name);
+            match self.get_operation(&operation.name).await {
+                Ok(polled) => {
+                    operation = polled;
+                    on_progress(&operation);
+                }
+                // the operation keeps running, poll again until the timeout
+                Err(err) if is_transient(&err) => {
+                    tracing::warn!("polling operation {} failed: {}", operation.name, err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Unavailable and other server errors as well as timeouts and connection
+/// errors.
+fn is_transient(err: &FirestoreAdminError) -> bool {
+    match err {
+        FirestoreAdminError::DeadlineExceeded(_) => true,
+        FirestoreAdminError::RequestFailure(status, _) => {
+            status.is_server_error() || *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        }
+        FirestoreAdminError::RequestError(err) => err.is_timeout() || err.is_connect(),
+        _ => false,
+    }
+}
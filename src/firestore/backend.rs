@@ -0,0 +1,603 @@
+use async_trait::async_trait;
+use firebase_client_auth::GoogleAuth;
+use firestore_grpc::tonic::codegen::InterceptedService;
+use firestore_grpc::tonic::{
+    metadata::{Ascii, MetadataMap, MetadataValue},
+    service::Interceptor,
+    transport::{Channel, ClientTlsConfig, Endpoint},
+    Code, Request, Response, Status,
+};
+use firestore_grpc::v1::{self as firestore, firestore_client::FirestoreClient};
+use futures::{Stream, StreamExt};
+use reqwest::header::HeaderMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use crate::instrumentation::{instrument, FIRESTORE};
+use crate::interceptor::{
+    InterceptedRequest, InterceptedResponse, Interceptors, RequestInterceptor,
+};
+use crate::timeout::request_timeout;
+use crate::FirestoreError;
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+const URL: &str = "https://firestore.googleapis.com";
+const DOMAIN: &str = "firestore.googleapis.com";
+
+fn default_endpoint() -> Endpoint {
+    Channel::from_static(URL)
+        .tls_config(ClientTlsConfig::new().domain_name(DOMAIN))
+        .unwrap()
+}
+
+/// Adds the authorization, the headers of the request interceptors and the
+/// timeout as `grpc-timeout` to every gRPC request.
+#[derive(Debug, Clone)]
+pub(crate) struct MetadataInterceptor {
+    authorization: Option<MetadataValue<Ascii>>,
+    headers: HeaderMap,
+    timeout: Option<Duration>,
+}
+
+impl Interceptor for MetadataInterceptor {
+    fn call(&mut self, mut req: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(timeout) = self.timeout {
+            req.set_timeout(timeout);
+        }
+        if !self.headers.is_empty() {
+            let mut headers = std::mem::take(req.metadata_mut()).into_headers();
+            headers.extend(self.headers.clone());
+            *req.metadata_mut() = MetadataMap::from_headers(headers);
+        }
+        if let Some(auth) = &self.authorization {
+            req.metadata_mut().insert("authorization", auth.clone());
+        }
+        Ok(req)
+    }
+}
+
+pub(crate) type GrpcClient = FirestoreClient<InterceptedService<Channel, MetadataInterceptor>>;
+
+pub(crate) async fn get_client(
+    endpoint: &Endpoint,
+    token: Option<String>,
+    headers: HeaderMap,
+) -> Result<GrpcClient, FirestoreError> {
+    let authorization = if let Some(token) = token {
+        let bearer_token = format!("Bearer {}", token);
+        Some(MetadataValue::from_str(&bearer_token)?)
+    } else {
+        None
+    };
+
+    let interceptor = MetadataInterceptor {
+        authorization,
+        headers,
+        timeout: request_timeout(),
+    };
+    Ok(FirestoreClient::with_interceptor(
+        endpoint.connect().await?,
+        interceptor,
+    ))
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// Selects the backend of [`super::FirebaseClient::with_transport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transport {
+    #[default]
+    Grpc,
+    /// The REST API, listen still uses gRPC. See [`super::rest::RestBackend`].
+    #[cfg(feature = "rest")]
+    Rest,
+}
+
+pub type ListenRequestStream = Pin<Box<dyn Stream<Item = firestore::ListenRequest> + Send>>;
+pub type ListenResponseStream =
+    Pin<Box<dyn Stream<Item = Result<firestore::ListenResponse, Status>> + Send>>;
+
+/// The operations [`super::FirebaseClient`] runs against Firestore. Requests use
+/// full resource names, e.g.
+/// `projects/{project_id}/databases/(default)/documents/{path}`. Backends
+/// should send the [`request_timeout`] along as deadline.
+#[async_trait]
+pub trait FirestoreBackend: Send + Sync + std::fmt::Debug {
+    /// The project of a backend that is not tied to an authorization, see
+    /// [`super::FirebaseClient::with_backend`].
+    fn project_id(&self) -> Option<String> {
+        None
+    }
+
+    /// The interceptors of backends that support them, see
+    /// [`super::FirebaseClient::add_interceptor`].
+    fn interceptors(&self) -> Option<&Interceptors> {
+        None
+    }
+
+    async fn get_document(
+        &self,
+        req: firestore::GetDocumentRequest,
+    ) -> Result<firestore::Document, FirestoreError>;
+
+    async fn list_documents(
+        &self,
+        req: firestore::ListDocumentsRequest,
+    ) -> Result<firestore::ListDocumentsResponse, FirestoreError>;
+
+    async fn list_collection_ids(
+        &self,
+        req: firestore::ListCollectionIdsRequest,
+    ) -> Result<firestore::ListCollectionIdsResponse, FirestoreError>;
+
+    async fn batch_get_documents(
+        &self,
+        req: firestore::BatchGetDocumentsRequest,
+    ) -> Result<Vec<Result<firestore::BatchGetDocumentsResponse, Status>>, FirestoreError>;
+
+    async fn run_query(
+        &self,
+        req: firestore::RunQueryRequest,
+    ) -> Result<Vec<Result<firestore::RunQueryResponse, Status>>, FirestoreError>;
+
+    async fn update_document(
+        &self,
+        req: firestore::UpdateDocumentRequest,
+    ) -> Result<firestore::Document, FirestoreError>;
+
+    async fn delete_document(
+        &self,
+        req: firestore::DeleteDocumentRequest,
+    ) -> Result<(), FirestoreError>;
+
+    async fn batch_write(
+        &self,
+        req: firestore::BatchWriteRequest,
+    ) -> Result<firestore::BatchWriteResponse, FirestoreError>;
+
+    async fn commit(
+        &self,
+        req: firestore::CommitRequest,
+    ) -> Result<firestore::CommitResponse, FirestoreError>;
+
+    /// Opens a listen stream on `database`
+    /// (`projects/{project_id}/databases/(default)`) that is fed by `requests`.
+    async fn listen(
+        &self,
+        database: String,
+        requests: ListenRequestStream,
+    ) -> Result<ListenResponseStream, FirestoreError>;
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// Talks to the Firestore gRPC API, the default backend of
+/// [`super::FirebaseClient`].
+#[derive(Debug)]
+pub struct GrpcBackend {
+    auth: GoogleAuth,
+    endpoint: Endpoint,
+    pub(crate) interceptors: Interceptors,
+}
+
+impl GrpcBackend {
+    pub fn new(auth: GoogleAuth) -> Self {
+        Self::with_endpoint(auth, default_endpoint())
+    }
+
+    /// Connects to `endpoint` instead of `firestore.googleapis.com`, e.g. an
+    /// emulator or a local test server.
+    pub fn with_endpoint(auth: GoogleAuth, endpoint: Endpoint) -> Self {
+        Self {
+            auth,
+            endpoint,
+            interceptors: Interceptors::default(),
+        }
+    }
+
+    /// Adds `interceptor` to the requests of this backend and its clones.
+    pub fn add_interceptor<I: RequestInterceptor + 'static>(&self, interceptor: I) {
+        self.interceptors.push(interceptor);
+    }
+
+    /// Runs the interceptors and returns a client that sends their headers.
+    async fn client(&self, request: &mut InterceptedRequest) -> Result<GrpcClient, FirestoreError> {
+        self.interceptors
+            .on_request(request)
+            .await
+            .map_err(FirestoreError::InterceptorError)?;
+        let token = self.auth.get_token().await?;
+        get_client(&self.endpoint, token, request.headers.clone()).await
+    }
+
+    /// Hands the response of `request` to the interceptors.
+    fn response<T>(
+        &self,
+        request: &InterceptedRequest,
+        res: Result<Response<T>, Status>,
+    ) -> Result<T, FirestoreError> {
+        self.interceptors.on_response(request, || match &res {
+            Ok(res) => InterceptedResponse {
+                status: format!("{:?}", Code::Ok),
+                headers: res.metadata().clone().into_headers(),
+            },
+            Err(status) => InterceptedResponse {
+                status: format!("{:?}", status.code()),
+                headers: status.metadata().clone().into_headers(),
+            },
+        });
+        Ok(res?.into_inner())
+    }
+
+    fn request(method: &str, resource: &str) -> InterceptedRequest {
+        InterceptedRequest::new(
+            FIRESTORE,
+            format!("google.firestore.v1.Firestore/{method}"),
+            resource.to_string(),
+        )
+    }
+
+    async fn call<T, F, Fut>(
+        &self,
+        method: &str,
+        resource: &str,
+        call: F,
+    ) -> Result<T, FirestoreError>
+    where
+        F: FnOnce(GrpcClient) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Status>>,
+    {
+        let mut request = Self::request(method, resource);
+        let client = self.client(&mut request).await?;
+        let res = call(client).await;
+        self.response(&request, res)
+    }
+}
+
+#[async_trait]
+impl FirestoreBackend for GrpcBackend {
+    fn project_id(&self) -> Option<String> {
+        Some(self.auth.project_id().to_string())
+    }
+
+    fn interceptors(&self) -> Option<&Interceptors> {
+        Some(&self.interceptors)
+    }
+
+    async fn get_document(
+        &self,
+        req: firestore::GetDocumentRequest,
+    ) -> Result<firestore::Document, FirestoreError> {
+        let resource = req.name.clone();
+        self.call("GetDocument", &resource, |mut client| async move {
+            client.get_document(req).await
+        })
+        .await
+    }
+
+    async fn list_documents(
+        &self,
+        req: firestore::ListDocumentsRequest,
+    ) -> Result<firestore::ListDocumentsResponse, FirestoreError> {
+        let resource = req.parent.clone();
+        self.call("ListDocuments", &resource, |mut client| async move {
+            client.list_documents(req).await
+        })
+        .await
+    }
+
+    async fn list_collection_ids(
+        &self,
+        req: firestore::ListCollectionIdsRequest,
+    ) -> Result<firestore::ListCollectionIdsResponse, FirestoreError> {
+        let resource = req.parent.clone();
+        self.call("ListCollectionIds", &resource, |mut client| async move {
+            client.list_collection_ids(req).await
+        })
+        .await
+    }
+
+    async fn batch_get_documents(
+        &self,
+        req: firestore::BatchGetDocumentsRequest,
+    ) -> Result<Vec<Result<firestore::BatchGetDocumentsResponse, Status>>, FirestoreError> {
+        let resource = req.database.clone();
+        let res = self
+            .call("BatchGetDocuments", &resource, |mut client| async move {
+                client.batch_get_documents(req).await
+            })
+            .await?;
+        Ok(res.collect().await)
+    }
+
+    async fn run_query(
+        &self,
+        req: firestore::RunQueryRequest,
+    ) -> Result<Vec<Result<firestore::RunQueryResponse, Status>>, FirestoreError> {
+        let resource = req.parent.clone();
+        let res = self
+            .call("RunQuery", &resource, |mut client| async move {
+                client.run_query(req).await
+            })
+            .await?;
+        Ok(res.collect().await)
+    }
+
+    async fn update_document(
+        &self,
+        req: firestore::UpdateDocumentRequest,
+    ) -> Result<firestore::Document, FirestoreError> {
+        let resource = req
+            .document
+            .as_ref()
+            .map(|doc| doc.name.clone())
+            .unwrap_or_default();
+        self.call("UpdateDocument", &resource, |mut client| async move {
+            client.update_document(req).await
+        })
+        .await
+    }
+
+    async fn delete_document(
+        &self,
+        req: firestore::DeleteDocumentRequest,
+    ) -> Result<(), FirestoreError> {
+        let resource = req.name.clone();
+        self.call("DeleteDocument", &resource, |mut client| async move {
+            client.delete_document(req).await
+        })
+        .await
+    }
+
+    async fn batch_write(
+        &self,
+        req: firestore::BatchWriteRequest,
+    ) -> Result<firestore::BatchWriteResponse, FirestoreError> {
+        let resource = req.database.clone();
+        self.call("BatchWrite", &resource, |mut client| async move {
+            client.batch_write(req).await
+        })
+        .await
+    }
+
+    async fn commit(
+        &self,
+        req: firestore::CommitRequest,
+    ) -> Result<firestore::CommitResponse, FirestoreError> {
+        let resource = req.database.clone();
+        self.call("Commit", &resource, |mut client| async move {
+            client.commit(req).await
+        })
+        .await
+    }
+
+    async fn listen(
+        &self,
+        database: String,
+        requests: ListenRequestStream,
+    ) -> Result<ListenResponseStream, FirestoreError> {
+        let mut request = Self::request("Listen", &database);
+        let mut client = self.client(&mut request).await?;
+        let mut req = Request::new(requests);
+        req.metadata_mut().insert(
+            "google-cloud-resource-prefix",
+            MetadataValue::from_str(&database)?,
+        );
+        // boxed to help the compiler prove that the call is Send
+        let call: Pin<Box<dyn Future<Output = _> + Send>> = Box::pin(client.listen(req));
+        let res = call.await;
+        Ok(Box::pin(self.response(&request, res)?))
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// Runs every request of the wrapped backend in a span and records metrics,
+/// see [`crate::instrumentation`].
+#[derive(Debug)]
+pub(crate) struct InstrumentedBackend<B> {
+    inner: B,
+}
+
+impl<B: FirestoreBackend> InstrumentedBackend<B> {
+    pub(crate) fn new(inner: B) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl<B: FirestoreBackend> FirestoreBackend for InstrumentedBackend<B> {
+    fn project_id(&self) -> Option<String> {
+        self.inner.project_id()
+    }
+
+    fn interceptors(&self) -> Option<&Interceptors> {
+        self.inner.interceptors()
+    }
+
+    async fn get_document(
+        &self,
+        req: firestore::GetDocumentRequest,
+    ) -> Result<firestore::Document, FirestoreError> {
+        let path = req.name.clone();
+        instrument(
+            FIRESTORE,
+            "get_document",
+            &path,
+            |_| 1,
+            self.inner.get_document(req),
+        )
+        .await
+    }
+
+    async fn list_documents(
+        &self,
+        req: firestore::ListDocumentsRequest,
+    ) -> Result<firestore::ListDocumentsResponse, FirestoreError> {
+        let path = format!("{}/{}", req.parent, req.collection_id);
+        instrument(
+            FIRESTORE,
+            "list_documents",
+            &path,
+            |res: &firestore::ListDocumentsResponse| res.documents.len(),
+            self.inner.list_documents(req),
+        )
+        .await
+    }
+
+    async fn list_collection_ids(
+        &self,
+        req: firestore::ListCollectionIdsRequest,
+    ) -> Result<firestore::ListCollectionIdsResponse, FirestoreError> {
+        let path = req.parent.clone();
+        instrument(
+            FIRESTORE,
+            "list_collection_ids",
+            &path,
+            |res: &firestore::ListCollectionIdsResponse| res.collection_ids.len(),
+            self.inner.list_collection_ids(req),
+        )
+        .await
+    }
+
+    async fn batch_get_documents(
+        &self,
+        req: firestore::BatchGetDocumentsRequest,
+    ) -> Result<Vec<Result<firestore::BatchGetDocumentsResponse, Status>>, FirestoreError> {
+        let path = req.database.clone();
+        instrument(
+            FIRESTORE,
+            "batch_get_documents",
+            &path,
+            |res: &Vec<Result<firestore::BatchGetDocumentsResponse, Status>>| {
+                res.iter()
+                    .filter(|res| {
+                        matches!(
+                            res,
+                            Ok(firestore::BatchGetDocumentsResponse {
+                                result: Some(
+                                    firestore::batch_get_documents_response::Result::Found(_)
+                                ),
+                                ..
+                            })
+                        )
+                    })
+                    .count()
+            },
+            self.inner.batch_get_documents(req),
+        )
+        .await
+    }
+
+    async fn run_query(
+        &self,
+        req: firestore::RunQueryRequest,
+    ) -> Result<Vec<Result<firestore::RunQueryResponse, Status>>, FirestoreError> {
+        let mut path = req.parent.clone();
+        if let Some(firestore::run_query_request::QueryType::StructuredQuery(query)) =
+            &req.query_type
+        {
+            for from in &query.from {
+                path = format!("{}/{}", path, from.collection_id);
+            }
+        }
+        instrument(
+            FIRESTORE,
+            "run_query",
+            &path,
+            |res: &Vec<Result<firestore::RunQueryResponse, Status>>| {
+                res.iter()
+                    .filter(|res| matches!(res, Ok(res) if res.document.is_some()))
+                    .count()
+            },
+            self.inner.run_query(req),
+        )
+        .await
+    }
+
+    async fn update_document(
+        &self,
+        req: firestore::UpdateDocumentRequest,
+    ) -> Result<firestore::Document, FirestoreError> {
+        let path = req
+            .document
+            .as_ref()
+            .map(|doc| doc.name.clone())
+            .unwrap_or_default();
+        instrument(
+            FIRESTORE,
+            "update_document",
+            &path,
+            |_| 1,
+            self.inner.update_document(req),
+        )
+        .await
+    }
+
+    async fn delete_document(
+        &self,
+        req: firestore::DeleteDocumentRequest,
+    ) -> Result<(), FirestoreError> {
+        let path = req.name.clone();
+        instrument(
+            FIRESTORE,
+            "delete_document",
+            &path,
+            |_| 1,
+            self.inner.delete_document(req),
+        )
+        .await
+    }
+
+    async fn batch_write(
+        &self,
+        req: firestore::BatchWriteRequest,
+    ) -> Result<firestore::BatchWriteResponse, FirestoreError> {
+        let path = req.database.clone();
+        let writes = req.writes.len();
+        instrument(
+            FIRESTORE,
+            "batch_write",
+            &path,
+            |_| writes,
+            self.inner.batch_write(req),
+        )
+        .await
+    }
+
+    async fn commit(
+        &self,
+        req: firestore::CommitRequest,
+    ) -> Result<firestore::CommitResponse, FirestoreError> {
+        let path = req.database.clone();
+        let writes = req.writes.len();
+        instrument(
+            FIRESTORE,
+            "commit",
+            &path,
+            |_| writes,
+            self.inner.commit(req),
+        )
+        .await
+    }
+
+    /// Only opening the stream is a request, see
+    /// [`crate::instrumentation::listen_reconnect`] for the stream itself.
+    async fn listen(
+        &self,
+        database: String,
+        requests: ListenRequestStream,
+    ) -> Result<ListenResponseStream, FirestoreError> {
+        let path = database.clone();
+        instrument(
+            FIRESTORE,
+            "listen",
+            &path,
+            |_| 0,
+            self.inner.listen(database, requests),
+        )
+        .await
+    }
+}
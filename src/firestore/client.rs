@@ -1,55 +1,23 @@
 use firebase_client_auth::{scopes, GoogleAuth, GoogleServiceAccount, ServiceAccountAuthorization};
 use firestore_grpc::google::firestore::v1::*;
-use firestore_grpc::tonic::codegen::InterceptedService;
 use firestore_grpc::tonic::Status;
-use firestore_grpc::tonic::{
-    metadata::MetadataValue,
-    transport::{Channel, ClientTlsConfig},
-    Request,
-};
-use firestore_grpc::v1::firestore_client::FirestoreClient;
-use futures::StreamExt;
 use itertools::Itertools;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::Instrument;
+
+use super::backend::{FirestoreBackend, GrpcBackend, InstrumentedBackend, Transport};
 
 use super::conversion::{IntoFirestoreDocument, IntoFirestoreDocumentValue};
+use super::field_path::IntoFieldPath;
 use super::structured_query::{self, StructuredQueryBuilder};
 use super::FromFirestoreDocument;
 
+use crate::app_check::{AppCheckInterceptor, AppCheckProvider};
+use crate::interceptor::RequestInterceptor;
+use crate::timeout::{run_with_timeout, DEFAULT_TIMEOUT};
 use crate::FirestoreError;
 
-// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
-
-const URL: &str = "https://firestore.googleapis.com";
-const DOMAIN: &str = "firestore.googleapis.com";
-
-pub(crate) async fn get_client(
-    token: Option<String>,
-) -> Result<
-    FirestoreClient<
-        InterceptedService<Channel, impl FnMut(Request<()>) -> Result<Request<()>, Status>>,
-    >,
-    FirestoreError,
-> {
-    let auth_header = if let Some(token) = token {
-        let bearer_token = format!("Bearer {}", token);
-        Some(MetadataValue::from_str(&bearer_token)?)
-    } else {
-        None
-    };
-
-    let endpoint = Channel::from_static(URL)
-        .tls_config(ClientTlsConfig::new().domain_name(DOMAIN))
-        .unwrap();
-    let service =
-        FirestoreClient::with_interceptor(endpoint.connect().await?, move |mut req: Request<()>| {
-            if let Some(auth) = &auth_header {
-                req.metadata_mut().insert("authorization", auth.clone());
-            }
-            Ok(req)
-        });
-    Ok(service)
-}
-
 // -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
 // list documents
 
@@ -61,6 +29,7 @@ pub struct ListDocumentsOptions<'a> {
     pub page_size: Option<i32>,
     pub order_by: Option<String>,
     pub page_token: Option<String>,
+    pub timeout: Option<Duration>,
 }
 
 impl<'a> ListDocumentsOptions<'a> {
@@ -72,19 +41,51 @@ impl<'a> ListDocumentsOptions<'a> {
             page_size: None,
             order_by: None,
             page_token: None,
+            timeout: None,
         }
     }
 
+    /// Overrides the timeout of the client for this request.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
     pub fn page_size(mut self, page_size: i32) -> Self {
         self.page_size = Some(page_size);
         self
     }
 
-    pub fn order_by<S: ToString>(mut self, order_by: S) -> Self {
+    /// Orders by `field`, call it again to order by further fields. `field` is
+    /// a single field path, a string like `"rank desc"` or `"a, b"` is one
+    /// (quoted) field name, use [`Self::order_by_raw`] for such clauses.
+    pub fn order_by<F: IntoFieldPath>(self, field: F) -> Self {
+        let order = field.into_field_path().to_string();
+        self.push_order_by(order)
+    }
+
+    /// Like [`Self::order_by`] but in descending order.
+    pub fn order_by_descending<F: IntoFieldPath>(self, field: F) -> Self {
+        let order = format!("{} desc", field.into_field_path());
+        self.push_order_by(order)
+    }
+
+    /// Sets the whole `order_by` clause as is, e.g. `"rank desc, name"`,
+    /// replacing the fields ordered by so far.
+    pub fn order_by_raw<S: ToString>(mut self, order_by: S) -> Self {
         self.order_by = Some(order_by.to_string());
         self
     }
 
+    fn push_order_by(mut self, order: String) -> Self {
+        self.order_by = Some(match self.order_by.take() {
+            Some(order_by) => format!("{}, {}", order_by, order),
+            None => order,
+        });
+        self
+    }
+
     pub fn page_token<S: ToString>(mut self, page_token: S) -> Self {
         self.page_token = Some(page_token.to_string());
         self
@@ -101,7 +102,10 @@ impl<'a> ListDocumentsOptions<'a> {
 
         let mut page = 0;
         loop {
-            let res = self.fetch_page().await?;
+            let res = self
+                .fetch_page()
+                .instrument(tracing::info_span!("firebase.page", page))
+                .await?;
 
             tracing::debug!("fetching page {}, {} documents", page, res.documents.len());
             page += 1;
@@ -136,8 +140,8 @@ impl<'a> ListDocumentsOptions<'a> {
             page_size,
             order_by,
             page_token,
+            timeout,
         } = self;
-        let token = client.get_token().await?;
         let project_id = &client.project_id;
         let parent = match parent {
             Some(parent) if !parent.is_empty() => {
@@ -166,9 +170,13 @@ impl<'a> ListDocumentsOptions<'a> {
             req.page_token = page_token.clone();
         }
 
-        let res = get_client(token).await?.list_documents(req).await?;
+        let res = run_with_timeout(
+            timeout.or(client.timeout),
+            client.backend.list_documents(req),
+        )
+        .await?;
 
-        Ok(res.into_inner())
+        Ok(res)
     }
 }
 
@@ -181,6 +189,7 @@ pub struct ListCollectionsOptions<'a> {
     pub parent: Option<String>,
     pub page_size: Option<i32>,
     pub page_token: Option<String>,
+    pub timeout: Option<Duration>,
 }
 
 impl<'a> ListCollectionsOptions<'a> {
@@ -190,9 +199,17 @@ impl<'a> ListCollectionsOptions<'a> {
             parent: None,
             page_size: None,
             page_token: None,
+            timeout: None,
         }
     }
 
+    /// Overrides the timeout of the client for this request.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
     #[must_use]
     pub fn page_size(mut self, page_size: i32) -> Self {
         self.page_size = Some(page_size);
@@ -217,7 +234,10 @@ impl<'a> ListCollectionsOptions<'a> {
 
         let mut page = 0;
         loop {
-            let res = self.fetch_page().await?;
+            let res = self
+                .fetch_page()
+                .instrument(tracing::info_span!("firebase.page", page))
+                .await?;
 
             tracing::debug!(
                 "fetching page {}, {} documents",
@@ -252,8 +272,8 @@ impl<'a> ListCollectionsOptions<'a> {
             parent,
             page_size,
             page_token,
+            timeout,
         } = self;
-        let token = client.get_token().await?;
         let project_id = &client.project_id;
         let parent = match parent {
             Some(parent) if !parent.is_empty() => {
@@ -278,9 +298,11 @@ impl<'a> ListCollectionsOptions<'a> {
             req.page_token = page_token.clone();
         }
 
-        let res = get_client(token).await?.list_collection_ids(req).await?;
-
-        Ok(res.into_inner())
+        run_with_timeout(
+            timeout.or(client.timeout),
+            client.backend.list_collection_ids(req),
+        )
+        .await
     }
 }
 
@@ -291,16 +313,31 @@ impl<'a> ListCollectionsOptions<'a> {
 pub struct GetDocumentOptions<'a> {
     pub client: &'a FirebaseClient,
     pub name: String,
+    pub timeout: Option<Duration>,
 }
 
 impl<'a> GetDocumentOptions<'a> {
     fn new(client: &'a FirebaseClient, name: String) -> Self {
-        Self { client, name }
+        Self {
+            client,
+            name,
+            timeout: None,
+        }
+    }
+
+    /// Overrides the timeout of the client for this request.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
     }
 
     pub async fn fetch(self) -> Result<Document, FirestoreError> {
-        let Self { client, name } = self;
-        let token = client.get_token().await?;
+        let Self {
+            client,
+            name,
+            timeout,
+        } = self;
         let project_id = &client.project_id;
         let name = format!(
             "projects/{}/databases/(default)/documents/{}",
@@ -314,8 +351,7 @@ impl<'a> GetDocumentOptions<'a> {
             ..Default::default()
         };
 
-        let res = get_client(token).await?.get_document(req).await?;
-        Ok(res.into_inner())
+        run_with_timeout(timeout.or(client.timeout), client.backend.get_document(req)).await
     }
 }
 
@@ -326,16 +362,31 @@ impl<'a> GetDocumentOptions<'a> {
 pub struct DeleteDocumentOptions<'a> {
     pub client: &'a FirebaseClient,
     pub name: String,
+    pub timeout: Option<Duration>,
 }
 
 impl<'a> DeleteDocumentOptions<'a> {
     fn new(client: &'a FirebaseClient, name: String) -> Self {
-        Self { client, name }
+        Self {
+            client,
+            name,
+            timeout: None,
+        }
+    }
+
+    /// Overrides the timeout of the client for this request.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
     }
 
     pub async fn fetch(self) -> Result<(), FirestoreError> {
-        let Self { client, name } = self;
-        let token = client.get_token().await?;
+        let Self {
+            client,
+            name,
+            timeout,
+        } = self;
         let project_id = &client.project_id;
         let name = format!(
             "projects/{}/databases/(default)/documents/{}",
@@ -349,8 +400,11 @@ impl<'a> DeleteDocumentOptions<'a> {
             current_document: None,
         };
 
-        get_client(token).await?.delete_document(req).await?;
-        Ok(())
+        run_with_timeout(
+            timeout.or(client.timeout),
+            client.backend.delete_document(req),
+        )
+        .await
     }
 }
 
@@ -361,18 +415,33 @@ impl<'a> DeleteDocumentOptions<'a> {
 pub struct BatchGetDocumentOptions<'a> {
     pub client: &'a FirebaseClient,
     pub names: Vec<String>,
+    pub timeout: Option<Duration>,
 }
 
 impl<'a> BatchGetDocumentOptions<'a> {
     fn new(client: &'a FirebaseClient, names: Vec<String>) -> Self {
-        Self { client, names }
+        Self {
+            client,
+            names,
+            timeout: None,
+        }
+    }
+
+    /// Overrides the timeout of the client for this request.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
     }
 
     pub async fn fetch(
         self,
     ) -> Result<Vec<Result<BatchGetDocumentsResponse, Status>>, FirestoreError> {
-        let Self { client, names } = self;
-        let token = client.get_token().await?;
+        let Self {
+            client,
+            names,
+            timeout,
+        } = self;
         let project_id = &client.project_id;
         let database = format!("projects/{}/databases/(default)", project_id);
         let documents = names
@@ -387,10 +456,11 @@ impl<'a> BatchGetDocumentOptions<'a> {
             database,
             ..Default::default()
         };
-        let res = get_client(token).await?.batch_get_documents(req).await?;
-        let stream = res.into_inner();
-        futures::pin_mut!(stream);
-        Ok(stream.collect().await)
+        run_with_timeout(
+            timeout.or(client.timeout),
+            client.backend.batch_get_documents(req),
+        )
+        .await
     }
 }
 
@@ -401,6 +471,7 @@ pub struct QueryOptions<'a> {
     client: &'a FirebaseClient,
     structured_query: StructuredQueryBuilder,
     parent: Option<String>,
+    timeout: Option<Duration>,
 }
 
 impl<'a> QueryOptions<'a> {
@@ -409,9 +480,17 @@ impl<'a> QueryOptions<'a> {
             client,
             parent: None,
             structured_query: StructuredQueryBuilder::new(),
+            timeout: None,
         }
     }
 
+    /// Overrides the timeout of the client for this request.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
     pub fn from<S: ToString>(mut self, collection_id: S) -> Self {
         self.structured_query
             .from_collections
@@ -429,7 +508,7 @@ impl<'a> QueryOptions<'a> {
         self
     }
 
-    pub fn order_by<S: ToString>(self, field: S) -> structured_query::OrderBuilder<Self> {
+    pub fn order_by<F: IntoFieldPath>(self, field: F) -> structured_query::OrderBuilder<Self> {
         structured_query::OrderBuilder::new(
             self,
             |me, order, start_at, end_at| {
@@ -441,24 +520,24 @@ impl<'a> QueryOptions<'a> {
         )
     }
 
-    pub fn unary_filter<S: ToString>(
+    pub fn unary_filter<F: IntoFieldPath>(
         mut self,
-        field: S,
+        field: F,
         op: structured_query::UnaryFilterOperator,
     ) -> Self {
         self.structured_query.set_unary_filter(field, op);
         self
     }
 
-    pub fn field_filter<T, S>(
+    pub fn field_filter<T, F>(
         mut self,
-        field: S,
+        field: F,
         op: structured_query::FieldFilterOperator,
         value: T,
     ) -> Self
     where
         T: IntoFirestoreDocumentValue,
-        S: ToString,
+        F: IntoFieldPath,
     {
         self.structured_query.set_field_filter(field, op, value);
         self
@@ -506,6 +585,7 @@ impl<'a> QueryOptions<'a> {
             client,
             structured_query,
             parent,
+            timeout,
         } = self;
 
         let project_id = &client.project_id;
@@ -529,11 +609,7 @@ impl<'a> QueryOptions<'a> {
             )),
         };
 
-        let token = client.get_token().await?;
-        let res = get_client(token).await?.run_query(req).await?;
-        let stream = res.into_inner();
-        futures::pin_mut!(stream);
-        Ok(stream.collect().await)
+        run_with_timeout(timeout.or(client.timeout), client.backend.run_query(req)).await
     }
 }
 
@@ -544,6 +620,8 @@ impl<'a> QueryOptions<'a> {
 pub struct UpdateDocumentOptions<'a> {
     pub client: &'a FirebaseClient,
     pub document: Document,
+    pub update_mask: Option<DocumentMask>,
+    pub timeout: Option<Duration>,
 }
 
 impl<'a> UpdateDocumentOptions<'a> {
@@ -559,9 +637,18 @@ impl<'a> UpdateDocumentOptions<'a> {
                 name,
                 ..Default::default()
             },
+            update_mask: None,
+            timeout: None,
         }
     }
 
+    /// Overrides the timeout of the client for this request.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
     pub fn document<T: IntoFirestoreDocument>(mut self, document: T) -> Self {
         let doc = std::mem::replace(
             &mut self.document,
@@ -580,35 +667,58 @@ impl<'a> UpdateDocumentOptions<'a> {
         self
     }
 
+    /// Only writes the fields at `paths` and keeps the other fields of the
+    /// stored document. Fields in the mask that are not set are deleted.
+    #[must_use]
+    pub fn update_mask<I, F>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = F>,
+        F: IntoFieldPath,
+    {
+        let mask = self.update_mask.get_or_insert_with(Default::default);
+        mask.field_paths.extend(
+            paths
+                .into_iter()
+                .map(|path| path.into_field_path().to_string()),
+        );
+        self
+    }
+
     pub async fn update(self) -> Result<Document, FirestoreError> {
-        let Self { document, client } = self;
-        let token = client.get_token().await?;
+        let Self {
+            document,
+            client,
+            update_mask,
+            timeout,
+        } = self;
 
         tracing::debug!("firestore update document {}", document.name);
 
         let req = UpdateDocumentRequest {
             document: Some(document),
-            update_mask: None,
+            update_mask,
             mask: None,
             current_document: None,
         };
 
-        let res = get_client(token).await?.update_document(req).await;
+        let res = run_with_timeout(
+            timeout.or(client.timeout),
+            client.backend.update_document(req),
+        )
+        .await;
 
-        let res = match res {
-            Err(status) => {
+        match res {
+            Err(FirestoreError::GrpcError(status)) => {
                 let details = String::from_utf8_lossy(status.details());
                 tracing::error!(
                     "error updating document: {} details: {}",
                     status.message(),
                     details
                 );
-                return Err(status.into());
+                Err(FirestoreError::GrpcError(status))
             }
-            Ok(res) => res,
-        };
-
-        Ok(res.into_inner())
+            res => res,
+        }
     }
 }
 
@@ -620,6 +730,7 @@ pub struct BatchUpdateDocumentOptions<'a> {
     pub client: &'a FirebaseClient,
     pub updates: Vec<Document>,
     pub deletes: Vec<String>,
+    pub timeout: Option<Duration>,
 }
 
 impl<'a> BatchUpdateDocumentOptions<'a> {
@@ -628,9 +739,17 @@ impl<'a> BatchUpdateDocumentOptions<'a> {
             client,
             updates: Vec::new(),
             deletes: Vec::new(),
+            timeout: None,
         }
     }
 
+    /// Overrides the timeout of the client for each request.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
     pub fn delete<S: ToString>(&mut self, id: S) {
         self.deletes.push(id.to_string());
     }
@@ -644,9 +763,9 @@ impl<'a> BatchUpdateDocumentOptions<'a> {
             client,
             updates,
             deletes,
+            timeout,
         } = self;
 
-        let token = client.get_token().await?;
         let project_id = &client.project_id;
         let database = format!("projects/{}/databases/(default)", project_id);
         let mut writes = Vec::new();
@@ -682,8 +801,9 @@ impl<'a> BatchUpdateDocumentOptions<'a> {
                 writes,
                 ..Default::default()
             };
-            let res = get_client(token.clone()).await?.batch_write(req).await?;
-            responses.push(res.into_inner());
+            let res = run_with_timeout(timeout.or(client.timeout), client.backend.batch_write(req))
+                .await?;
+            responses.push(res);
         }
 
         Ok(responses)
@@ -696,6 +816,8 @@ impl<'a> BatchUpdateDocumentOptions<'a> {
 pub struct FirebaseClient {
     pub project_id: String,
     pub auth: GoogleAuth,
+    pub(crate) backend: Arc<dyn FirestoreBackend>,
+    pub(crate) timeout: Option<Duration>,
 }
 
 impl Clone for FirebaseClient {
@@ -703,10 +825,36 @@ impl Clone for FirebaseClient {
         Self {
             project_id: self.project_id.clone(),
             auth: self.auth.box_clone(),
+            backend: self.backend.clone(),
+            timeout: self.timeout,
         }
     }
 }
 
+/// The authorization of clients whose backend takes care of authorization
+/// itself or does not need it.
+#[derive(Debug, Clone)]
+pub(crate) struct BackendAuthorization {
+    pub(crate) project_id: String,
+}
+
+#[async_trait::async_trait]
+impl firebase_client_auth::Authorization for BackendAuthorization {
+    fn project_id(&self) -> &str {
+        &self.project_id
+    }
+
+    async fn get_token(
+        &self,
+    ) -> Result<Option<String>, firebase_client_auth::error::GCloudAuthError> {
+        Ok(None)
+    }
+
+    fn box_clone(&self) -> GoogleAuth {
+        Box::new(self.clone())
+    }
+}
+
 impl FirebaseClient {
     pub fn for_account(account: GoogleServiceAccount) -> Self {
         FirebaseClient::new(Box::new(
@@ -718,14 +866,86 @@ impl FirebaseClient {
     }
 
     pub fn new(auth: GoogleAuth) -> Self {
-        let project_id = auth.project_id().to_string();
-        FirebaseClient { project_id, auth }
+        let backend = GrpcBackend::new(auth.box_clone());
+        Self::from_backend(auth, backend)
+    }
+
+    /// Like [`Self::new`] but connects to `endpoint`, see
+    /// [`GrpcBackend::with_endpoint`].
+    pub fn with_endpoint(
+        auth: GoogleAuth,
+        endpoint: firestore_grpc::tonic::transport::Endpoint,
+    ) -> Self {
+        let backend = GrpcBackend::with_endpoint(auth.box_clone(), endpoint);
+        Self::from_backend(auth, backend)
+    }
+
+    /// Like [`Self::new`] but talks to Firestore over `transport`.
+    pub fn with_transport(auth: GoogleAuth, transport: Transport) -> Self {
+        match transport {
+            Transport::Grpc => Self::new(auth),
+            #[cfg(feature = "rest")]
+            Transport::Rest => {
+                let backend = super::rest::RestBackend::new(auth.box_clone());
+                Self::from_backend(auth, backend)
+            }
+        }
+    }
+
+    /// A client that runs all operations against `backend`, e.g. an
+    /// [`super::memory::InMemoryFirestore`] in tests.
+    pub fn with_backend<B: FirestoreBackend + 'static>(backend: B) -> Self {
+        let project_id = backend
+            .project_id()
+            .unwrap_or_else(|| "default".to_string());
+        Self::from_backend(Box::new(BackendAuthorization { project_id }), backend)
+    }
+
+    fn from_backend<B: FirestoreBackend + 'static>(auth: GoogleAuth, backend: B) -> Self {
+        FirebaseClient {
+            project_id: auth.project_id().to_string(),
+            auth,
+            backend: Arc::new(InstrumentedBackend::new(backend)),
+            timeout: Some(DEFAULT_TIMEOUT),
+        }
+    }
+
+    /// Adds `interceptor` to the requests of this client and all its clones,
+    /// they share the backend. Only the gRPC and REST transports support
+    /// interceptors, other backends ignore them.
+    pub fn add_interceptor<I: RequestInterceptor + 'static>(&self, interceptor: I) {
+        match self.backend.interceptors() {
+            Some(interceptors) => interceptors.push(interceptor),
+            None => tracing::warn!("the backend of the client does not support interceptors"),
+        }
+    }
+
+    /// The timeout of requests without one of their own, [`DEFAULT_TIMEOUT`]
+    /// by default. `None` waits forever. Listen streams have no timeout.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Attaches the App Check token of `provider` to all requests of this
+    /// client and its clones, see [`AppCheckInterceptor`].
+    pub fn add_app_check<P: AppCheckProvider + 'static>(&self, provider: P) {
+        self.add_interceptor(AppCheckInterceptor::new(provider))
+    }
+
+    pub fn backend(&self) -> &dyn FirestoreBackend {
+        self.backend.as_ref()
     }
 
     pub async fn get_token(&self) -> Result<Option<String>, FirestoreError> {
         Ok(self.auth.get_token().await?)
     }
 
+    pub async fn token_expires_at(&self) -> Option<std::time::SystemTime> {
+        self.auth.token_expires_at().await
+    }
+
     pub fn documents_path(&self) -> String {
         format!("projects/{}/databases/(default)/documents", self.project_id)
     }
@@ -755,7 +975,7 @@ impl FirebaseClient {
     {
         let col_name = col.as_ref();
         let id = id.as_ref();
-        let doc = match self.get_document(&format!("{col_name}/{id}")).fetch().await {
+        let doc = match self.get_document(format!("{col_name}/{id}")).fetch().await {
             Err(err) => {
                 tracing::error!("Unable to get doc from firebase: {err}");
                 return Err(err);
@@ -797,10 +1017,18 @@ impl FirebaseClient {
         super::fetch_and_update::FetchAndUpdate::new(self, col, id)
     }
 
-    pub fn run_query(&self) -> QueryOptions {
+    pub fn run_query(&self) -> QueryOptions<'_> {
         QueryOptions::new(self)
     }
 
+    /// Opens a single `Listen` stream that multiplexes several targets, see
+    /// [`super::listen_session::ListenSession`].
+    pub async fn listen_session(
+        &self,
+    ) -> Result<super::listen_session::ListenSession, FirestoreError> {
+        super::listen_session::ListenSession::connect(self).await
+    }
+
     pub fn stream_builder<S: ToString>(
         &self,
         collection_id: S,
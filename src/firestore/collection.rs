@@ -2,13 +2,17 @@ use chrono::prelude::*;
 use firestore_grpc::v1 as firestore;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
 };
 
-use crate::CachedCollectionError;
+use crate::{CachedCollectionError, FirestoreError};
 
-use super::{conversion::FromFirestoreDocument, FirebaseClient};
+use super::{
+    conversion::FromFirestoreDocument,
+    store::{self, CollectionStore},
+    FirebaseClient,
+};
 
 pub type SharedDocuments = HashMap<String, firestore::Document>;
 
@@ -25,7 +29,7 @@ pub enum CollectionChange {
     },
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct CollectionUpdate {
     pub changes: Vec<CollectionChange>,
     pub documents: SharedDocuments,
@@ -33,6 +37,59 @@ pub struct CollectionUpdate {
     pub resume_token: Vec<u8>,
 }
 
+/// A [`CollectionUpdate`] with documents converted to `T`.
+#[derive(Debug, Clone)]
+pub struct TypedCollectionUpdate<T> {
+    pub changes: Vec<CollectionChange>,
+    pub documents: HashMap<String, T>,
+    pub time: Option<DateTime<Utc>>,
+    pub resume_token: Vec<u8>,
+}
+
+impl<T> TypedCollectionUpdate<T>
+where
+    T: FromFirestoreDocument,
+{
+    /// Converts the documents of `update`. Documents that cannot be converted
+    /// are left out of the result, including their changes, and reported as
+    /// errors.
+    pub fn convert(update: CollectionUpdate) -> (Self, Vec<FirestoreError>) {
+        let CollectionUpdate {
+            mut changes,
+            documents,
+            time,
+            resume_token,
+        } = update;
+
+        let mut errors = Vec::new();
+        let mut converted = HashMap::new();
+        for (name, doc) in documents {
+            match T::convert_doc(doc) {
+                Ok(val) => {
+                    converted.insert(name, val);
+                }
+                Err(err) => errors.push(FirestoreError::DocumentConversionError {
+                    document: name,
+                    message: err.to_string(),
+                }),
+            }
+        }
+
+        changes.retain(|change| match change {
+            CollectionChange::Change { id, .. } => converted.contains_key(id),
+            CollectionChange::Delete { .. } => true,
+        });
+
+        let update = Self {
+            changes,
+            documents: converted,
+            time,
+            resume_token,
+        };
+        (update, errors)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CachedCollection<T> {
     pub name: String,
@@ -40,6 +97,9 @@ pub struct CachedCollection<T> {
     pub documents: HashMap<String, T>,
     pub time: Option<DateTime<Utc>>,
     pub resume_token: Option<Vec<u8>>,
+    /// Ids of documents changed or removed since the last save.
+    #[serde(skip)]
+    dirty: HashSet<String>,
 }
 
 impl<T> CachedCollection<T> {
@@ -50,9 +110,67 @@ impl<T> CachedCollection<T> {
             cache_file: None,
             resume_token: None,
             time: None,
+            dirty: HashSet::new(),
         }
     }
 
+    /// Loads the collection from `store`. When nothing is stored yet, the
+    /// stored format is not supported anymore or the stored data is corrupt,
+    /// an empty collection is returned that can be filled again.
+    pub fn load_from<S: ToString>(
+        name: S,
+        store: &dyn CollectionStore<T>,
+    ) -> Result<Self, CachedCollectionError> {
+        match store.load() {
+            Ok(Some(collection)) => Ok(collection),
+            Ok(None) => Ok(Self::new(name)),
+            Err(CachedCollectionError::UnsupportedVersion(version)) => {
+                tracing::warn!(
+                    "cached collection {} has unsupported format version {}, rebuilding",
+                    name.to_string(),
+                    version
+                );
+                Ok(Self::new(name))
+            }
+            Err(CachedCollectionError::CacheSerializationError(err)) => {
+                tracing::warn!(
+                    "cached collection {} cannot be read, rebuilding: {}",
+                    name.to_string(),
+                    err
+                );
+                Ok(Self::new(name))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Writes the documents changed since the last save to `store`.
+    pub fn save_to(&mut self, store: &dyn CollectionStore<T>) -> Result<(), CachedCollectionError> {
+        store.save(self, &self.dirty)?;
+        self.dirty.clear();
+        Ok(())
+    }
+
+    /// Ids of the documents changed or removed since the last save.
+    pub fn dirty(&self) -> &HashSet<String> {
+        &self.dirty
+    }
+
+    /// Takes the dirty ids, for saves that happen outside of the lock of the
+    /// collection.
+    pub(crate) fn take_dirty(&mut self) -> HashSet<String> {
+        std::mem::take(&mut self.dirty)
+    }
+
+    pub(crate) fn mark_dirty(&mut self, ids: impl IntoIterator<Item = String>) {
+        self.dirty.extend(ids);
+    }
+
+    /// Marks all documents as changed, e.g. before saving to a new store.
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty.extend(self.documents.keys().cloned());
+    }
+
     pub fn len(&self) -> usize {
         self.documents.len()
     }
@@ -109,11 +227,13 @@ where
     }
 
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CachedCollectionError> {
-        tracing::debug!("loading from file {}", path.as_ref().display());
-        let file = std::fs::OpenOptions::new().read(true).open(&path)?;
-        let mut loaded: Self = serde_json::from_reader(file)?;
-        loaded.cache_file = Some(path.as_ref().to_path_buf());
-        Ok(loaded)
+        store::load_json_file(path.as_ref())?.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} does not exist", path.as_ref().display()),
+            )
+            .into()
+        })
     }
 }
 
@@ -121,7 +241,8 @@ impl<T> CachedCollection<T>
 where
     T: FromFirestoreDocument + Serialize,
 {
-    pub fn save(&self) -> Result<(), CachedCollectionError> {
+    /// Writes the whole collection to its cache file and clears the dirty ids.
+    pub fn save(&mut self) -> Result<(), CachedCollectionError> {
         let file = match &self.cache_file {
             None => {
                 tracing::error!("cannot save cached collection {}, no cache file", self.name);
@@ -130,35 +251,36 @@ where
             Some(file) => file,
         };
 
-        tracing::debug!("saving to file {}", file.display());
-
-        let file = std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(file)?;
-
-        serde_json::to_writer(file, &self)?;
-        // serde_json::to_writer_pretty(file, &self)?;
+        store::save_json_file(file, self)?;
+        self.dirty.clear();
         Ok(())
     }
 
-    pub fn update_from(&mut self, mut other: CollectionUpdate) -> Vec<String> {
-        let changes = other.changes.drain(..);
+    pub fn update_from(&mut self, other: CollectionUpdate) -> Vec<String> {
+        let (update, errors) = TypedCollectionUpdate::<T>::convert(other);
+        for err in errors {
+            tracing::error!("[cached collection {}] {}", self.name, err);
+        }
+
         let mut changed_docs = Vec::new();
-        for change in changes {
+        for change in update.changes {
             match change {
-                CollectionChange::Change { id, .. } => changed_docs.push(id),
+                CollectionChange::Change { id, .. } => {
+                    self.dirty.insert(id.clone());
+                    changed_docs.push(id);
+                }
                 CollectionChange::Delete { id, .. } => {
                     self.documents.remove(&id);
+                    self.dirty.insert(id);
                 }
             }
         }
 
-        let other: Self = other.into();
-        self.time = other.time;
-        self.resume_token = other.resume_token;
-        self.documents.extend(other.documents);
+        self.time = update.time;
+        if !update.resume_token.is_empty() {
+            self.resume_token = Some(update.resume_token);
+        }
+        self.documents.extend(update.documents);
 
         changed_docs
     }
@@ -173,6 +295,7 @@ where
             let name = doc.name.clone();
             match T::convert_doc(doc) {
                 Ok(val) => {
+                    self.dirty.insert(name.clone());
                     self.documents.insert(name, val);
                 }
                 Err(err) => {
@@ -193,22 +316,16 @@ where
     T: FromFirestoreDocument,
 {
     fn from(collection: CollectionUpdate) -> Self {
-        let CollectionUpdate {
+        let (update, errors) = TypedCollectionUpdate::<T>::convert(collection);
+        for err in errors {
+            tracing::error!("[cached collection] {}", err);
+        }
+        let TypedCollectionUpdate {
             documents,
             resume_token,
             time,
             ..
-        } = collection;
-        let documents = documents
-            .into_iter()
-            .filter_map(|(key, doc)| match T::convert_doc(doc) {
-                Ok(val) => Some((key, val)),
-                Err(err) => {
-                    eprintln!("Failure when converting firestore document {}", err);
-                    None
-                }
-            })
-            .collect::<HashMap<_, _>>();
+        } = update;
 
         CachedCollection {
             name: "???".to_string(), // FIXME
@@ -216,6 +333,99 @@ where
             time,
             resume_token: Some(resume_token),
             cache_file: None,
+            dirty: HashSet::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[derive(Debug, Serialize)]
+    struct Named(String);
+
+    impl FromFirestoreDocument for Named {
+        type Err = String;
+
+        fn convert_doc(doc: firestore::Document) -> Result<Self, Self::Err> {
+            if doc.fields.is_empty() {
+                Ok(Named(doc.name))
+            } else {
+                Err("unexpected fields".to_string())
+            }
+        }
+    }
+
+    #[test]
+    fn typed_update_reports_conversion_errors() {
+        let good = firestore::Document {
+            name: "good".to_string(),
+            ..Default::default()
+        };
+        let mut bad = firestore::Document {
+            name: "bad".to_string(),
+            ..Default::default()
+        };
+        bad.fields
+            .insert("field".to_string(), firestore::Value { value_type: None });
+
+        let update = CollectionUpdate {
+            changes: ["good", "bad"]
+                .map(|id| CollectionChange::Change {
+                    id: id.to_string(),
+                    time: Utc::now(),
+                })
+                .to_vec(),
+            documents: HashMap::from_iter([(good.name.clone(), good), (bad.name.clone(), bad)]),
+            ..Default::default()
+        };
+
+        let mut collection = CachedCollection::<Named>::new("items");
+        assert_eq!(collection.update_from(update.clone()), vec!["good"]);
+        assert_eq!(collection.documents.len(), 1);
+
+        let (update, errors) = TypedCollectionUpdate::<Named>::convert(update);
+        assert_eq!(
+            update
+                .documents
+                .values()
+                .map(|doc| doc.0.as_str())
+                .collect::<Vec<_>>(),
+            vec!["good"]
+        );
+        assert_eq!(update.changes.len(), 1);
+        assert_eq!(
+            errors.iter().map(|err| err.to_string()).collect::<Vec<_>>(),
+            vec!["Cannot convert document bad: unexpected fields"]
+        );
+    }
+
+    #[test]
+    fn save_clears_dirty_ids() {
+        let path =
+            std::env::temp_dir().join(format!("cached-collection-{}.json", std::process::id()));
+        let mut collection = CachedCollection::<Named>::new("items");
+        collection.cache_file = Some(path.clone());
+        collection.update_from(CollectionUpdate {
+            changes: vec![CollectionChange::Change {
+                id: "a".to_string(),
+                time: Utc::now(),
+            }],
+            documents: HashMap::from_iter([(
+                "a".to_string(),
+                firestore::Document {
+                    name: "a".to_string(),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        });
+        assert_eq!(collection.dirty().len(), 1);
+
+        collection.save().unwrap();
+        assert!(collection.dirty().is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+}
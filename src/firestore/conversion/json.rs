@@ -1,3 +1,5 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
 use firestore_grpc::v1 as firestore;
 use prost_types::Timestamp;
 use serde::de::DeserializeOwned;
@@ -12,74 +14,168 @@ use super::{
 
 // -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
 
-impl FromFirestoreValue for Value {
-    type Err = FirestoreConversionError;
+const TIMESTAMP_TAG: &str = "$timestamp";
+const BYTES_TAG: &str = "$bytes";
+const REFERENCE_TAG: &str = "$reference";
+const GEO_POINT_TAG: &str = "$geopoint";
+const DOUBLE_TAG: &str = "$double";
 
-    fn convert(val: firestore::Value) -> Result<Value, FirestoreConversionError> {
+/// How timestamps are represented in JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampFormat {
+    /// Milliseconds since the epoch, drops sub millisecond precision.
+    #[default]
+    Millis,
+    /// An RFC 3339 string like `"2023-01-01T12:00:00.123456Z"`.
+    Rfc3339,
+    /// An object `{"seconds": ..., "nanos": ...}`.
+    SecondsNanos,
+}
+
+/// How bytes are represented in JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BytesFormat {
+    /// A string of the UTF-8 decoded bytes, invalid UTF-8 becomes `""`.
+    #[default]
+    LossyUtf8,
+    /// A base64 string.
+    Base64,
+}
+
+/// Controls how Firestore values that have no JSON counterpart (timestamps,
+/// bytes, references, geo points) are converted.
+///
+/// The default matches the `FromFirestoreValue` / `IntoFirestoreDocumentValue`
+/// impls of [`Value`]. [`JsonConversionOptions::lossless`] round-trips
+/// firestore → JSON → firestore without loss by tagging those values, e.g.
+/// `{"$timestamp": "2023-01-01T12:00:00Z"}`, `{"$bytes": "AAE="}`,
+/// `{"$reference": "projects/..."}` or `{"$geopoint": {"latitude": 1.0,
+/// "longitude": 2.0}}`. Non-finite doubles become `{"$double": "NaN"}`. Map
+/// keys starting with `$` are escaped as `$$...` so that they are never taken
+/// for tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct JsonConversionOptions {
+    pub timestamps: TimestampFormat,
+    pub bytes: BytesFormat,
+    pub tagged: bool,
+}
+
+impl JsonConversionOptions {
+    /// RFC 3339 timestamps, base64 bytes and tagged values.
+    pub fn lossless() -> Self {
+        Self {
+            timestamps: TimestampFormat::Rfc3339,
+            bytes: BytesFormat::Base64,
+            tagged: true,
+        }
+    }
+
+    #[must_use]
+    pub fn timestamps(mut self, format: TimestampFormat) -> Self {
+        self.timestamps = format;
+        self
+    }
+
+    #[must_use]
+    pub fn bytes(mut self, format: BytesFormat) -> Self {
+        self.bytes = format;
+        self
+    }
+
+    /// Wrap timestamps, bytes, references, geo points and non-finite doubles in
+    /// objects with a single tag key like `$timestamp` so they can be
+    /// converted back. Keys of maps that start with `$` are escaped by
+    /// doubling the `$`, e.g. `$timestamp` becomes `$$timestamp`.
+    #[must_use]
+    pub fn tagged(mut self, tagged: bool) -> Self {
+        self.tagged = tagged;
+        self
+    }
+
+    fn tag(&self, tag: &str, value: Value) -> Value {
+        if self.tagged {
+            let mut obj = Map::new();
+            obj.insert(tag.to_string(), value);
+            Value::Object(obj)
+        } else {
+            value
+        }
+    }
+
+    fn escape_key(&self, key: String) -> String {
+        if self.tagged && key.starts_with('$') {
+            format!("${key}")
+        } else {
+            key
+        }
+    }
+
+    fn unescape_key(&self, key: String) -> String {
+        match key.strip_prefix('$') {
+            Some(unescaped) if self.tagged && unescaped.starts_with('$') => unescaped.to_string(),
+            _ => key,
+        }
+    }
+
+    // firestore -> JSON
+
+    pub fn value_to_json(&self, val: firestore::Value) -> Result<Value, FirestoreConversionError> {
+        use firestore::value::ValueType;
         let result = match val.value_type {
-            None => Value::Null,
-            Some(firestore::value::ValueType::NullValue(_)) => Value::Null,
-            Some(firestore::value::ValueType::BooleanValue(val)) => Value::Bool(val),
-            Some(firestore::value::ValueType::IntegerValue(val)) => Value::Number(val.into()),
-            Some(firestore::value::ValueType::DoubleValue(val)) => {
-                let n = Number::from_f64(val).unwrap_or_else(|| 0.into());
-                Value::Number(n)
-            }
-            Some(firestore::value::ValueType::TimestampValue(val)) => {
-                let ms = std::time::Duration::new(val.seconds as u64, val.nanos as u32).as_millis()
-                    as u64;
-                Value::Number(ms.into())
+            None | Some(ValueType::NullValue(_)) => Value::Null,
+            Some(ValueType::BooleanValue(val)) => Value::Bool(val),
+            Some(ValueType::IntegerValue(val)) => Value::Number(val.into()),
+            Some(ValueType::DoubleValue(val)) => match Number::from_f64(val) {
+                Some(n) => Value::Number(n),
+                None if self.tagged => self.tag(DOUBLE_TAG, Value::String(val.to_string())),
+                None => Value::Number(0.into()),
+            },
+            Some(ValueType::TimestampValue(val)) => {
+                self.tag(TIMESTAMP_TAG, self.timestamp_to_json(&val)?)
             }
-            Some(firestore::value::ValueType::StringValue(val)) => Value::String(val),
-            Some(firestore::value::ValueType::ArrayValue(val)) => Value::Array(
+            Some(ValueType::StringValue(val)) => Value::String(val),
+            Some(ValueType::ArrayValue(val)) => Value::Array(
                 val.values
                     .into_iter()
-                    .map(|val| Value::convert(val).unwrap())
-                    .collect::<Vec<_>>(),
+                    .map(|val| self.value_to_json(val))
+                    .collect::<Result<Vec<_>, _>>()?,
             ),
-            Some(firestore::value::ValueType::MapValue(val)) => {
-                let mut obj = Map::new();
-                for (key, val) in val.fields {
-                    obj.insert(key.to_string(), Value::convert(val)?);
-                }
-                Value::Object(obj)
-            }
-            Some(firestore::value::ValueType::BytesValue(val)) => {
-                eprintln!("Trying to convert firestore BytesValue to JSON... no guarantees");
-                let string = String::from_utf8(val).unwrap_or_else(|_| String::new());
-                Value::String(string)
-            }
-            Some(firestore::value::ValueType::ReferenceValue(val)) => {
-                eprintln!("Trying to convert firestore ReferenceValue to JSON... no guarantees");
-                Value::String(val)
+            Some(ValueType::MapValue(val)) => Value::Object(self.fields_to_json(val.fields)?),
+            Some(ValueType::BytesValue(val)) => {
+                let string = match self.bytes {
+                    BytesFormat::LossyUtf8 => String::from_utf8(val).unwrap_or_default(),
+                    BytesFormat::Base64 => BASE64.encode(val),
+                };
+                self.tag(BYTES_TAG, Value::String(string))
             }
-            Some(firestore::value::ValueType::GeoPointValue(val)) => {
-                eprintln!("Trying to convert firestore GeoPointValue to JSON... no guarantees");
+            Some(ValueType::ReferenceValue(val)) => self.tag(REFERENCE_TAG, Value::String(val)),
+            Some(ValueType::GeoPointValue(val)) => {
                 let mut obj = Map::new();
-                obj.insert(
-                    "latitude".to_string(),
-                    Number::from_f64(val.latitude)
-                        .map(Value::Number)
-                        .unwrap_or(Value::Null),
-                );
-                obj.insert(
-                    "longitude".to_string(),
-                    Number::from_f64(val.longitude)
-                        .map(Value::Number)
-                        .unwrap_or(Value::Null),
-                );
-                Value::Object(obj)
+                obj.insert("latitude".to_string(), float_to_json(val.latitude));
+                obj.insert("longitude".to_string(), float_to_json(val.longitude));
+                self.tag(GEO_POINT_TAG, Value::Object(obj))
             }
         };
 
         Ok(result)
     }
-}
 
-impl FromFirestoreDocument for Value {
-    type Err = FirestoreConversionError;
+    pub fn fields_to_json(
+        &self,
+        fields: HashMap<String, firestore::Value>,
+    ) -> Result<Map<String, Value>, FirestoreConversionError> {
+        fields
+            .into_iter()
+            .map(|(key, val)| Ok((self.escape_key(key), self.value_to_json(val)?)))
+            .collect()
+    }
 
-    fn convert_doc(doc: firestore::Document) -> Result<Value, FirestoreConversionError> {
+    /// A JSON object with the `name`, `create_time`, `update_time` and
+    /// `fields` of the document. Document times are never tagged.
+    pub fn document_to_json(
+        &self,
+        doc: firestore::Document,
+    ) -> Result<Value, FirestoreConversionError> {
         let firestore::Document {
             create_time,
             update_time,
@@ -87,42 +183,56 @@ impl FromFirestoreDocument for Value {
             name,
         } = doc;
 
-        let mut obj = Map::new();
-
-        let create_time = if let Some(t) = create_time {
-            let ms = std::time::Duration::new(t.seconds as u64, t.nanos as u32).as_millis() as u64;
-            Value::Number(ms.into())
-        } else {
-            Value::Null
+        let time = |time: Option<Timestamp>| match time {
+            Some(t) => self.timestamp_to_json(&t),
+            None => Ok(Value::Null),
         };
 
-        let update_time = if let Some(t) = update_time {
-            let ms = std::time::Duration::new(t.seconds as u64, t.nanos as u32).as_millis() as u64;
-            Value::Number(ms.into())
-        } else {
-            Value::Null
-        };
-
-        obj.insert("create_time".to_string(), create_time);
-        obj.insert("update_time".to_string(), update_time);
+        let mut obj = Map::new();
+        obj.insert("create_time".to_string(), time(create_time)?);
+        obj.insert("update_time".to_string(), time(update_time)?);
         obj.insert("name".to_string(), Value::String(name));
-
-        let mut fields_obj = Map::new();
-        for (key, val) in fields {
-            fields_obj.insert(key.to_string(), Value::convert(val)?);
-        }
-        obj.insert("fields".to_string(), Value::Object(fields_obj));
+        obj.insert(
+            "fields".to_string(),
+            Value::Object(self.fields_to_json(fields)?),
+        );
 
         Ok(Value::Object(obj))
     }
-}
 
-// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+    fn timestamp_to_json(&self, ts: &Timestamp) -> Result<Value, FirestoreConversionError> {
+        let value = match self.timestamps {
+            TimestampFormat::Millis => {
+                Value::Number((ts.seconds * 1000 + i64::from(ts.nanos) / 1_000_000).into())
+            }
+            TimestampFormat::Rfc3339 => {
+                let time = u32::try_from(ts.nanos)
+                    .ok()
+                    .and_then(|nanos| Utc.timestamp_opt(ts.seconds, nanos).single())
+                    .ok_or_else(|| {
+                        FirestoreConversionError::FromFirestoreError(format!(
+                            "invalid timestamp {:?}",
+                            ts
+                        ))
+                    })?;
+                Value::String(time.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            }
+            TimestampFormat::SecondsNanos => {
+                let mut obj = Map::new();
+                obj.insert("seconds".to_string(), ts.seconds.into());
+                obj.insert("nanos".to_string(), ts.nanos.into());
+                Value::Object(obj)
+            }
+        };
+        Ok(value)
+    }
 
-impl IntoFirestoreDocumentValue for Value {
-    fn into_document_value(self) -> firestore::Value {
+    // JSON -> firestore
+
+    /// Tagged values are only recognized when [`Self::tagged`] is set.
+    pub fn json_to_value(&self, json: Value) -> Result<firestore::Value, FirestoreConversionError> {
         use firestore::value::ValueType;
-        let value_type = match self {
+        let value_type = match json {
             Value::Null => ValueType::NullValue(0),
             Value::Bool(bool) => ValueType::BooleanValue(bool),
             Value::Number(val) => {
@@ -136,21 +246,177 @@ impl IntoFirestoreDocumentValue for Value {
             Value::Array(val) => ValueType::ArrayValue(firestore::ArrayValue {
                 values: val
                     .into_iter()
-                    .map(|val| val.into_document_value())
-                    .collect::<Vec<_>>(),
+                    .map(|val| self.json_to_value(val))
+                    .collect::<Result<Vec<_>, _>>()?,
             }),
-            Value::Object(val) => ValueType::MapValue(firestore::MapValue {
-                fields: HashMap::from_iter(
-                    val.into_iter()
-                        .map(|(key, val)| (key, val.into_document_value())),
-                ),
+            Value::Object(obj) if self.tagged && obj.len() == 1 => {
+                let (tag, val) = obj.iter().next().expect("one entry");
+                match tag.as_str() {
+                    TIMESTAMP_TAG => ValueType::TimestampValue(self.json_to_timestamp(val)?),
+                    BYTES_TAG => ValueType::BytesValue(self.json_to_bytes(val)?),
+                    REFERENCE_TAG => ValueType::ReferenceValue(expect_str(val, tag)?.to_string()),
+                    GEO_POINT_TAG => {
+                        ValueType::GeoPointValue(firestore_grpc::google::r#type::LatLng {
+                            latitude: expect_f64(&val["latitude"], "latitude")?,
+                            longitude: expect_f64(&val["longitude"], "longitude")?,
+                        })
+                    }
+                    DOUBLE_TAG => ValueType::DoubleValue(
+                        expect_str(val, tag)?
+                            .parse()
+                            .map_err(|_| invalid(tag, val))?,
+                    ),
+                    _ => ValueType::MapValue(firestore::MapValue {
+                        fields: self.json_to_fields(obj)?,
+                    }),
+                }
+            }
+            Value::Object(obj) => ValueType::MapValue(firestore::MapValue {
+                fields: self.json_to_fields(obj)?,
             }),
         };
 
-        firestore::Value {
+        Ok(firestore::Value {
             value_type: Some(value_type),
+        })
+    }
+
+    pub fn json_to_fields(
+        &self,
+        obj: Map<String, Value>,
+    ) -> Result<HashMap<String, firestore::Value>, FirestoreConversionError> {
+        obj.into_iter()
+            .map(|(key, val)| Ok((self.unescape_key(key), self.json_to_value(val)?)))
+            .collect()
+    }
+
+    /// The reverse of [`Self::document_to_json`].
+    pub fn json_to_document(
+        &self,
+        json: Value,
+    ) -> Result<firestore::Document, FirestoreConversionError> {
+        let mut obj = match json {
+            Value::Object(obj) => obj,
+            _ => {
+                return Err(FirestoreConversionError::IntoFirestoreError(
+                    "document is not an object".to_string(),
+                ))
+            }
+        };
+        let name = match obj.remove("name") {
+            Some(Value::String(name)) => name,
+            _ => {
+                return Err(FirestoreConversionError::IntoFirestoreError(
+                    "name is not a string".to_string(),
+                ))
+            }
+        };
+        let fields = match obj.remove("fields") {
+            Some(Value::Object(fields)) => self.json_to_fields(fields)?,
+            _ => {
+                return Err(FirestoreConversionError::IntoFirestoreError(
+                    "fields is not an object".to_string(),
+                ))
+            }
+        };
+        let time = |key: &str| match obj.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(val) => self.json_to_timestamp(val).map(Some),
+        };
+
+        Ok(firestore::Document {
+            name,
+            fields,
+            create_time: time("create_time")?,
+            update_time: time("update_time")?,
+        })
+    }
+
+    /// Accepts all [`TimestampFormat`]s.
+    fn json_to_timestamp(&self, val: &Value) -> Result<Timestamp, FirestoreConversionError> {
+        match val {
+            Value::Number(millis) => {
+                let millis = millis.as_i64().ok_or_else(|| invalid(TIMESTAMP_TAG, val))?;
+                Ok(Timestamp {
+                    seconds: millis.div_euclid(1000),
+                    nanos: (millis.rem_euclid(1000) * 1_000_000) as i32,
+                })
+            }
+            Value::String(time) => {
+                let time =
+                    DateTime::parse_from_rfc3339(time).map_err(|_| invalid(TIMESTAMP_TAG, val))?;
+                Ok(Timestamp {
+                    seconds: time.timestamp(),
+                    nanos: time.timestamp_subsec_nanos() as i32,
+                })
+            }
+            Value::Object(obj) => {
+                let seconds = obj.get("seconds").and_then(Value::as_i64);
+                let nanos = obj
+                    .get("nanos")
+                    .and_then(Value::as_i64)
+                    .and_then(|nanos| i32::try_from(nanos).ok());
+                match (seconds, nanos) {
+                    (Some(seconds), Some(nanos)) => Ok(Timestamp { seconds, nanos }),
+                    _ => Err(invalid(TIMESTAMP_TAG, val)),
+                }
+            }
+            _ => Err(invalid(TIMESTAMP_TAG, val)),
         }
     }
+
+    fn json_to_bytes(&self, val: &Value) -> Result<Vec<u8>, FirestoreConversionError> {
+        let string = expect_str(val, BYTES_TAG)?;
+        match self.bytes {
+            BytesFormat::LossyUtf8 => Ok(string.as_bytes().to_vec()),
+            BytesFormat::Base64 => BASE64.decode(string).map_err(|_| invalid(BYTES_TAG, val)),
+        }
+    }
+}
+
+fn float_to_json(val: f64) -> Value {
+    Number::from_f64(val)
+        .map(Value::Number)
+        .unwrap_or(Value::Null)
+}
+
+fn invalid(what: &str, val: &Value) -> FirestoreConversionError {
+    FirestoreConversionError::IntoFirestoreError(format!("invalid {}: {}", what, val))
+}
+
+fn expect_str<'a>(val: &'a Value, what: &str) -> Result<&'a str, FirestoreConversionError> {
+    val.as_str().ok_or_else(|| invalid(what, val))
+}
+
+fn expect_f64(val: &Value, what: &str) -> Result<f64, FirestoreConversionError> {
+    val.as_f64().ok_or_else(|| invalid(what, val))
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+impl FromFirestoreValue for Value {
+    type Err = FirestoreConversionError;
+
+    fn convert(val: firestore::Value) -> Result<Value, FirestoreConversionError> {
+        JsonConversionOptions::default().value_to_json(val)
+    }
+}
+
+impl FromFirestoreDocument for Value {
+    type Err = FirestoreConversionError;
+
+    fn convert_doc(doc: firestore::Document) -> Result<Value, FirestoreConversionError> {
+        JsonConversionOptions::default().document_to_json(doc)
+    }
+}
+
+impl IntoFirestoreDocumentValue for Value {
+    fn into_document_value(self) -> firestore::Value {
+        // untagged conversions don't fail
+        JsonConversionOptions::default()
+            .json_to_value(self)
+            .unwrap_or_default()
+    }
 }
 
 impl IntoFirestoreDocument for Value {
@@ -159,10 +425,7 @@ impl IntoFirestoreDocument for Value {
     /// self are the fields of the document, not the document itself
     fn into_document_from_fields(self) -> Result<firestore::Document, Self::Err> {
         let fields = if let Value::Object(obj) = self {
-            HashMap::from_iter(
-                obj.into_iter()
-                    .map(|(key, val)| (key, val.into_document_value())),
-            )
+            JsonConversionOptions::default().json_to_fields(obj)?
         } else {
             return Err(FirestoreConversionError::IntoFirestoreError(format!(
                 "{:?} is not an object",
@@ -176,53 +439,8 @@ impl IntoFirestoreDocument for Value {
         })
     }
 
-    fn into_document(mut self) -> Result<firestore::Document, Self::Err> {
-        let name = if let Value::String(name) = self["name"].clone() {
-            name
-        } else {
-            return Err(FirestoreConversionError::IntoFirestoreError(
-                "name is not a string".to_string(),
-            ));
-        };
-        let fields = if let Some(value @ Value::Object(..)) = self.get_mut("fields") {
-            value
-                .take()
-                .into_document_from_fields()
-                .map(|doc| doc.fields)?
-        } else {
-            return Err(FirestoreConversionError::IntoFirestoreError(
-                "fields is not an object".to_string(),
-            ));
-        };
-        let create_time = match self.get("create_time") {
-            Some(Value::Number(millis)) if millis.as_u64().is_some() => {
-                let millis = millis.as_u64().unwrap();
-                let d = std::time::Duration::from_millis(millis);
-                Some(Timestamp {
-                    seconds: d.as_secs() as i64,
-                    nanos: d.subsec_nanos() as i32,
-                })
-            }
-            _ => None,
-        };
-        let update_time = match self.get("create_time") {
-            Some(Value::Number(millis)) if millis.as_u64().is_some() => {
-                let millis = millis.as_u64().unwrap();
-                let d = std::time::Duration::from_millis(millis);
-                Some(Timestamp {
-                    seconds: d.as_secs() as i64,
-                    nanos: d.subsec_nanos() as i32,
-                })
-            }
-            _ => None,
-        };
-
-        Ok(firestore::Document {
-            name,
-            fields,
-            create_time,
-            update_time,
-        })
+    fn into_document(self) -> Result<firestore::Document, Self::Err> {
+        JsonConversionOptions::default().json_to_document(self)
     }
 }
 
@@ -290,3 +508,115 @@ where
 
     Ok(obj)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use firestore::value::ValueType;
+    use pretty_assertions::assert_eq;
+
+    fn value(value_type: ValueType) -> firestore::Value {
+        firestore::Value {
+            value_type: Some(value_type),
+        }
+    }
+
+    fn document() -> firestore::Document {
+        let fields = HashMap::from([
+            (
+                "time".to_string(),
+                value(ValueType::TimestampValue(Timestamp {
+                    seconds: 1_700_000_000,
+                    nanos: 123_456_789,
+                })),
+            ),
+            (
+                "bytes".to_string(),
+                value(ValueType::BytesValue(vec![0, 159, 255])),
+            ),
+            (
+                "ref".to_string(),
+                value(ValueType::ReferenceValue(
+                    "projects/p/databases/(default)/documents/items/a".to_string(),
+                )),
+            ),
+            (
+                "geo".to_string(),
+                value(ValueType::GeoPointValue(
+                    firestore_grpc::google::r#type::LatLng {
+                        latitude: 52.5,
+                        longitude: 13.4,
+                    },
+                )),
+            ),
+            (
+                "nan".to_string(),
+                value(ValueType::DoubleValue(f64::INFINITY)),
+            ),
+            (
+                "name".to_string(),
+                value(ValueType::StringValue("a".to_string())),
+            ),
+        ]);
+        firestore::Document {
+            name: "projects/p/databases/(default)/documents/items/a".to_string(),
+            fields,
+            create_time: Some(Timestamp {
+                seconds: 1_600_000_000,
+                nanos: 1_000_000,
+            }),
+            update_time: Some(Timestamp {
+                seconds: 1_650_000_000,
+                nanos: 2_000_000,
+            }),
+        }
+    }
+
+    #[test]
+    fn lossless_round_trip() {
+        for timestamps in [TimestampFormat::Rfc3339, TimestampFormat::SecondsNanos] {
+            let options = JsonConversionOptions::lossless().timestamps(timestamps);
+            let json = options.document_to_json(document()).unwrap();
+            assert_eq!(options.json_to_document(json).unwrap(), document());
+        }
+
+        let json = JsonConversionOptions::lossless()
+            .value_to_json(value(ValueType::BytesValue(vec![0, 1])))
+            .unwrap();
+        assert_eq!(json, serde_json::json!({ "$bytes": "AAE=" }));
+
+        // maps that look like tags stay maps
+        let options = JsonConversionOptions::lossless();
+        for key in [
+            "$timestamp",
+            "$bytes",
+            "$reference",
+            "$geopoint",
+            "$double",
+            "$$x",
+        ] {
+            let map = value(ValueType::MapValue(firestore::MapValue {
+                fields: HashMap::from([(
+                    key.to_string(),
+                    value(ValueType::StringValue("NaN".to_string())),
+                )]),
+            }));
+            let json = options.value_to_json(map.clone()).unwrap();
+            assert_eq!(
+                json.as_object().unwrap().keys().next().unwrap(),
+                &format!("${key}")
+            );
+            assert_eq!(options.json_to_value(json).unwrap(), map);
+        }
+    }
+
+    #[test]
+    fn default_conversion_keeps_update_time() {
+        let json = Value::convert_doc(document()).unwrap();
+        assert_eq!(json["update_time"], serde_json::json!(1_650_000_000_002i64));
+
+        let doc = json.into_document().unwrap();
+        assert_eq!(doc.create_time, document().create_time);
+        assert_eq!(doc.update_time, document().update_time);
+    }
+}
@@ -5,6 +5,11 @@ use std::fmt::{Debug, Display};
 pub mod json;
 pub use json::*;
 
+mod primitives;
+
+pub mod rest;
+pub use rest::*;
+
 use crate::FirestoreConversionError;
 
 pub trait FromFirestoreDocument: Sized {
@@ -49,6 +54,21 @@ impl IntoFirestoreDocumentValue for firestore::Value {
     }
 }
 
+/// Conversions that can fail, e.g. for `u64` values beyond `i64::MAX`, which
+/// Firestore integers cannot hold.
+pub trait TryIntoFirestoreDocumentValue {
+    fn try_into_document_value(self) -> Result<firestore::Value, FirestoreConversionError>;
+}
+
+impl<T> TryIntoFirestoreDocumentValue for T
+where
+    T: IntoFirestoreDocumentValue,
+{
+    fn try_into_document_value(self) -> Result<firestore::Value, FirestoreConversionError> {
+        Ok(self.into_document_value())
+    }
+}
+
 impl<T> IntoFirestoreDocumentValue for Vec<T>
 where
     T: IntoFirestoreDocumentValue,
@@ -143,13 +163,14 @@ impl IntoFirestoreDocumentValue for f64 {
 }
 
 impl IntoFirestoreDocumentValue for DateTime<Utc> {
+    /// Timestamps are stored with millisecond precision.
     fn into_document_value(self) -> firestore::Value {
-        let time = std::time::UNIX_EPOCH
-            + std::time::Duration::from_millis(self.timestamp_millis().try_into().unwrap());
-        firestore_grpc::v1::Value {
-            value_type: Some(firestore_grpc::v1::value::ValueType::TimestampValue(
-                time.into(),
-            )),
+        let millis = self.timestamp_millis();
+        firestore::Value {
+            value_type: Some(ValueType::TimestampValue(prost_types::Timestamp {
+                seconds: millis.div_euclid(1000),
+                nanos: (millis.rem_euclid(1000) * 1_000_000) as i32,
+            })),
         }
     }
 }
@@ -0,0 +1,455 @@
+//! Conversions between Firestore values and std / chrono types.
+//!
+//! `Vec<u8>` is stored as bytes, which is why `u8` itself has no conversion.
+//! Tuples are stored as arrays.
+
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+use firestore_grpc::v1::{self as firestore, value::ValueType};
+use prost_types::Timestamp;
+use std::collections::{BTreeMap, HashMap};
+use std::time::SystemTime;
+
+use super::{FromFirestoreValue, IntoFirestoreDocumentValue, TryIntoFirestoreDocumentValue};
+use crate::firestore::values::value_type_name;
+use crate::FirestoreConversionError;
+
+fn value(value_type: ValueType) -> firestore::Value {
+    firestore::Value {
+        value_type: Some(value_type),
+    }
+}
+
+fn unexpected(expected: &'static str, val: &firestore::Value) -> FirestoreConversionError {
+    FirestoreConversionError::UnexpectedValueType {
+        expected,
+        actual: value_type_name(val),
+    }
+}
+
+fn out_of_range(value: impl ToString, target: &'static str) -> FirestoreConversionError {
+    FirestoreConversionError::OutOfRange {
+        value: value.to_string(),
+        target,
+    }
+}
+
+fn timestamp(val: firestore::Value) -> Result<Timestamp, FirestoreConversionError> {
+    match val.value_type {
+        Some(ValueType::TimestampValue(ts)) => Ok(ts),
+        _ => Err(unexpected("timestamp", &val)),
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+impl FromFirestoreValue for firestore::Value {
+    type Err = FirestoreConversionError;
+
+    fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+        Ok(val)
+    }
+}
+
+impl IntoFirestoreDocumentValue for bool {
+    fn into_document_value(self) -> firestore::Value {
+        value(ValueType::BooleanValue(self))
+    }
+}
+
+impl FromFirestoreValue for bool {
+    type Err = FirestoreConversionError;
+
+    fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+        match val.value_type {
+            Some(ValueType::BooleanValue(b)) => Ok(b),
+            _ => Err(unexpected("boolean", &val)),
+        }
+    }
+}
+
+impl IntoFirestoreDocumentValue for &str {
+    fn into_document_value(self) -> firestore::Value {
+        value(ValueType::StringValue(self.to_string()))
+    }
+}
+
+impl FromFirestoreValue for String {
+    type Err = FirestoreConversionError;
+
+    fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+        match val.value_type {
+            Some(ValueType::StringValue(s)) => Ok(s),
+            _ => Err(unexpected("string", &val)),
+        }
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// numbers
+
+macro_rules! into_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl IntoFirestoreDocumentValue for $ty {
+                fn into_document_value(self) -> firestore::Value {
+                    value(ValueType::IntegerValue(self.into()))
+                }
+            }
+        )*
+    };
+}
+
+into_integer!(i8, i16, u16);
+
+/// Firestore integers are 64 bit signed and doubles cannot hold all larger
+/// values exactly, so values beyond `i64::MAX` are out of range.
+macro_rules! try_into_unsigned_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl TryIntoFirestoreDocumentValue for $ty {
+                fn try_into_document_value(self) -> Result<firestore::Value, FirestoreConversionError> {
+                    i64::try_from(self)
+                        .map(|i| value(ValueType::IntegerValue(i)))
+                        .map_err(|_| out_of_range(self, "Firestore integer"))
+                }
+            }
+        )*
+    };
+}
+
+try_into_unsigned_integer!(u64, usize);
+
+/// Integer values are range checked, doubles are rejected.
+macro_rules! from_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl FromFirestoreValue for $ty {
+                type Err = FirestoreConversionError;
+
+                fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+                    match val.value_type {
+                        Some(ValueType::IntegerValue(i)) => {
+                            <$ty>::try_from(i).map_err(|_| out_of_range(i, stringify!($ty)))
+                        }
+                        _ => Err(unexpected("integer", &val)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+from_integer!(i8, i16, i32, i64, u16, u32, u64, usize);
+
+impl IntoFirestoreDocumentValue for f32 {
+    fn into_document_value(self) -> firestore::Value {
+        value(ValueType::DoubleValue(self.into()))
+    }
+}
+
+impl FromFirestoreValue for f64 {
+    type Err = FirestoreConversionError;
+
+    fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+        match val.value_type {
+            Some(ValueType::DoubleValue(d)) => Ok(d),
+            Some(ValueType::IntegerValue(i)) => Ok(i as f64),
+            _ => Err(unexpected("double", &val)),
+        }
+    }
+}
+
+impl FromFirestoreValue for f32 {
+    type Err = FirestoreConversionError;
+
+    fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+        f64::convert(val).map(|d| d as f32)
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// time
+
+impl IntoFirestoreDocumentValue for Timestamp {
+    fn into_document_value(self) -> firestore::Value {
+        value(ValueType::TimestampValue(self))
+    }
+}
+
+impl FromFirestoreValue for Timestamp {
+    type Err = FirestoreConversionError;
+
+    fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+        timestamp(val)
+    }
+}
+
+impl IntoFirestoreDocumentValue for SystemTime {
+    fn into_document_value(self) -> firestore::Value {
+        value(ValueType::TimestampValue(self.into()))
+    }
+}
+
+impl FromFirestoreValue for SystemTime {
+    type Err = FirestoreConversionError;
+
+    fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+        let ts = timestamp(val)?;
+        SystemTime::try_from(ts).map_err(|ts| out_of_range(format!("{:?}", ts), "SystemTime"))
+    }
+}
+
+impl FromFirestoreValue for DateTime<Utc> {
+    type Err = FirestoreConversionError;
+
+    fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+        let ts = timestamp(val)?;
+        u32::try_from(ts.nanos)
+            .ok()
+            .and_then(|nanos| Utc.timestamp_opt(ts.seconds, nanos).single())
+            .ok_or_else(|| out_of_range(format!("{:?}", ts), "DateTime"))
+    }
+}
+
+impl IntoFirestoreDocumentValue for DateTime<FixedOffset> {
+    fn into_document_value(self) -> firestore::Value {
+        self.with_timezone(&Utc).into_document_value()
+    }
+}
+
+impl FromFirestoreValue for DateTime<FixedOffset> {
+    type Err = FirestoreConversionError;
+
+    fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+        DateTime::<Utc>::convert(val).map(|time| time.fixed_offset())
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// containers
+
+impl IntoFirestoreDocumentValue for Vec<u8> {
+    fn into_document_value(self) -> firestore::Value {
+        value(ValueType::BytesValue(self))
+    }
+}
+
+impl FromFirestoreValue for Vec<u8> {
+    type Err = FirestoreConversionError;
+
+    fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+        match val.value_type {
+            Some(ValueType::BytesValue(bytes)) => Ok(bytes),
+            _ => Err(unexpected("bytes", &val)),
+        }
+    }
+}
+
+/// `null` and missing values are `None`.
+impl<T> FromFirestoreValue for Option<T>
+where
+    T: FromFirestoreValue<Err = FirestoreConversionError>,
+{
+    type Err = FirestoreConversionError;
+
+    fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+        match val.value_type {
+            None | Some(ValueType::NullValue(_)) => Ok(None),
+            _ => T::convert(val).map(Some),
+        }
+    }
+}
+
+impl<T> FromFirestoreValue for Vec<T>
+where
+    T: FromFirestoreValue<Err = FirestoreConversionError>,
+{
+    type Err = FirestoreConversionError;
+
+    fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+        match val.value_type {
+            Some(ValueType::ArrayValue(array)) => {
+                array.values.into_iter().map(T::convert).collect()
+            }
+            _ => Err(unexpected("array", &val)),
+        }
+    }
+}
+
+fn map_value<K, V>(fields: impl Iterator<Item = (K, V)>) -> firestore::Value
+where
+    K: ToString,
+    V: IntoFirestoreDocumentValue,
+{
+    value(ValueType::MapValue(firestore::MapValue {
+        fields: fields
+            .map(|(key, val)| (key.to_string(), val.into_document_value()))
+            .collect(),
+    }))
+}
+
+fn map_fields(
+    val: firestore::Value,
+) -> Result<HashMap<String, firestore::Value>, FirestoreConversionError> {
+    match val.value_type {
+        Some(ValueType::MapValue(map)) => Ok(map.fields),
+        _ => Err(unexpected("map", &val)),
+    }
+}
+
+impl<K, V> IntoFirestoreDocumentValue for HashMap<K, V>
+where
+    K: ToString,
+    V: IntoFirestoreDocumentValue,
+{
+    fn into_document_value(self) -> firestore::Value {
+        map_value(self.into_iter())
+    }
+}
+
+impl<V> FromFirestoreValue for HashMap<String, V>
+where
+    V: FromFirestoreValue<Err = FirestoreConversionError>,
+{
+    type Err = FirestoreConversionError;
+
+    fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+        map_fields(val)?
+            .into_iter()
+            .map(|(key, val)| Ok((key, V::convert(val)?)))
+            .collect()
+    }
+}
+
+impl<K, V> IntoFirestoreDocumentValue for BTreeMap<K, V>
+where
+    K: ToString,
+    V: IntoFirestoreDocumentValue,
+{
+    fn into_document_value(self) -> firestore::Value {
+        map_value(self.into_iter())
+    }
+}
+
+impl<V> FromFirestoreValue for BTreeMap<String, V>
+where
+    V: FromFirestoreValue<Err = FirestoreConversionError>,
+{
+    type Err = FirestoreConversionError;
+
+    fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+        map_fields(val)?
+            .into_iter()
+            .map(|(key, val)| Ok((key, V::convert(val)?)))
+            .collect()
+    }
+}
+
+macro_rules! tuple_conversions {
+    ($len:literal => $($name:ident $idx:tt),+) => {
+        impl<$($name),+> IntoFirestoreDocumentValue for ($($name,)+)
+        where
+            $($name: IntoFirestoreDocumentValue),+
+        {
+            fn into_document_value(self) -> firestore::Value {
+                value(ValueType::ArrayValue(firestore::ArrayValue {
+                    values: vec![$(self.$idx.into_document_value()),+],
+                }))
+            }
+        }
+
+        impl<$($name),+> FromFirestoreValue for ($($name,)+)
+        where
+            $($name: FromFirestoreValue<Err = FirestoreConversionError>),+
+        {
+            type Err = FirestoreConversionError;
+
+            fn convert(val: firestore::Value) -> Result<Self, Self::Err> {
+                let values = match val.value_type {
+                    Some(ValueType::ArrayValue(array)) => array.values,
+                    _ => return Err(unexpected("array", &val)),
+                };
+                if values.len() != $len {
+                    return Err(FirestoreConversionError::FromFirestoreError(format!(
+                        "expected an array of {} values, got {}",
+                        $len,
+                        values.len()
+                    )));
+                }
+                let mut values = values.into_iter();
+                Ok(($($name::convert(values.next().unwrap_or_default())?,)+))
+            }
+        }
+    };
+}
+
+tuple_conversions!(1 => A 0);
+tuple_conversions!(2 => A 0, B 1);
+tuple_conversions!(3 => A 0, B 1, C 2);
+tuple_conversions!(4 => A 0, B 1, C 2, D 3);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firestore::structured_query::{field_filter, FieldFilterOperator};
+    use pretty_assertions::assert_eq;
+
+    fn round_trip<T>(val: T) -> T
+    where
+        T: IntoFirestoreDocumentValue + FromFirestoreValue<Err = FirestoreConversionError>,
+    {
+        T::convert(val.into_document_value()).unwrap()
+    }
+
+    #[test]
+    fn converts_std_types_both_ways() {
+        assert!(round_trip(true));
+        assert_eq!(round_trip(-3i8), -3);
+        assert_eq!(round_trip(1.5f32), 1.5);
+        assert_eq!(round_trip(vec![1u8, 2, 3]), vec![1, 2, 3]);
+        assert_eq!(round_trip(Some("a".to_string())), Some("a".to_string()));
+        assert_eq!(round_trip(None::<i64>), None);
+        assert_eq!(round_trip((1i64, "a".to_string())), (1, "a".to_string()));
+
+        let map = BTreeMap::from([("a".to_string(), vec![1i64, 2])]);
+        assert_eq!(round_trip(map.clone()), map);
+
+        let time = Utc.timestamp_opt(-1, 5_000_000).unwrap();
+        assert_eq!(round_trip(time), time);
+
+        // filters accept the new types directly
+        field_filter("active", FieldFilterOperator::Equal, true);
+        field_filter("name", FieldFilterOperator::Equal, "a");
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let err = u64::convert((-1i64).into_document_value()).unwrap_err();
+        assert_eq!(err.to_string(), "Value -1 does not fit into u64");
+
+        let err = i8::convert(300i64.into_document_value()).unwrap_err();
+        assert_eq!(err.to_string(), "Value 300 does not fit into i8");
+
+        let err = bool::convert("yes".into_document_value()).unwrap_err();
+        assert_eq!(err.to_string(), "Expected boolean value, got string value");
+
+        assert!(<(i64, i64)>::convert(vec![1i64].into_document_value()).is_err());
+    }
+
+    #[test]
+    fn checks_the_range_of_unsigned_integers() {
+        let val = (i64::MAX as u64).try_into_document_value().unwrap();
+        assert_eq!(u64::convert(val).unwrap(), i64::MAX as u64);
+        assert_eq!(
+            u64::convert(7usize.try_into_document_value().unwrap()).unwrap(),
+            7
+        );
+
+        let err = u64::MAX.try_into_document_value().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Value 18446744073709551615 does not fit into Firestore integer"
+        );
+        assert!(true.try_into_document_value().is_ok());
+    }
+}
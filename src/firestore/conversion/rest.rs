@@ -0,0 +1,325 @@
+//! The typed JSON encoding the Firestore REST API uses, e.g.
+//! `{"fields": {"a": {"stringValue": "x"}, "n": {"integerValue": "3"}}}`.
+//! Unlike the untyped mapping of [`super::json`] the conversion is exact for
+//! every value type.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use firestore_grpc::v1::{self as firestore, value::ValueType};
+use prost_types::Timestamp;
+use serde_json::{json, Map, Number, Value};
+use std::collections::HashMap;
+
+use crate::FirestoreConversionError;
+
+use super::{FromFirestoreDocument, IntoFirestoreDocument};
+
+/// A document in the REST encoding, e.g. a REST response, a `curl` dump or a
+/// Cloud Functions payload. Pass it to
+/// [`UpdateDocumentOptions::document`](crate::firestore::client::UpdateDocumentOptions::document)
+/// to write it or get it from a fetch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestDocument(pub Value);
+
+impl IntoFirestoreDocument for RestDocument {
+    type Err = FirestoreConversionError;
+
+    /// Only the fields are used, `name`, `createTime` and `updateTime` are
+    /// ignored.
+    fn into_document_from_fields(self) -> Result<firestore::Document, Self::Err> {
+        let fields = document_from_rest_json(self.0)?.fields;
+        Ok(firestore::Document {
+            fields,
+            ..Default::default()
+        })
+    }
+
+    fn into_document(self) -> Result<firestore::Document, Self::Err> {
+        document_from_rest_json(self.0)
+    }
+}
+
+impl FromFirestoreDocument for RestDocument {
+    type Err = FirestoreConversionError;
+
+    fn convert_doc(doc: firestore::Document) -> Result<Self, Self::Err> {
+        Ok(RestDocument(document_to_rest_json(&doc)?))
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// firestore -> REST
+
+pub fn document_to_rest_json(doc: &firestore::Document) -> Result<Value, FirestoreConversionError> {
+    let mut obj = Map::new();
+    obj.insert("name".to_string(), Value::String(doc.name.clone()));
+    obj.insert("fields".to_string(), fields_to_rest_json(&doc.fields)?);
+    if let Some(time) = &doc.create_time {
+        obj.insert("createTime".to_string(), timestamp_to_rest_json(time)?);
+    }
+    if let Some(time) = &doc.update_time {
+        obj.insert("updateTime".to_string(), timestamp_to_rest_json(time)?);
+    }
+    Ok(Value::Object(obj))
+}
+
+pub(crate) fn fields_to_rest_json(
+    fields: &HashMap<String, firestore::Value>,
+) -> Result<Value, FirestoreConversionError> {
+    fields
+        .iter()
+        .map(|(key, val)| Ok((key.clone(), value_to_rest_json(val)?)))
+        .collect::<Result<_, _>>()
+        .map(Value::Object)
+}
+
+pub(crate) fn values_to_rest_json(
+    values: &[firestore::Value],
+) -> Result<Vec<Value>, FirestoreConversionError> {
+    values.iter().map(value_to_rest_json).collect()
+}
+
+pub fn value_to_rest_json(val: &firestore::Value) -> Result<Value, FirestoreConversionError> {
+    Ok(match &val.value_type {
+        None | Some(ValueType::NullValue(_)) => json!({ "nullValue": null }),
+        Some(ValueType::BooleanValue(b)) => json!({ "booleanValue": b }),
+        // int64 is a string in proto3 JSON
+        Some(ValueType::IntegerValue(i)) => json!({ "integerValue": i.to_string() }),
+        Some(ValueType::DoubleValue(d)) => json!({ "doubleValue": double_to_rest_json(*d) }),
+        Some(ValueType::TimestampValue(ts)) => {
+            json!({ "timestampValue": timestamp_to_rest_json(ts)? })
+        }
+        Some(ValueType::StringValue(s)) => json!({ "stringValue": s }),
+        Some(ValueType::BytesValue(bytes)) => json!({ "bytesValue": BASE64.encode(bytes) }),
+        Some(ValueType::ReferenceValue(reference)) => json!({ "referenceValue": reference }),
+        Some(ValueType::GeoPointValue(point)) => json!({
+            "geoPointValue": {
+                "latitude": double_to_rest_json(point.latitude),
+                "longitude": double_to_rest_json(point.longitude),
+            }
+        }),
+        Some(ValueType::ArrayValue(array)) => json!({
+            "arrayValue": { "values": values_to_rest_json(&array.values)? }
+        }),
+        Some(ValueType::MapValue(map)) => json!({
+            "mapValue": { "fields": fields_to_rest_json(&map.fields)? }
+        }),
+    })
+}
+
+fn double_to_rest_json(d: f64) -> Value {
+    match Number::from_f64(d) {
+        Some(n) => Value::Number(n),
+        None if d.is_nan() => Value::String("NaN".to_string()),
+        None if d > 0.0 => Value::String("Infinity".to_string()),
+        None => Value::String("-Infinity".to_string()),
+    }
+}
+
+pub(crate) fn timestamp_to_rest_json(ts: &Timestamp) -> Result<Value, FirestoreConversionError> {
+    let time = u32::try_from(ts.nanos)
+        .ok()
+        .and_then(|nanos| Utc.timestamp_opt(ts.seconds, nanos).single())
+        .ok_or_else(|| FirestoreConversionError::OutOfRange {
+            value: format!("{}s {}ns", ts.seconds, ts.nanos),
+            target: "an RFC 3339 timestamp",
+        })?;
+    Ok(Value::String(
+        time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+    ))
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// REST -> firestore
+
+fn invalid(what: &str, val: &Value) -> FirestoreConversionError {
+    FirestoreConversionError::IntoFirestoreError(format!("invalid REST {}: {}", what, val))
+}
+
+/// A missing `name` becomes `""`, e.g. for request bodies.
+pub fn document_from_rest_json(
+    json: Value,
+) -> Result<firestore::Document, FirestoreConversionError> {
+    let obj = json.as_object().ok_or_else(|| invalid("document", &json))?;
+    let name = match obj.get("name") {
+        None => String::new(),
+        Some(Value::String(name)) => name.clone(),
+        Some(val) => return Err(invalid("document name", val)),
+    };
+    let fields = match obj.get("fields") {
+        None => HashMap::new(),
+        Some(fields) => fields_from_rest_json(fields)?,
+    };
+    let time = |key: &str| obj.get(key).map(timestamp_from_rest_json).transpose();
+
+    Ok(firestore::Document {
+        name,
+        fields,
+        create_time: time("createTime")?,
+        update_time: time("updateTime")?,
+    })
+}
+
+fn fields_from_rest_json(
+    fields: &Value,
+) -> Result<HashMap<String, firestore::Value>, FirestoreConversionError> {
+    fields
+        .as_object()
+        .ok_or_else(|| invalid("fields", fields))?
+        .iter()
+        .map(|(key, val)| Ok((key.clone(), value_from_rest_json(val)?)))
+        .collect()
+}
+
+pub fn value_from_rest_json(json: &Value) -> Result<firestore::Value, FirestoreConversionError> {
+    let (key, val) = match json.as_object() {
+        Some(obj) if obj.len() == 1 => obj.iter().next().expect("one entry"),
+        _ => return Err(invalid("value", json)),
+    };
+
+    let value_type = match key.as_str() {
+        "nullValue" => ValueType::NullValue(0),
+        "booleanValue" => ValueType::BooleanValue(val.as_bool().ok_or_else(|| invalid(key, val))?),
+        "integerValue" => ValueType::IntegerValue(match val {
+            Value::String(s) => s.parse().map_err(|_| invalid(key, val))?,
+            _ => val.as_i64().ok_or_else(|| invalid(key, val))?,
+        }),
+        "doubleValue" => ValueType::DoubleValue(double_from_rest_json(val)?),
+        "timestampValue" => ValueType::TimestampValue(timestamp_from_rest_json(val)?),
+        "stringValue" => {
+            ValueType::StringValue(val.as_str().ok_or_else(|| invalid(key, val))?.to_string())
+        }
+        "bytesValue" => ValueType::BytesValue(
+            val.as_str()
+                .and_then(|s| BASE64.decode(s).ok())
+                .ok_or_else(|| invalid(key, val))?,
+        ),
+        "referenceValue" => {
+            ValueType::ReferenceValue(val.as_str().ok_or_else(|| invalid(key, val))?.to_string())
+        }
+        "geoPointValue" => ValueType::GeoPointValue(firestore_grpc::google::r#type::LatLng {
+            // omitted when 0
+            latitude: val
+                .get("latitude")
+                .map(double_from_rest_json)
+                .transpose()?
+                .unwrap_or_default(),
+            longitude: val
+                .get("longitude")
+                .map(double_from_rest_json)
+                .transpose()?
+                .unwrap_or_default(),
+        }),
+        "arrayValue" => ValueType::ArrayValue(firestore::ArrayValue {
+            // omitted when empty
+            values: match val.get("values") {
+                None => Vec::new(),
+                Some(Value::Array(values)) => values
+                    .iter()
+                    .map(value_from_rest_json)
+                    .collect::<Result<_, _>>()?,
+                Some(values) => return Err(invalid("array values", values)),
+            },
+        }),
+        "mapValue" => ValueType::MapValue(firestore::MapValue {
+            fields: match val.get("fields") {
+                None => HashMap::new(),
+                Some(fields) => fields_from_rest_json(fields)?,
+            },
+        }),
+        _ => return Err(invalid("value", json)),
+    };
+
+    Ok(firestore::Value {
+        value_type: Some(value_type),
+    })
+}
+
+fn double_from_rest_json(val: &Value) -> Result<f64, FirestoreConversionError> {
+    match val {
+        Value::Number(n) => n.as_f64().ok_or_else(|| invalid("double", val)),
+        Value::String(s) => match s.as_str() {
+            "NaN" => Ok(f64::NAN),
+            "Infinity" => Ok(f64::INFINITY),
+            "-Infinity" => Ok(f64::NEG_INFINITY),
+            _ => s.parse().map_err(|_| invalid("double", val)),
+        },
+        _ => Err(invalid("double", val)),
+    }
+}
+
+pub(crate) fn timestamp_from_rest_json(val: &Value) -> Result<Timestamp, FirestoreConversionError> {
+    let time = val
+        .as_str()
+        .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+        .ok_or_else(|| invalid("timestamp", val))?;
+    Ok(Timestamp {
+        seconds: time.timestamp(),
+        nanos: time.timestamp_subsec_nanos() as i32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn round_trips_rest_documents() {
+        let json = json!({
+            "name": "projects/p/databases/(default)/documents/items/a",
+            "fields": {
+                "a": { "stringValue": "x" },
+                "n": { "integerValue": "9007199254740993" },
+                "d": { "doubleValue": "-Infinity" },
+                "t": { "timestampValue": "2023-11-14T22:13:20.123456Z" },
+                "b": { "bytesValue": "AJ//" },
+                "r": { "referenceValue": "projects/p/databases/(default)/documents/items/b" },
+                "g": { "geoPointValue": { "latitude": 52.5, "longitude": 13.4 } },
+                "none": { "nullValue": null },
+                "list": { "arrayValue": { "values": [{ "booleanValue": true }] } },
+                "map": { "mapValue": { "fields": { "x": { "integerValue": "1" } } } },
+            },
+            "createTime": "2023-11-14T22:13:20Z",
+            "updateTime": "2023-11-14T22:13:21.500Z",
+        });
+
+        let doc = document_from_rest_json(json.clone()).unwrap();
+        assert_eq!(
+            doc.fields["n"].value_type,
+            Some(ValueType::IntegerValue(9007199254740993))
+        );
+        assert_eq!(
+            doc.fields["b"].value_type,
+            Some(ValueType::BytesValue(vec![0, 159, 255]))
+        );
+        assert_eq!(document_to_rest_json(&doc).unwrap(), json);
+    }
+
+    #[test]
+    fn rejects_out_of_range_timestamps() {
+        let val = firestore::Value {
+            value_type: Some(ValueType::TimestampValue(Timestamp {
+                seconds: i64::MAX,
+                nanos: 0,
+            })),
+        };
+        let err = value_to_rest_json(&val).unwrap_err();
+        assert!(matches!(err, FirestoreConversionError::OutOfRange { .. }));
+    }
+
+    #[test]
+    fn accepts_omitted_defaults() {
+        let doc = RestDocument(json!({
+            "fields": { "list": { "arrayValue": {} }, "map": { "mapValue": {} } }
+        }))
+        .into_document_from_fields()
+        .unwrap();
+        assert_eq!(
+            doc.fields["list"].value_type,
+            Some(ValueType::ArrayValue(Default::default()))
+        );
+
+        assert!(value_from_rest_json(&json!({ "integerValue": "x" })).is_err());
+    }
+}
@@ -1,12 +1,34 @@
+use firestore_grpc::tonic::Code;
+use firestore_grpc::v1::{
+    self as firestore,
+    document_transform::{
+        field_transform::{ServerValue, TransformType},
+        FieldTransform,
+    },
+    precondition::ConditionType,
+    write::Operation,
+    CommitRequest, DocumentMask, Precondition,
+};
+use std::time::Duration;
+
+use crate::timeout::run_with_timeout;
 use crate::FirestoreError;
 
-use super::{FirebaseClient, FromFirestoreDocument};
+use super::values::diff_documents;
+use super::{
+    FieldPath, FirebaseClient, FromFirestoreDocument, IntoFieldPath, IntoFirestoreDocument,
+};
+
+const MAX_ATTEMPTS: usize = 5;
 
+/// Read-modify-write of a single document. See [`FetchAndUpdate::modify`].
 pub struct FetchAndUpdate<'a> {
     col_name: &'a str,
     id: &'a str,
     client: &'a FirebaseClient,
-    add_updated_timestamp: Option<String>,
+    add_updated_timestamp: Option<FieldPath>,
+    max_attempts: usize,
+    timeout: Option<Duration>,
 }
 
 impl<'a> FetchAndUpdate<'a> {
@@ -16,55 +38,264 @@ impl<'a> FetchAndUpdate<'a> {
             id,
             client,
             add_updated_timestamp: None,
+            max_attempts: MAX_ATTEMPTS,
+            timeout: None,
         }
     }
 
+    /// Sets the field to the server time of the write.
+    #[must_use]
+    pub fn add_updated_timestamp(mut self, field: impl IntoFieldPath) -> Self {
+        self.add_updated_timestamp = Some(field.into_field_path());
+        self
+    }
+
+    /// How often [`Self::modify`] runs when the document is changed
+    /// concurrently, 5 by default.
     #[must_use]
-    pub fn add_updated_timestamp(mut self, add_updated_timestamp: impl ToString) -> Self {
-        self.add_updated_timestamp = Some(add_updated_timestamp.to_string());
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts.max(1);
         self
     }
 
-    pub async fn modify<T>(&self, modify_fn: impl FnOnce(&mut T)) -> Result<(), FirestoreError>
+    /// Overrides the timeout of the client for the fetches and writes.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    fn timeout_or_default(&self) -> Option<Duration> {
+        self.timeout.or(self.client.timeout)
+    }
+
+    fn document_name(&self) -> String {
+        format!(
+            "{}/{}/{}",
+            self.client.documents_path(),
+            self.col_name,
+            self.id
+        )
+    }
+
+    /// Fetches the document, runs `modify_fn` and writes the fields it
+    /// changed. The write is conditional on the document not having changed
+    /// since it was fetched. On a conflict the document is fetched again and
+    /// `modify_fn` is rerun, up to [`Self::max_attempts`] times, after which
+    /// the conflict error is returned.
+    pub async fn modify<T>(&self, mut modify_fn: impl FnMut(&mut T)) -> Result<(), FirestoreError>
     where
         T: FromFirestoreDocument + serde::Serialize,
         T::Err: Into<FirestoreError>,
     {
-        let mut doc = self
-            .client
-            .get_document_of_collection(self.col_name, self.id)
-            .await?;
-        modify_fn(&mut doc);
-        self.store(doc).await
+        let name = self.document_name();
+        let mut attempt = 1;
+        loop {
+            let mut get = self
+                .client
+                .get_document(format!("{}/{}", self.col_name, self.id));
+            get.timeout = self.timeout_or_default();
+            let doc = get.fetch().await?;
+            let update_time = doc.update_time.clone();
+
+            let mut value = T::convert_doc(doc).map_err(Into::into)?;
+            // diff the serialized forms so that fields unknown to T are kept
+            let before = serialize(&value)?;
+            modify_fn(&mut value);
+            let after = serialize(&value)?;
+
+            let mut paths = diff_documents(&before, &after)
+                .paths()
+                .into_iter()
+                .map(|path| FieldPath::parse(&path))
+                .filter(|path| !self.is_timestamp_field(path))
+                .map(|path| path.to_string())
+                .collect::<Vec<_>>();
+            if paths.is_empty() {
+                tracing::debug!("fetch_and_update {}: nothing changed", name);
+                return Ok(());
+            }
+            paths.dedup();
+
+            let write = firestore::Write {
+                operation: Some(Operation::Update(firestore::Document {
+                    name: name.clone(),
+                    ..after
+                })),
+                update_mask: Some(DocumentMask { field_paths: paths }),
+                update_transforms: self.update_transforms(),
+                current_document: update_time.map(|update_time| Precondition {
+                    condition_type: Some(ConditionType::UpdateTime(update_time)),
+                }),
+            };
+
+            match self.commit(write).await {
+                Err(FirestoreError::GrpcError(status))
+                    if is_conflict(status.code()) && attempt < self.max_attempts =>
+                {
+                    tracing::debug!(
+                        "fetch_and_update {}: conflict on attempt {}: {}",
+                        name,
+                        attempt,
+                        status.message()
+                    );
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
     }
 
+    /// Overwrites the whole document with `doc`, unconditionally.
     pub async fn store<T>(&self, doc: T) -> Result<(), FirestoreError>
     where
         T: serde::Serialize,
     {
-        let Self {
-            col_name,
-            id,
-            client,
-            add_updated_timestamp,
-        } = self;
+        let mut doc = serialize(&doc)?;
+        if let Some(field) = &self.add_updated_timestamp {
+            // set by the transform
+            if let [key] = field.segments() {
+                doc.fields.remove(key);
+            }
+        }
 
-        let mut doc = serde_json::to_value(doc).map_err(|e| {
-            tracing::error!("unable to serialize transcription doc: {}", e);
-            e
-        })?;
+        let write = firestore::Write {
+            operation: Some(Operation::Update(firestore::Document {
+                name: self.document_name(),
+                ..doc
+            })),
+            update_transforms: self.update_transforms(),
+            ..Default::default()
+        };
+        self.commit(write).await
+    }
 
-        if let Some(add_updated_timestamp) = add_updated_timestamp {
-            // FIXME better have a trait for this with T?
-            doc[add_updated_timestamp] = chrono::Utc::now().to_rfc3339().into();
+    fn is_timestamp_field(&self, path: &FieldPath) -> bool {
+        match &self.add_updated_timestamp {
+            Some(field) => path.segments().starts_with(field.segments()),
+            None => false,
         }
+    }
 
-        client
-            .update_document(&format!("{col_name}/{id}"))
-            .document(doc)
-            .update()
-            .await?;
+    fn update_transforms(&self) -> Vec<FieldTransform> {
+        self.add_updated_timestamp
+            .iter()
+            .map(|field| FieldTransform {
+                field_path: field.to_string(),
+                transform_type: Some(TransformType::SetToServerValue(
+                    ServerValue::RequestTime as i32,
+                )),
+            })
+            .collect()
+    }
 
+    async fn commit(&self, write: firestore::Write) -> Result<(), FirestoreError> {
+        let req = CommitRequest {
+            database: format!("projects/{}/databases/(default)", self.client.project_id),
+            writes: vec![write],
+            transaction: Vec::new(),
+        };
+        run_with_timeout(self.timeout_or_default(), self.client.backend.commit(req)).await?;
         Ok(())
     }
 }
+
+fn serialize<T: serde::Serialize>(value: &T) -> Result<firestore::Document, FirestoreError> {
+    let json = serde_json::to_value(value).map_err(|e| {
+        tracing::error!("unable to serialize document: {}", e);
+        e
+    })?;
+    Ok(json.into_document_from_fields()?)
+}
+
+/// A failed update_time precondition or a contended write.
+fn is_conflict(code: Code) -> bool {
+    matches!(code, Code::FailedPrecondition | Code::Aborted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firestore::memory::InMemoryFirestore;
+    use crate::firestore::{DocumentExt, IntoFirestoreDocumentValue};
+    use pretty_assertions::assert_eq;
+    use serde::Serialize;
+    use std::collections::HashMap;
+
+    #[derive(Debug, Serialize)]
+    struct Counter {
+        count: i64,
+        label: String,
+    }
+
+    impl FromFirestoreDocument for Counter {
+        type Err = FirestoreError;
+
+        fn convert_doc(doc: firestore::Document) -> Result<Self, Self::Err> {
+            Ok(Counter {
+                count: doc.get_path_as("count")?,
+                label: doc.get_path_as("label")?,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_on_concurrent_writes_and_writes_changed_fields() {
+        let db = InMemoryFirestore::new();
+        let client = FirebaseClient::with_backend(db.clone());
+        let doc = client
+            .update_document("counters/a")
+            .field("count", 1)
+            .field("label", "a")
+            .field("other", true)
+            .update()
+            .await
+            .unwrap();
+
+        let mut calls = 0;
+        client
+            .fetch_and_update("counters", "a")
+            .add_updated_timestamp("updated")
+            .modify(|counter: &mut Counter| {
+                calls += 1;
+                if calls == 1 {
+                    // a concurrent writer between fetch and commit
+                    let mut concurrent = doc.clone();
+                    concurrent
+                        .fields
+                        .insert("count".to_string(), 10.into_document_value());
+                    db.insert(concurrent);
+                }
+                counter.count += 1;
+            })
+            .await
+            .unwrap();
+        assert_eq!(calls, 2);
+
+        let doc = client.get_document("counters/a").fetch().await.unwrap();
+        let mut keys = doc.fields.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec!["count", "label", "other", "updated"]);
+        assert_eq!(doc.get_path_as::<i64>("count").unwrap(), 11);
+        assert!(matches!(
+            doc.fields["updated"].value_type,
+            Some(firestore::value::ValueType::TimestampValue(_))
+        ));
+
+        let err = client
+            .fetch_and_update("counters", "a")
+            .max_attempts(1)
+            .modify(|counter: &mut Counter| {
+                let mut concurrent = db.document(&doc.name).unwrap();
+                concurrent.fields = HashMap::new();
+                db.insert(concurrent);
+                counter.label = "b".to_string();
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            FirestoreError::GrpcError(status) if status.code() == Code::FailedPrecondition
+        ));
+    }
+}
@@ -0,0 +1,157 @@
+//! Field paths like ``address.`zip-code` `` that address (nested) fields of
+//! documents.
+
+use firestore_grpc::v1::{self as firestore, value::ValueType};
+use std::fmt;
+
+use super::conversion::FromFirestoreValue;
+use super::values::{join_field_path, split_field_path};
+
+/// The path to a field, made of map keys. Segments are quoted when the path is
+/// formatted, so a key like `"user.name"` stays a single segment:
+///
+/// ```
+/// # use firebase_client::firestore::FieldPath;
+/// let path = FieldPath::new(["users", "user.name"]);
+/// assert_eq!(path.to_string(), "users.`user.name`");
+/// assert_eq!(FieldPath::parse("users.`user.name`"), path);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FieldPath {
+    segments: Vec<String>,
+}
+
+impl FieldPath {
+    pub fn new<I, S>(segments: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            segments: segments.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Parses the dotted syntax, segments can be quoted with backticks.
+    pub fn parse(path: &str) -> Self {
+        Self {
+            segments: split_field_path(path),
+        }
+    }
+
+    /// The special path `__name__` that refers to the document name.
+    pub fn document_id() -> Self {
+        Self::new(["__name__"])
+    }
+
+    pub fn segments(&self) -> &[String] {
+        &self.segments
+    }
+
+    #[must_use]
+    pub fn child<S: Into<String>>(mut self, segment: S) -> Self {
+        self.segments.push(segment.into());
+        self
+    }
+}
+
+impl fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let segments = self.segments.iter().map(String::as_str).collect::<Vec<_>>();
+        f.write_str(&join_field_path(&segments))
+    }
+}
+
+/// Accepted by all APIs that take a field. Strings are parsed with
+/// [`FieldPath::parse`], i.e. dots separate segments.
+pub trait IntoFieldPath {
+    fn into_field_path(self) -> FieldPath;
+}
+
+impl IntoFieldPath for FieldPath {
+    fn into_field_path(self) -> FieldPath {
+        self
+    }
+}
+
+impl IntoFieldPath for &FieldPath {
+    fn into_field_path(self) -> FieldPath {
+        self.clone()
+    }
+}
+
+impl IntoFieldPath for &str {
+    fn into_field_path(self) -> FieldPath {
+        FieldPath::parse(self)
+    }
+}
+
+impl IntoFieldPath for String {
+    fn into_field_path(self) -> FieldPath {
+        FieldPath::parse(&self)
+    }
+}
+
+impl IntoFieldPath for &String {
+    fn into_field_path(self) -> FieldPath {
+        FieldPath::parse(self)
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// Reads nested fields of documents.
+pub trait DocumentExt {
+    fn get_path(&self, path: impl IntoFieldPath) -> Option<&firestore::Value>;
+
+    /// Converts the value at `path`, a missing value converts like `null`.
+    fn get_path_as<T: FromFirestoreValue>(&self, path: impl IntoFieldPath) -> Result<T, T::Err> {
+        let value = self.get_path(path).cloned().unwrap_or_default();
+        T::convert(value)
+    }
+}
+
+impl DocumentExt for firestore::Document {
+    fn get_path(&self, path: impl IntoFieldPath) -> Option<&firestore::Value> {
+        let path = path.into_field_path();
+        let (first, rest) = path.segments().split_first()?;
+        let mut value = self.fields.get(first)?;
+        for segment in rest {
+            value = match &value.value_type {
+                Some(ValueType::MapValue(map)) => map.fields.get(segment)?,
+                _ => return None,
+            };
+        }
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firestore::IntoFirestoreDocumentValue;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+
+    #[test]
+    fn quotes_segments_that_are_not_identifiers() {
+        let path = FieldPath::new(["a", "b-c", "ünï", "d`e"]).child("_f1");
+        assert_eq!(path.to_string(), "a.`b-c`.`ünï`.`d\\`e`._f1");
+        assert_eq!(FieldPath::parse(&path.to_string()), path);
+        assert_eq!("a.b-c".into_field_path().to_string(), "a.`b-c`");
+    }
+
+    #[test]
+    fn reads_nested_fields() {
+        let user = HashMap::from([("user.name", "ada")]);
+        let doc = firestore::Document {
+            fields: HashMap::from([("users".to_string(), user.into_document_value())]),
+            ..Default::default()
+        };
+
+        let path = FieldPath::new(["users", "user.name"]);
+        assert_eq!(doc.get_path_as::<String>(&path).unwrap(), "ada");
+        assert_eq!(doc.get_path("users.user.name"), None);
+        assert_eq!(doc.get_path_as::<Option<i64>>("missing").unwrap(), None);
+    }
+}
@@ -0,0 +1,571 @@
+use firestore_grpc::tonic;
+use firestore_grpc::v1::{
+    self as firestore,
+    listen_response::ResponseType,
+    target::{DocumentsTarget, TargetType},
+    target_change::TargetChangeType,
+    ListenResponse,
+};
+
+use futures::{Stream, StreamExt};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicI32, Ordering},
+    Arc, Mutex,
+};
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+use super::backend::ListenResponseStream;
+use super::collection::CollectionUpdate;
+use super::streaming::{query_target, CollectionStream, CollectionStreamState, GRPC_TARGET_ID};
+use super::structured_query::StructuredQueryBuilder;
+use super::FirebaseClient;
+use crate::FirestoreError;
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+type UpdateSender = mpsc::UnboundedSender<TargetMessage>;
+type SessionTargets = Arc<Mutex<HashMap<i32, SessionTarget>>>;
+
+enum TargetMessage {
+    Update(Result<CollectionUpdate, FirestoreError>),
+    /// The target state is out of sync and the target needs to be re-added
+    /// without resume token.
+    Reset,
+}
+
+#[derive(Debug)]
+enum SessionMessage {
+    Request(Box<firestore::ListenRequest>),
+    Close,
+}
+
+struct SessionTarget {
+    target: firestore::Target,
+    state: CollectionStreamState,
+    updates: UpdateSender,
+}
+
+/// Describes what a [`ListenSession`] target listens to. Either a structured
+/// query or a fixed set of documents.
+#[derive(Debug, Clone)]
+pub struct ListenTarget {
+    target_type: ListenTargetType,
+    parent: Option<String>,
+    resume_token: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+enum ListenTargetType {
+    Query(StructuredQueryBuilder),
+    Documents(Vec<String>),
+}
+
+impl ListenTarget {
+    /// Listen to all documents of `collection_id`.
+    pub fn collection<S: ToString>(collection_id: S) -> Self {
+        Self::query(StructuredQueryBuilder::new().from(collection_id))
+    }
+
+    /// Listen to the results of `query`.
+    pub fn query(query: StructuredQueryBuilder) -> Self {
+        Self {
+            target_type: ListenTargetType::Query(query),
+            parent: None,
+            resume_token: None,
+        }
+    }
+
+    /// Listen to the documents with the given names (relative to the documents
+    /// root, e.g. `"users/alice"`).
+    pub fn documents<S: ToString>(names: &[S]) -> Self {
+        Self {
+            target_type: ListenTargetType::Documents(
+                names.iter().map(|name| name.to_string()).collect(),
+            ),
+            parent: None,
+            resume_token: None,
+        }
+    }
+
+    /// The parent document of a query target, relative to the documents root.
+    #[must_use]
+    pub fn parent<S: ToString>(mut self, parent: S) -> Self {
+        self.parent = Some(parent.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn resume_token(self, resume_token: Vec<u8>) -> Self {
+        self.resume_token_maybe(Some(resume_token))
+    }
+
+    #[must_use]
+    pub fn resume_token_maybe(mut self, resume_token: Option<Vec<u8>>) -> Self {
+        self.resume_token = resume_token;
+        self
+    }
+
+    fn into_target(self, target_id: i32, documents_path: &str) -> firestore::Target {
+        let Self {
+            target_type,
+            parent,
+            resume_token,
+        } = self;
+
+        match target_type {
+            ListenTargetType::Query(query) => {
+                let parent = match parent {
+                    Some(parent) if !parent.is_empty() => format!("{documents_path}/{parent}"),
+                    _ => documents_path.to_string(),
+                };
+                query_target(target_id, parent, query.build(), resume_token)
+            }
+
+            ListenTargetType::Documents(names) => firestore::Target {
+                target_id,
+                expected_count: None,
+                once: false,
+                target_type: Some(TargetType::Documents(DocumentsTarget {
+                    documents: names
+                        .into_iter()
+                        .map(|name| format!("{documents_path}/{name}"))
+                        .collect(),
+                })),
+                resume_type: resume_token.map(firestore::target::ResumeType::ResumeToken),
+            },
+        }
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// A single bidirectional `Listen` stream that multiplexes any number of
+/// targets. Targets can be added and removed while the session is running, each
+/// target gets its own [`CollectionStream`].
+///
+/// The underlying stream stays open until [`ListenSession::close`] is called or
+/// the session and all of its target streams are dropped.
+///
+/// ```ignore
+/// let session = client.listen_session().await?;
+/// let (_, mut users) = session.add_target(ListenTarget::collection("users"))?;
+/// let (_, mut rooms) = session.add_target(ListenTarget::collection("rooms"))?;
+/// ```
+#[derive(Clone)]
+pub struct ListenSession {
+    database: String,
+    documents_path: String,
+    requests: mpsc::UnboundedSender<SessionMessage>,
+    targets: SessionTargets,
+    next_target_id: Arc<AtomicI32>,
+}
+
+impl ListenSession {
+    pub(crate) async fn connect(client: &FirebaseClient) -> Result<Self, FirestoreError> {
+        let database = format!("projects/{}/databases/(default)", client.project_id);
+        let documents_path = format!("{database}/documents");
+        let (requests, mut requests_rx) = mpsc::unbounded_channel();
+
+        let stream_name = database.clone();
+        let outbound = async_stream::stream! {
+            while let Some(msg) = requests_rx.recv().await {
+                match msg {
+                    SessionMessage::Request(req) => yield *req,
+                    SessionMessage::Close => break,
+                }
+            }
+            tracing::debug!("Stopping listen session for {}", stream_name);
+        };
+
+        let backend = client.backend.clone();
+        let backend_database = database.clone();
+
+        // The response headers might only arrive once the first target was
+        // added, so the call itself is made in the background.
+        let targets = SessionTargets::default();
+        let routed_targets = targets.clone();
+        tokio::spawn(async move {
+            match backend.listen(backend_database, Box::pin(outbound)).await {
+                Err(FirestoreError::GrpcError(err)) => fail_targets(&routed_targets, *err),
+                Err(err) => {
+                    fail_targets(&routed_targets, tonic::Status::unavailable(err.to_string()))
+                }
+                Ok(inbound) => route_responses(inbound, routed_targets).await,
+            }
+        });
+
+        Ok(Self {
+            database,
+            documents_path,
+            requests,
+            targets,
+            // counted up from the id of the single target streams
+            next_target_id: Arc::new(AtomicI32::new(GRPC_TARGET_ID)),
+        })
+    }
+
+    /// Adds `target` to the session. Returns the target id that was assigned to
+    /// it and the stream of its updates. Dropping the stream removes the target.
+    pub fn add_target(
+        &self,
+        target: ListenTarget,
+    ) -> Result<(i32, CollectionStream), FirestoreError> {
+        let target_id = self.next_target_id.fetch_add(1, Ordering::SeqCst);
+        let target = target.into_target(target_id, &self.documents_path);
+        let (updates, updates_rx) = mpsc::unbounded_channel();
+
+        self.targets.lock().unwrap().insert(
+            target_id,
+            SessionTarget {
+                target: target.clone(),
+                state: CollectionStreamState::new(),
+                updates,
+            },
+        );
+
+        tracing::debug!("adding listen target {}", target_id);
+
+        if let Err(err) = self.send(firestore::listen_request::TargetChange::AddTarget(target)) {
+            self.targets.lock().unwrap().remove(&target_id);
+            return Err(err);
+        }
+
+        let stream = TargetStream {
+            target_id,
+            updates: updates_rx,
+            session: self.clone(),
+        };
+
+        Ok((target_id, Box::pin(stream)))
+    }
+
+    /// Stops listening to the target with `target_id`. Its stream will end.
+    pub fn remove_target(&self, target_id: i32) {
+        if self.targets.lock().unwrap().remove(&target_id).is_none() {
+            return;
+        }
+
+        tracing::debug!("removing listen target {}", target_id);
+
+        if let Err(err) = self.send(firestore::listen_request::TargetChange::RemoveTarget(
+            target_id,
+        )) {
+            tracing::debug!("unable to remove listen target {}: {}", target_id, err);
+        }
+    }
+
+    /// Removes the target and adds it again without resume token so that the
+    /// server sends all of its documents again.
+    fn reset_target(&self, target_id: i32) {
+        let target = match self.targets.lock().unwrap().get_mut(&target_id) {
+            None => return,
+            Some(target) => {
+                target.target.resume_type = None;
+                target.target.clone()
+            }
+        };
+
+        tracing::debug!("resetting listen target {}", target_id);
+
+        let res = self
+            .send(firestore::listen_request::TargetChange::RemoveTarget(
+                target_id,
+            ))
+            .and_then(|_| self.send(firestore::listen_request::TargetChange::AddTarget(target)));
+        if let Err(err) = res {
+            tracing::debug!("unable to reset listen target {}: {}", target_id, err);
+        }
+    }
+
+    /// The ids of all targets currently part of the session.
+    pub fn target_ids(&self) -> Vec<i32> {
+        self.targets.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Ends the underlying `Listen` stream. All target streams will end.
+    pub fn close(&self) {
+        if self.requests.send(SessionMessage::Close).is_err() {
+            tracing::debug!("listen session for {} already closed", self.database);
+        }
+    }
+
+    fn send(
+        &self,
+        target_change: firestore::listen_request::TargetChange,
+    ) -> Result<(), FirestoreError> {
+        let req = firestore::ListenRequest {
+            database: self.database.clone(),
+            labels: HashMap::new(),
+            target_change: Some(target_change),
+        };
+        self.requests
+            .send(SessionMessage::Request(Box::new(req)))
+            .map_err(|_| tonic::Status::cancelled("listen session is closed").into())
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+struct TargetStream {
+    target_id: i32,
+    updates: mpsc::UnboundedReceiver<TargetMessage>,
+    session: ListenSession,
+}
+
+impl Stream for TargetStream {
+    type Item = Result<CollectionUpdate, FirestoreError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match self.updates.poll_recv(cx) {
+                Poll::Ready(Some(TargetMessage::Reset)) => {
+                    self.session.reset_target(self.target_id);
+                }
+                Poll::Ready(Some(TargetMessage::Update(update))) => {
+                    return Poll::Ready(Some(update))
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl Drop for TargetStream {
+    fn drop(&mut self) {
+        self.session.remove_target(self.target_id);
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+async fn route_responses(mut inbound: ListenResponseStream, targets: SessionTargets) {
+    while let Some(res) = inbound.next().await {
+        match res {
+            Err(err) => {
+                fail_targets(&targets, err);
+                return;
+            }
+            Ok(res) => route_response(res, &mut targets.lock().unwrap()),
+        }
+    }
+
+    tracing::debug!("listen session stream ended");
+    targets.lock().unwrap().clear();
+}
+
+fn fail_targets(targets: &SessionTargets, err: tonic::Status) {
+    tracing::warn!("listen session errored: {}", err);
+    for (_, target) in targets.lock().unwrap().drain() {
+        let status = tonic::Status::new(err.code(), err.message().to_string());
+        let _ = target
+            .updates
+            .send(TargetMessage::Update(Err(status.into())));
+    }
+}
+
+/// Splits `res` into the responses that concern the individual targets and
+/// hands them to the state of each target.
+fn route_response(res: ListenResponse, targets: &mut HashMap<i32, SessionTarget>) {
+    let response_type = match res.response_type {
+        None => return,
+        Some(response_type) => response_type,
+    };
+
+    let routed: Vec<(i32, ResponseType)> = match response_type {
+        ResponseType::TargetChange(change) => {
+            if change.target_change_type() == TargetChangeType::Remove {
+                if let Some(cause) = &change.cause {
+                    for id in &change.target_ids {
+                        if let Some(target) = targets.remove(id) {
+                            tracing::info!(?cause, "listen target {} removed by server", id);
+                            let status =
+                                tonic::Status::new(cause.code.into(), cause.message.clone());
+                            let _ = target
+                                .updates
+                                .send(TargetMessage::Update(Err(status.into())));
+                        }
+                    }
+                    return;
+                }
+            }
+
+            // A change without target ids applies to all targets
+            let ids = if change.target_ids.is_empty() {
+                targets.keys().copied().collect()
+            } else {
+                change.target_ids.clone()
+            };
+
+            ids.into_iter()
+                .map(|id| {
+                    let change = firestore::TargetChange {
+                        target_ids: vec![id],
+                        ..change.clone()
+                    };
+                    (id, ResponseType::TargetChange(change))
+                })
+                .collect()
+        }
+
+        ResponseType::DocumentChange(change) => {
+            let mut routed = Vec::new();
+            for id in &change.target_ids {
+                routed.push((*id, ResponseType::DocumentChange(change.clone())));
+            }
+            if let Some(document) = &change.document {
+                for id in &change.removed_target_ids {
+                    routed.push((
+                        *id,
+                        ResponseType::DocumentRemove(firestore::DocumentRemove {
+                            document: document.name.clone(),
+                            removed_target_ids: vec![*id],
+                            read_time: document.update_time.clone(),
+                        }),
+                    ));
+                }
+            }
+            routed
+        }
+
+        ResponseType::DocumentDelete(delete) => delete
+            .removed_target_ids
+            .iter()
+            .map(|id| (*id, ResponseType::DocumentDelete(delete.clone())))
+            .collect(),
+
+        ResponseType::DocumentRemove(remove) => remove
+            .removed_target_ids
+            .iter()
+            .map(|id| (*id, ResponseType::DocumentRemove(remove.clone())))
+            .collect(),
+
+        ResponseType::Filter(filter) => vec![(filter.target_id, ResponseType::Filter(filter))],
+    };
+
+    for (id, response_type) in routed {
+        let target = match targets.get_mut(&id) {
+            None => {
+                tracing::trace!("ignoring listen response for unknown target {}", id);
+                continue;
+            }
+            Some(target) => target,
+        };
+
+        let res = ListenResponse {
+            response_type: Some(response_type),
+        };
+        let mut messages = Vec::new();
+        if let Some(update) = target.state.handle_listen_response(res) {
+            messages.push(TargetMessage::Update(Ok(update)));
+        }
+        if target.state.take_reset_request() {
+            messages.push(TargetMessage::Reset);
+        }
+        for msg in messages {
+            if target.updates.send(msg).is_err() {
+                tracing::debug!("listen target {} has no receiver anymore", id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn target() -> (SessionTarget, mpsc::UnboundedReceiver<TargetMessage>) {
+        let (updates, rx) = mpsc::unbounded_channel();
+        let target = SessionTarget {
+            target: Default::default(),
+            state: CollectionStreamState::new(),
+            updates,
+        };
+        (target, rx)
+    }
+
+    fn next_update(rx: &mut mpsc::UnboundedReceiver<TargetMessage>) -> CollectionUpdate {
+        match rx.try_recv() {
+            Ok(TargetMessage::Update(Ok(update))) => update,
+            _ => panic!("expected update"),
+        }
+    }
+
+    fn response(response_type: ResponseType) -> ListenResponse {
+        ListenResponse {
+            response_type: Some(response_type),
+        }
+    }
+
+    fn current(target_ids: Vec<i32>) -> ListenResponse {
+        response(ResponseType::TargetChange(firestore::TargetChange {
+            target_change_type: TargetChangeType::Current.into(),
+            target_ids,
+            ..Default::default()
+        }))
+    }
+
+    fn changed_ids(update: CollectionUpdate) -> Vec<String> {
+        let mut ids = update.documents.into_keys().collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn routes_document_changes_by_target_id() {
+        let (a, mut a_rx) = target();
+        let (b, mut b_rx) = target();
+        let mut targets = HashMap::from_iter([(1, a), (2, b)]);
+
+        for (name, target_ids) in [("doc-a", vec![1]), ("doc-ab", vec![1, 2])] {
+            let document = firestore::Document {
+                name: name.to_string(),
+                ..Default::default()
+            };
+            route_response(
+                response(ResponseType::DocumentChange(firestore::DocumentChange {
+                    document: Some(document),
+                    target_ids,
+                    removed_target_ids: Vec::new(),
+                })),
+                &mut targets,
+            );
+        }
+        route_response(current(Vec::new()), &mut targets);
+
+        let a_update = next_update(&mut a_rx);
+        let b_update = next_update(&mut b_rx);
+        assert_eq!(changed_ids(a_update), vec!["doc-a", "doc-ab"]);
+        assert_eq!(changed_ids(b_update), vec!["doc-ab"]);
+    }
+
+    #[test]
+    fn removed_target_ids_become_removals() {
+        let (a, mut a_rx) = target();
+        let mut targets = HashMap::from_iter([(1, a)]);
+
+        route_response(
+            response(ResponseType::DocumentChange(firestore::DocumentChange {
+                document: Some(firestore::Document {
+                    name: "doc".to_string(),
+                    ..Default::default()
+                }),
+                target_ids: vec![2],
+                removed_target_ids: vec![1],
+            })),
+            &mut targets,
+        );
+        route_response(current(vec![1]), &mut targets);
+
+        let update = next_update(&mut a_rx);
+        assert!(update.documents.is_empty());
+        assert!(matches!(
+            update.changes.as_slice(),
+            [crate::firestore::collection::CollectionChange::Delete { id, .. }] if id == "doc"
+        ));
+    }
+}
@@ -0,0 +1,374 @@
+use firestore_grpc::v1::{
+    self as firestore,
+    structured_query::{filter::FilterType, unary_filter::OperandType, Direction, Order},
+    value::ValueType,
+};
+use std::cmp::Ordering;
+
+use super::structured_query::{
+    CompositeFilterOperator, FieldFilterOperator, Filter, StructuredQueryBuilder,
+    UnaryFilterOperator,
+};
+use super::values::{compare_documents, compare_values, document_field, type_order, values_equal};
+
+/// Evaluates queries against documents in memory, e.g. the documents of a
+/// cached collection, with the same semantics as the Firestore backend.
+impl StructuredQueryBuilder {
+    /// Whether `doc` is part of the result set of this query, ignoring cursors,
+    /// offset and limit.
+    pub fn matches(&self, doc: &firestore::Document) -> bool {
+        if !self.from_collections.is_empty() {
+            let collection_id = doc.name.rsplit('/').nth(1).unwrap_or_default();
+            if !self.from_collections.iter().any(|c| c == collection_id) {
+                return false;
+            }
+        }
+
+        // documents without a value for an ordered field are not part of the
+        // result set
+        let has_order_fields = self.effective_order_by().iter().all(|order| {
+            order
+                .field
+                .as_ref()
+                .map(|field| document_field(doc, &field.field_path).is_some())
+                .unwrap_or(true)
+        });
+
+        has_order_fields
+            && self
+                .filter
+                .as_ref()
+                .map(|filter| filter_matches(filter, doc))
+                .unwrap_or(true)
+    }
+
+    /// Runs the query against `documents` and returns the matching documents in
+    /// query order.
+    pub fn evaluate<'a, I>(&self, documents: I) -> Vec<&'a firestore::Document>
+    where
+        I: IntoIterator<Item = &'a firestore::Document>,
+    {
+        let order_by = self.effective_order_by();
+
+        let mut result = documents
+            .into_iter()
+            .filter(|doc| self.matches(doc))
+            .filter(|doc| {
+                self.start_at
+                    .as_ref()
+                    .map(|cursor| {
+                        let ord = compare_to_cursor(&order_by, doc, cursor);
+                        ord == Ordering::Greater || (cursor.before && ord == Ordering::Equal)
+                    })
+                    .unwrap_or(true)
+            })
+            .filter(|doc| {
+                self.end_at
+                    .as_ref()
+                    .map(|cursor| {
+                        let ord = compare_to_cursor(&order_by, doc, cursor);
+                        ord == Ordering::Less || (!cursor.before && ord == Ordering::Equal)
+                    })
+                    .unwrap_or(true)
+            })
+            .collect::<Vec<_>>();
+
+        result.sort_by(|a, b| compare_documents(&order_by, a, b));
+
+        let offset = self.offset.max(0) as usize;
+        let limit = self
+            .limit
+            .map(|limit| limit.max(0) as usize)
+            .unwrap_or(usize::MAX);
+        result.into_iter().skip(offset).take(limit).collect()
+    }
+
+    /// Like the backend, fields of inequality filters that are not explicitly
+    /// ordered by are implicitly ordered by, ascending and after the explicit
+    /// orders.
+    fn effective_order_by(&self) -> Vec<Order> {
+        let mut order_by = self.order_by.clone();
+
+        let mut inequality_fields = Vec::new();
+        if let Some(filter) = &self.filter {
+            collect_inequality_fields(filter, &mut inequality_fields);
+        }
+        inequality_fields.sort();
+        inequality_fields.dedup();
+
+        for field_path in inequality_fields {
+            let ordered = order_by.iter().any(|order| {
+                order
+                    .field
+                    .as_ref()
+                    .map(|field| field.field_path == field_path)
+                    .unwrap_or(false)
+            });
+            if !ordered {
+                order_by.push(Order {
+                    field: Some(firestore::structured_query::FieldReference { field_path }),
+                    direction: Direction::Ascending.into(),
+                });
+            }
+        }
+
+        order_by
+    }
+}
+
+fn collect_inequality_fields(filter: &Filter, fields: &mut Vec<String>) {
+    match &filter.filter_type {
+        Some(FilterType::CompositeFilter(composite)) => {
+            for filter in &composite.filters {
+                collect_inequality_fields(filter, fields);
+            }
+        }
+        Some(FilterType::FieldFilter(filter)) => {
+            use firestore::structured_query::field_filter::Operator::*;
+            let inequality = matches!(
+                filter.op(),
+                LessThan | LessThanOrEqual | GreaterThan | GreaterThanOrEqual | NotEqual | NotIn
+            );
+            if let (true, Some(field)) = (inequality, &filter.field) {
+                fields.push(field.field_path.clone());
+            }
+        }
+        Some(FilterType::UnaryFilter(filter)) => {
+            use firestore::structured_query::unary_filter::Operator::*;
+            if let (IsNotNan | IsNotNull, Some(OperandType::Field(field))) =
+                (filter.op(), &filter.operand_type)
+            {
+                fields.push(field.field_path.clone());
+            }
+        }
+        None => {}
+    }
+}
+
+/// Compares `doc` against the position of `cursor`. Only the orders the cursor
+/// has values for are taken into account.
+fn compare_to_cursor(
+    order_by: &[Order],
+    doc: &firestore::Document,
+    cursor: &firestore::Cursor,
+) -> Ordering {
+    for (order, cursor_value) in order_by.iter().zip(cursor.values.iter()) {
+        let path = match &order.field {
+            Some(field) => field.field_path.as_str(),
+            None => continue,
+        };
+
+        let ord = match document_field(doc, path) {
+            None => Ordering::Less,
+            Some(value) if path == "__name__" => match &cursor_value.value_type {
+                Some(ValueType::ReferenceValue(_)) => compare_values(&value, cursor_value),
+                // cursors on the document id may use the plain id
+                Some(ValueType::StringValue(id)) => doc
+                    .name
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or_default()
+                    .cmp(id.as_str()),
+                _ => compare_values(&value, cursor_value),
+            },
+            Some(value) => compare_values(&value, cursor_value),
+        };
+        let ord = match order.direction() {
+            Direction::Descending => ord.reverse(),
+            _ => ord,
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+fn filter_matches(filter: &Filter, doc: &firestore::Document) -> bool {
+    match &filter.filter_type {
+        None => true,
+        Some(FilterType::CompositeFilter(composite)) => match composite.op() {
+            CompositeFilterOperator::Or => composite
+                .filters
+                .iter()
+                .any(|filter| filter_matches(filter, doc)),
+            _ => composite
+                .filters
+                .iter()
+                .all(|filter| filter_matches(filter, doc)),
+        },
+        Some(FilterType::FieldFilter(filter)) => {
+            let (field, value) = match (&filter.field, &filter.value) {
+                (Some(field), Some(value)) => (field, value),
+                _ => return false,
+            };
+            match document_field(doc, &field.field_path) {
+                None => false,
+                Some(field_value) => field_filter_matches(filter.op(), &field_value, value),
+            }
+        }
+        Some(FilterType::UnaryFilter(filter)) => {
+            let field = match &filter.operand_type {
+                Some(OperandType::Field(field)) => field,
+                None => return false,
+            };
+            let value = match document_field(doc, &field.field_path) {
+                None => return false,
+                Some(value) => value,
+            };
+            let is_nan = matches!(value.value_type, Some(ValueType::DoubleValue(d)) if d.is_nan());
+            let is_null = matches!(value.value_type, None | Some(ValueType::NullValue(_)));
+            match filter.op() {
+                UnaryFilterOperator::IsNan => is_nan,
+                UnaryFilterOperator::IsNull => is_null,
+                UnaryFilterOperator::IsNotNan => !is_nan,
+                UnaryFilterOperator::IsNotNull => !is_null,
+                UnaryFilterOperator::Unspecified => false,
+            }
+        }
+    }
+}
+
+fn field_filter_matches(
+    op: FieldFilterOperator,
+    field_value: &firestore::Value,
+    value: &firestore::Value,
+) -> bool {
+    use firestore::structured_query::field_filter::Operator::*;
+
+    let is_null = matches!(field_value.value_type, None | Some(ValueType::NullValue(_)));
+    let array_values = |value: &firestore::Value| match &value.value_type {
+        Some(ValueType::ArrayValue(array)) => array.values.clone(),
+        _ => Vec::new(),
+    };
+
+    match op {
+        LessThan | LessThanOrEqual | GreaterThan | GreaterThanOrEqual => {
+            // range filters only match values of the same type
+            if type_order(field_value) != type_order(value) {
+                return false;
+            }
+            let ord = compare_values(field_value, value);
+            match op {
+                LessThan => ord == Ordering::Less,
+                LessThanOrEqual => ord != Ordering::Greater,
+                GreaterThan => ord == Ordering::Greater,
+                _ => ord != Ordering::Less,
+            }
+        }
+        Equal => values_equal(field_value, value),
+        NotEqual => !is_null && !values_equal(field_value, value),
+        ArrayContains => array_values(field_value)
+            .iter()
+            .any(|element| values_equal(element, value)),
+        ArrayContainsAny => {
+            let candidates = array_values(value);
+            array_values(field_value)
+                .iter()
+                .any(|element| candidates.iter().any(|c| values_equal(element, c)))
+        }
+        In => array_values(value)
+            .iter()
+            .any(|candidate| values_equal(field_value, candidate)),
+        NotIn => {
+            !is_null
+                && !array_values(value)
+                    .iter()
+                    .any(|candidate| values_equal(field_value, candidate))
+        }
+        Unspecified => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firestore::structured_query::field_filter;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+
+    fn doc(id: &str, fields: &[(&str, firestore::Value)]) -> firestore::Document {
+        firestore::Document {
+            name: format!("projects/p/databases/(default)/documents/items/{id}"),
+            fields: fields
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect::<HashMap<_, _>>(),
+            ..Default::default()
+        }
+    }
+
+    fn int(i: i64) -> firestore::Value {
+        firestore::Value {
+            value_type: Some(ValueType::IntegerValue(i)),
+        }
+    }
+
+    fn string(s: &str) -> firestore::Value {
+        firestore::Value {
+            value_type: Some(ValueType::StringValue(s.to_string())),
+        }
+    }
+
+    fn ids(docs: Vec<&firestore::Document>) -> Vec<&str> {
+        docs.iter()
+            .map(|doc| doc.name.rsplit('/').next().unwrap())
+            .collect()
+    }
+
+    fn documents() -> Vec<firestore::Document> {
+        vec![
+            doc("a", &[("rank", int(3)), ("tag", string("x"))]),
+            doc("b", &[("rank", int(1)), ("tag", string("y"))]),
+            doc("c", &[("rank", string("2")), ("tag", string("x"))]),
+            doc("d", &[("tag", string("x"))]),
+            doc("e", &[("rank", int(2)), ("tag", string("x"))]),
+        ]
+    }
+
+    #[test]
+    fn range_filters_order_implicitly_and_skip_other_types() {
+        let docs = documents();
+        let query = StructuredQueryBuilder::new().from("items").field_filter(
+            "rank",
+            FieldFilterOperator::GreaterThan,
+            1,
+        );
+        assert_eq!(ids(query.evaluate(&docs)), vec!["e", "a"]);
+    }
+
+    #[test]
+    fn composite_filters_with_order_cursor_and_limit() {
+        let docs = documents();
+        let query = StructuredQueryBuilder::new()
+            .from("items")
+            .composite_filter(
+                CompositeFilterOperator::Or,
+                vec![
+                    field_filter("tag", FieldFilterOperator::Equal, "x".to_string()),
+                    field_filter("rank", FieldFilterOperator::Equal, 1),
+                ],
+            )
+            .order_by("rank")
+            .descending()
+            .start_at_before("2".to_string())
+            .done();
+
+        // "d" has no rank, strings sort after numbers
+        assert_eq!(ids(query.evaluate(&docs)), vec!["c", "a", "e", "b"]);
+        assert_eq!(
+            ids(query.clone().offset(1).limit(2).evaluate(&docs)),
+            vec!["a", "e"]
+        );
+
+        let query = query.order_by("__name__").ascending().done();
+        let query = StructuredQueryBuilder {
+            start_at: Some(firestore::Cursor {
+                values: vec![int(3), string("a")],
+                before: false,
+            }),
+            ..query
+        };
+        assert_eq!(ids(query.evaluate(&docs)), vec!["e", "b"]);
+    }
+}
@@ -0,0 +1,1100 @@
+// The in-memory backend fails with `Status` like the server does, boxing it
+// here would only mean unboxing it again at the backend boundary.
+#![allow(clippy::result_large_err)]
+
+use async_trait::async_trait;
+use firestore_grpc::google::rpc;
+use firestore_grpc::tonic::Status;
+use firestore_grpc::v1::{
+    self as firestore, batch_get_documents_response,
+    document_transform::{
+        field_transform::{ServerValue, TransformType},
+        FieldTransform,
+    },
+    listen_request,
+    listen_response::ResponseType,
+    precondition::ConditionType,
+    run_query_request::QueryType,
+    structured_query::{Direction, FieldReference, Order},
+    target::{query_target, ResumeType, TargetType},
+    target_change::TargetChangeType,
+    value::ValueType,
+    write::Operation,
+};
+use futures::StreamExt;
+use prost_types::Timestamp;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{broadcast, mpsc};
+
+use super::backend::{FirestoreBackend, ListenRequestStream, ListenResponseStream};
+use super::structured_query::StructuredQueryBuilder;
+use super::values::{compare_documents, compare_values, document_field, split_field_path};
+use super::values::{merge_masked_fields, set_field};
+use super::values::{type_order, values_equal};
+use crate::FirestoreError;
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// The documents written by one request to an [`InMemoryFirestore`].
+#[derive(Debug, Clone)]
+pub struct ChangeNotification {
+    pub version: u64,
+    pub time: SystemTime,
+    /// Full names of the written documents.
+    pub documents: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    documents: BTreeMap<String, firestore::Document>,
+    version: u64,
+    time: Option<SystemTime>,
+    /// The version at which each document, including deleted ones, was last
+    /// written. Used to resume listen targets.
+    written: BTreeMap<String, u64>,
+}
+
+impl State {
+    /// Commit times are strictly increasing so that they can be used as
+    /// preconditions.
+    fn next_time(&mut self) -> SystemTime {
+        let now = SystemTime::now();
+        let time = match self.time {
+            Some(last) if now <= last => last + Duration::from_micros(1),
+            _ => now,
+        };
+        self.time = Some(time);
+        time
+    }
+
+    fn read_time(&self) -> Timestamp {
+        self.time.unwrap_or_else(SystemTime::now).into()
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    project_id: String,
+    state: Mutex<State>,
+    changes: broadcast::Sender<ChangeNotification>,
+}
+
+/// A [`FirestoreBackend`] that keeps documents in memory, for tests.
+///
+/// Queries, preconditions, field transforms and listen targets follow the
+/// semantics of the Firestore backend. Clones share the same documents.
+///
+/// ```ignore
+/// let db = InMemoryFirestore::new();
+/// let client = FirebaseClient::with_backend(db.clone());
+/// client.update_document("items/a").field("rank", 1).update().await?;
+/// assert_eq!(db.documents().len(), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct InMemoryFirestore {
+    inner: Arc<Inner>,
+}
+
+impl InMemoryFirestore {
+    pub fn new() -> Self {
+        Self::with_project_id("in-memory")
+    }
+
+    pub fn with_project_id<S: ToString>(project_id: S) -> Self {
+        let (changes, _) = broadcast::channel(256);
+        Self {
+            inner: Arc::new(Inner {
+                project_id: project_id.to_string(),
+                state: Mutex::new(State::default()),
+                changes,
+            }),
+        }
+    }
+
+    pub fn documents_path(&self) -> String {
+        format!(
+            "projects/{}/databases/(default)/documents",
+            self.inner.project_id
+        )
+    }
+
+    /// Stores `document` under its full name, replacing an existing document.
+    pub fn insert(&self, document: firestore::Document) -> firestore::Document {
+        let name = document.name.clone();
+        let write = firestore::Write {
+            operation: Some(Operation::Update(document)),
+            ..Default::default()
+        };
+        self.apply(vec![write], true)
+            .expect("writes without preconditions succeed");
+        self.document(&name).expect("inserted document")
+    }
+
+    /// The document with the full `name`.
+    pub fn document(&self, name: &str) -> Option<firestore::Document> {
+        self.inner
+            .state
+            .lock()
+            .unwrap()
+            .documents
+            .get(name)
+            .cloned()
+    }
+
+    /// All documents ordered by name.
+    pub fn documents(&self) -> Vec<firestore::Document> {
+        let state = self.inner.state.lock().unwrap();
+        state.documents.values().cloned().collect()
+    }
+
+    /// Receives a notification for every request that wrote documents.
+    pub fn subscribe(&self) -> broadcast::Receiver<ChangeNotification> {
+        self.inner.changes.subscribe()
+    }
+
+    /// Applies `writes`. If `atomic`, either all writes are applied or the
+    /// first error is returned. Otherwise every write succeeds or fails on its
+    /// own.
+    fn apply(
+        &self,
+        writes: Vec<firestore::Write>,
+        atomic: bool,
+    ) -> Result<(Vec<Result<firestore::WriteResult, Status>>, SystemTime), Status> {
+        let mut state = self.inner.state.lock().unwrap();
+        let time = state.next_time();
+        let mut documents = state.documents.clone();
+        let mut results = Vec::new();
+        let mut written = Vec::new();
+
+        for write in writes {
+            match apply_write(&mut documents, write, time) {
+                Ok((name, result)) => {
+                    written.push(name);
+                    results.push(Ok(result));
+                }
+                Err(status) if atomic => return Err(status),
+                Err(status) => results.push(Err(status)),
+            }
+        }
+
+        state.documents = documents;
+        if !written.is_empty() {
+            state.version += 1;
+            let version = state.version;
+            for name in &written {
+                state.written.insert(name.clone(), version);
+            }
+            // no subscribers is fine
+            let _ = self.inner.changes.send(ChangeNotification {
+                version,
+                time,
+                documents: written,
+            });
+        }
+
+        Ok((results, time))
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// writes
+
+fn apply_write(
+    documents: &mut BTreeMap<String, firestore::Document>,
+    write: firestore::Write,
+    time: SystemTime,
+) -> Result<(String, firestore::WriteResult), Status> {
+    let firestore::Write {
+        update_mask,
+        update_transforms,
+        current_document,
+        operation,
+    } = write;
+
+    let operation = operation.ok_or_else(|| Status::invalid_argument("write without operation"))?;
+    let name = match &operation {
+        Operation::Update(document) => document.name.clone(),
+        Operation::Delete(name) => name.clone(),
+        Operation::Transform(transform) => transform.document.clone(),
+    };
+
+    let existing = documents.get(&name);
+    check_precondition(&name, existing, current_document)?;
+
+    let update_time: Timestamp = time.into();
+    let create_time = existing
+        .and_then(|doc| doc.create_time.clone())
+        .unwrap_or_else(|| update_time.clone());
+    let mut document = existing.cloned().unwrap_or_else(|| firestore::Document {
+        name: name.clone(),
+        ..Default::default()
+    });
+
+    let transforms = match operation {
+        Operation::Delete(_) => {
+            documents.remove(&name);
+            let result = firestore::WriteResult {
+                update_time: Some(update_time),
+                transform_results: Vec::new(),
+            };
+            return Ok((name, result));
+        }
+        Operation::Transform(transform) => transform.field_transforms,
+        Operation::Update(update) => {
+            match update_mask {
+                None => document.fields = update.fields,
+                Some(mask) => merge_masked_fields(&mut document, &update, &mask.field_paths),
+            }
+            update_transforms
+        }
+    };
+
+    let mut transform_results = Vec::new();
+    for transform in transforms {
+        let value = apply_transform(&document, &transform, &update_time)?;
+        set_field(
+            &mut document.fields,
+            &split_field_path(&transform.field_path),
+            Some(value.clone()),
+        );
+        transform_results.push(value);
+    }
+
+    document.create_time = Some(create_time);
+    document.update_time = Some(update_time.clone());
+    documents.insert(name.clone(), document);
+
+    let result = firestore::WriteResult {
+        update_time: Some(update_time),
+        transform_results,
+    };
+    Ok((name, result))
+}
+
+fn check_precondition(
+    name: &str,
+    existing: Option<&firestore::Document>,
+    precondition: Option<firestore::Precondition>,
+) -> Result<(), Status> {
+    match precondition.and_then(|precondition| precondition.condition_type) {
+        Some(ConditionType::Exists(true)) if existing.is_none() => {
+            Err(Status::not_found(format!("No document to update: {name}")))
+        }
+        Some(ConditionType::Exists(false)) if existing.is_some() => Err(Status::already_exists(
+            format!("Document already exists: {name}"),
+        )),
+        Some(ConditionType::UpdateTime(update_time)) => match existing {
+            Some(doc) if doc.update_time.as_ref() == Some(&update_time) => Ok(()),
+            Some(_) => Err(Status::failed_precondition(format!(
+                "The update time of {name} does not match the precondition"
+            ))),
+            None => Err(Status::not_found(format!("No document to update: {name}"))),
+        },
+        _ => Ok(()),
+    }
+}
+
+fn apply_transform(
+    document: &firestore::Document,
+    transform: &FieldTransform,
+    request_time: &Timestamp,
+) -> Result<firestore::Value, Status> {
+    let current = document_field(document, &transform.field_path);
+    let array_values = |value: Option<firestore::Value>| match value.and_then(|v| v.value_type) {
+        Some(ValueType::ArrayValue(array)) => array.values,
+        _ => Vec::new(),
+    };
+
+    let value_type = match &transform.transform_type {
+        Some(TransformType::SetToServerValue(value))
+            if ServerValue::from_i32(*value) == Some(ServerValue::RequestTime) =>
+        {
+            ValueType::TimestampValue(request_time.clone())
+        }
+        Some(TransformType::Increment(operand)) => {
+            let current = current.and_then(|value| value.value_type);
+            match (current, &operand.value_type) {
+                (Some(ValueType::IntegerValue(a)), Some(ValueType::IntegerValue(b))) => {
+                    ValueType::IntegerValue(a.saturating_add(*b))
+                }
+                (Some(ValueType::IntegerValue(a)), Some(ValueType::DoubleValue(b))) => {
+                    ValueType::DoubleValue(a as f64 + b)
+                }
+                (Some(ValueType::DoubleValue(a)), Some(ValueType::IntegerValue(b))) => {
+                    ValueType::DoubleValue(a + *b as f64)
+                }
+                (Some(ValueType::DoubleValue(a)), Some(ValueType::DoubleValue(b))) => {
+                    ValueType::DoubleValue(a + b)
+                }
+                _ => return Ok(operand.clone()),
+            }
+        }
+        Some(TransformType::Maximum(operand)) => {
+            return Ok(extremum(current, operand, Ordering::Greater))
+        }
+        Some(TransformType::Minimum(operand)) => {
+            return Ok(extremum(current, operand, Ordering::Less))
+        }
+        Some(TransformType::AppendMissingElements(elements)) => {
+            let mut values = array_values(current);
+            for element in &elements.values {
+                if !values.iter().any(|value| values_equal(value, element)) {
+                    values.push(element.clone());
+                }
+            }
+            ValueType::ArrayValue(firestore::ArrayValue { values })
+        }
+        Some(TransformType::RemoveAllFromArray(elements)) => {
+            let values = array_values(current)
+                .into_iter()
+                .filter(|value| {
+                    !elements
+                        .values
+                        .iter()
+                        .any(|element| values_equal(value, element))
+                })
+                .collect();
+            ValueType::ArrayValue(firestore::ArrayValue { values })
+        }
+        _ => {
+            return Err(Status::invalid_argument(format!(
+                "Invalid transform of field {}",
+                transform.field_path
+            )))
+        }
+    };
+
+    Ok(firestore::Value {
+        value_type: Some(value_type),
+    })
+}
+
+/// The `keep` side of the current field value and `operand`. Non-numeric
+/// field values are replaced.
+fn extremum(
+    current: Option<firestore::Value>,
+    operand: &firestore::Value,
+    keep: Ordering,
+) -> firestore::Value {
+    let is_number = |value: &firestore::Value| {
+        matches!(
+            value.value_type,
+            Some(ValueType::IntegerValue(_) | ValueType::DoubleValue(_))
+        )
+    };
+    match current {
+        Some(current)
+            if is_number(&current)
+                && type_order(&current) == type_order(operand)
+                && compare_values(&current, operand) == keep =>
+        {
+            current
+        }
+        _ => operand.clone(),
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// reads
+
+/// A structured query together with the parent it runs on.
+#[derive(Debug)]
+struct Query {
+    parent: String,
+    all_descendants: bool,
+    query: StructuredQueryBuilder,
+}
+
+impl Query {
+    fn new(parent: String, query: firestore::StructuredQuery) -> Self {
+        let all_descendants = query.from.iter().any(|from| from.all_descendants);
+        Self {
+            parent,
+            all_descendants,
+            query: query.into(),
+        }
+    }
+
+    fn in_scope(&self, doc: &firestore::Document) -> bool {
+        let rest = match doc
+            .name
+            .strip_prefix(&self.parent)
+            .and_then(|rest| rest.strip_prefix('/'))
+        {
+            None => return false,
+            Some(rest) => rest,
+        };
+        // collection id and document id
+        self.all_descendants || rest.split('/').count() == 2
+    }
+
+    fn matches(&self, doc: &firestore::Document) -> bool {
+        self.in_scope(doc) && self.query.matches(doc)
+    }
+}
+
+/// Parses the `order_by` of a list request, e.g. `"rank desc, name"`.
+fn parse_order_by(order_by: &str) -> Vec<Order> {
+    order_by
+        .split(',')
+        .filter_map(|order| {
+            let mut parts = order.split_whitespace();
+            let field_path = parts.next()?.to_string();
+            let direction = match parts.next() {
+                Some(direction) if direction.eq_ignore_ascii_case("desc") => Direction::Descending,
+                _ => Direction::Ascending,
+            };
+            Some(Order {
+                field: Some(FieldReference { field_path }),
+                direction: direction.into(),
+            })
+        })
+        .collect()
+}
+
+/// Page tokens are the offset of the page.
+fn paginate<T>(
+    items: Vec<T>,
+    page_size: i32,
+    page_token: &str,
+) -> Result<(Vec<T>, String), Status> {
+    let offset = match page_token {
+        "" => 0,
+        token => token
+            .parse::<usize>()
+            .map_err(|_| Status::invalid_argument(format!("Invalid page token {token}")))?,
+    };
+    let page_size = if page_size > 0 {
+        page_size as usize
+    } else {
+        usize::MAX
+    };
+    let total = items.len();
+    let items = items
+        .into_iter()
+        .skip(offset)
+        .take(page_size)
+        .collect::<Vec<_>>();
+    let end = offset + items.len();
+    let next_page_token = if end < total {
+        end.to_string()
+    } else {
+        String::new()
+    };
+    Ok((items, next_page_token))
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+#[async_trait]
+impl FirestoreBackend for InMemoryFirestore {
+    fn project_id(&self) -> Option<String> {
+        Some(self.inner.project_id.clone())
+    }
+
+    async fn get_document(
+        &self,
+        req: firestore::GetDocumentRequest,
+    ) -> Result<firestore::Document, FirestoreError> {
+        self.document(&req.name)
+            .ok_or_else(|| Status::not_found(format!("Document \"{}\" not found", req.name)).into())
+    }
+
+    async fn list_documents(
+        &self,
+        req: firestore::ListDocumentsRequest,
+    ) -> Result<firestore::ListDocumentsResponse, FirestoreError> {
+        let collection = format!("{}/{}", req.parent, req.collection_id);
+        let state = self.inner.state.lock().unwrap();
+        let mut documents = state
+            .documents
+            .values()
+            .filter(|doc| {
+                doc.name
+                    .rsplit_once('/')
+                    .map(|(parent, _)| parent == collection)
+                    .unwrap_or(false)
+            })
+            .collect::<Vec<_>>();
+        if !req.order_by.is_empty() {
+            let order_by = parse_order_by(&req.order_by);
+            documents.sort_by(|a, b| compare_documents(&order_by, a, b));
+        }
+
+        let (documents, next_page_token) = paginate(documents, req.page_size, &req.page_token)?;
+        Ok(firestore::ListDocumentsResponse {
+            documents: documents.into_iter().cloned().collect(),
+            next_page_token,
+        })
+    }
+
+    async fn list_collection_ids(
+        &self,
+        req: firestore::ListCollectionIdsRequest,
+    ) -> Result<firestore::ListCollectionIdsResponse, FirestoreError> {
+        let prefix = format!("{}/", req.parent);
+        let state = self.inner.state.lock().unwrap();
+        let collection_ids = state
+            .documents
+            .keys()
+            .filter_map(|name| name.strip_prefix(&prefix)?.split('/').next())
+            .map(String::from)
+            .collect::<BTreeSet<_>>();
+
+        let (collection_ids, next_page_token) = paginate(
+            collection_ids.into_iter().collect(),
+            req.page_size,
+            &req.page_token,
+        )?;
+        Ok(firestore::ListCollectionIdsResponse {
+            collection_ids,
+            next_page_token,
+        })
+    }
+
+    async fn batch_get_documents(
+        &self,
+        req: firestore::BatchGetDocumentsRequest,
+    ) -> Result<Vec<Result<firestore::BatchGetDocumentsResponse, Status>>, FirestoreError> {
+        let state = self.inner.state.lock().unwrap();
+        let read_time = state.read_time();
+        Ok(req
+            .documents
+            .into_iter()
+            .map(|name| {
+                let result = match state.documents.get(&name) {
+                    Some(doc) => batch_get_documents_response::Result::Found(doc.clone()),
+                    None => batch_get_documents_response::Result::Missing(name),
+                };
+                Ok(firestore::BatchGetDocumentsResponse {
+                    result: Some(result),
+                    read_time: Some(read_time.clone()),
+                    ..Default::default()
+                })
+            })
+            .collect())
+    }
+
+    async fn run_query(
+        &self,
+        req: firestore::RunQueryRequest,
+    ) -> Result<Vec<Result<firestore::RunQueryResponse, Status>>, FirestoreError> {
+        let query = match req.query_type {
+            Some(QueryType::StructuredQuery(query)) => Query::new(req.parent, query),
+            None => return Err(Status::invalid_argument("Missing query").into()),
+        };
+
+        let state = self.inner.state.lock().unwrap();
+        let read_time = state.read_time();
+        let documents = query
+            .query
+            .evaluate(state.documents.values().filter(|doc| query.in_scope(doc)));
+
+        // like the server, an empty result is a single response without document
+        if documents.is_empty() {
+            return Ok(vec![Ok(firestore::RunQueryResponse {
+                read_time: Some(read_time),
+                ..Default::default()
+            })]);
+        }
+
+        Ok(documents
+            .into_iter()
+            .map(|doc| {
+                Ok(firestore::RunQueryResponse {
+                    document: Some(doc.clone()),
+                    read_time: Some(read_time.clone()),
+                    ..Default::default()
+                })
+            })
+            .collect())
+    }
+
+    async fn update_document(
+        &self,
+        req: firestore::UpdateDocumentRequest,
+    ) -> Result<firestore::Document, FirestoreError> {
+        let document = req
+            .document
+            .ok_or_else(|| Status::invalid_argument("Missing document"))?;
+        let name = document.name.clone();
+        let write = firestore::Write {
+            update_mask: req.update_mask,
+            update_transforms: Vec::new(),
+            current_document: req.current_document,
+            operation: Some(Operation::Update(document)),
+        };
+        self.apply(vec![write], true)?;
+        self.document(&name)
+            .ok_or_else(|| Status::not_found(format!("Document \"{name}\" not found")).into())
+    }
+
+    async fn delete_document(
+        &self,
+        req: firestore::DeleteDocumentRequest,
+    ) -> Result<(), FirestoreError> {
+        let write = firestore::Write {
+            current_document: req.current_document,
+            operation: Some(Operation::Delete(req.name)),
+            ..Default::default()
+        };
+        self.apply(vec![write], true)?;
+        Ok(())
+    }
+
+    async fn batch_write(
+        &self,
+        req: firestore::BatchWriteRequest,
+    ) -> Result<firestore::BatchWriteResponse, FirestoreError> {
+        let (results, _) = self.apply(req.writes, false)?;
+        let mut response = firestore::BatchWriteResponse::default();
+        for result in results {
+            let (write_result, status) = match result {
+                Ok(write_result) => (write_result, rpc::Status::default()),
+                Err(status) => (
+                    firestore::WriteResult::default(),
+                    rpc::Status {
+                        code: status.code() as i32,
+                        message: status.message().to_string(),
+                        details: Vec::new(),
+                    },
+                ),
+            };
+            response.write_results.push(write_result);
+            response.status.push(status);
+        }
+        Ok(response)
+    }
+
+    async fn commit(
+        &self,
+        req: firestore::CommitRequest,
+    ) -> Result<firestore::CommitResponse, FirestoreError> {
+        let (results, time) = self.apply(req.writes, true)?;
+        Ok(firestore::CommitResponse {
+            write_results: results.into_iter().filter_map(Result::ok).collect(),
+            commit_time: Some(time.into()),
+        })
+    }
+
+    async fn listen(
+        &self,
+        _database: String,
+        mut requests: ListenRequestStream,
+    ) -> Result<ListenResponseStream, FirestoreError> {
+        let (responses, mut responses_rx) = mpsc::unbounded_channel();
+        let mut changes = self.subscribe();
+        let mut watch = Watch {
+            firestore: self.clone(),
+            targets: HashMap::new(),
+            responses,
+        };
+
+        tokio::spawn(async move {
+            loop {
+                let sent = tokio::select! {
+                    _ = watch.responses.closed() => break,
+
+                    req = requests.next() => match req.and_then(|req| req.target_change) {
+                        None => break,
+                        Some(listen_request::TargetChange::AddTarget(target)) => {
+                            watch.add_target(target)
+                        }
+                        Some(listen_request::TargetChange::RemoveTarget(target_id)) => {
+                            watch.remove_target(target_id)
+                        }
+                    },
+
+                    change = changes.recv() => match change {
+                        Ok(change) => watch.apply_change(Some(change)),
+                        Err(broadcast::error::RecvError::Lagged(_)) => watch.apply_change(None),
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                };
+                if sent.is_err() {
+                    break;
+                }
+            }
+            tracing::debug!("in-memory listen stream ended");
+        });
+
+        Ok(Box::pin(async_stream::stream! {
+            while let Some(res) = responses_rx.recv().await {
+                yield res;
+            }
+        }))
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// listen
+
+type ListenSender = mpsc::UnboundedSender<Result<firestore::ListenResponse, Status>>;
+type SendResult = Result<(), mpsc::error::SendError<Result<firestore::ListenResponse, Status>>>;
+
+enum TargetKind {
+    Query(Query),
+    Documents(HashSet<String>),
+}
+
+impl TargetKind {
+    fn matches(&self, doc: &firestore::Document) -> bool {
+        match self {
+            TargetKind::Query(query) => query.matches(doc),
+            TargetKind::Documents(names) => names.contains(&doc.name),
+        }
+    }
+}
+
+struct WatchedTarget {
+    kind: TargetKind,
+    /// Names of the documents the listener was told match.
+    matching: HashSet<String>,
+    /// The version of the last change sent.
+    version: u64,
+}
+
+/// The server side of one listen stream.
+struct Watch {
+    firestore: InMemoryFirestore,
+    targets: HashMap<i32, WatchedTarget>,
+    responses: ListenSender,
+}
+
+fn resume_token(version: u64) -> Vec<u8> {
+    version.to_be_bytes().to_vec()
+}
+
+fn target_change(
+    change_type: TargetChangeType,
+    target_ids: Vec<i32>,
+    resume_token: Vec<u8>,
+    read_time: Option<Timestamp>,
+) -> ResponseType {
+    ResponseType::TargetChange(firestore::TargetChange {
+        target_change_type: change_type.into(),
+        target_ids,
+        cause: None,
+        resume_token,
+        read_time,
+    })
+}
+
+/// The response telling target `target_id` about the current state of the
+/// document `name`.
+fn document_response(
+    target_id: i32,
+    name: &str,
+    doc: Option<&firestore::Document>,
+    matches: bool,
+    read_time: &Timestamp,
+) -> ResponseType {
+    match doc {
+        Some(doc) if matches => ResponseType::DocumentChange(firestore::DocumentChange {
+            document: Some(doc.clone()),
+            target_ids: vec![target_id],
+            removed_target_ids: Vec::new(),
+        }),
+        Some(_) => ResponseType::DocumentRemove(firestore::DocumentRemove {
+            document: name.to_string(),
+            removed_target_ids: vec![target_id],
+            read_time: Some(read_time.clone()),
+        }),
+        None => ResponseType::DocumentDelete(firestore::DocumentDelete {
+            document: name.to_string(),
+            removed_target_ids: vec![target_id],
+            read_time: Some(read_time.clone()),
+        }),
+    }
+}
+
+impl Watch {
+    fn send(&self, responses: Vec<ResponseType>) -> SendResult {
+        for response_type in responses {
+            self.responses.send(Ok(firestore::ListenResponse {
+                response_type: Some(response_type),
+            }))?;
+        }
+        Ok(())
+    }
+
+    fn add_target(&mut self, target: firestore::Target) -> SendResult {
+        let target_id = target.target_id;
+        let kind = match target.target_type {
+            Some(TargetType::Query(firestore::target::QueryTarget {
+                parent,
+                query_type: Some(query_target::QueryType::StructuredQuery(query)),
+            })) => TargetKind::Query(Query::new(parent, query)),
+            Some(TargetType::Documents(documents)) => {
+                TargetKind::Documents(documents.documents.into_iter().collect())
+            }
+            _ => {
+                return self.responses.send(Err(Status::invalid_argument(format!(
+                    "Invalid listen target {target_id}"
+                ))))
+            }
+        };
+        let resume_version = match &target.resume_type {
+            Some(ResumeType::ResumeToken(token)) => {
+                token.as_slice().try_into().ok().map(u64::from_be_bytes)
+            }
+            _ => None,
+        };
+
+        let state = self.firestore.inner.state.lock().unwrap();
+        let read_time = state.read_time();
+        let matching = state
+            .documents
+            .values()
+            .filter(|doc| kind.matches(doc))
+            .map(|doc| doc.name.clone())
+            .collect::<HashSet<_>>();
+
+        let mut responses = vec![target_change(
+            TargetChangeType::Add,
+            vec![target_id],
+            Vec::new(),
+            None,
+        )];
+        match resume_version {
+            // only what changed since the token, including documents that no
+            // longer match
+            Some(version) => {
+                for (name, _) in state.written.iter().filter(|(_, v)| **v > version) {
+                    let doc = state.documents.get(name);
+                    let matches = matching.contains(name);
+                    responses.push(document_response(target_id, name, doc, matches, &read_time));
+                }
+            }
+            None => {
+                for name in &matching {
+                    let doc = state.documents.get(name);
+                    responses.push(document_response(target_id, name, doc, true, &read_time));
+                }
+            }
+        }
+        responses.push(target_change(
+            TargetChangeType::Current,
+            vec![target_id],
+            resume_token(state.version),
+            Some(read_time.clone()),
+        ));
+        responses.push(target_change(
+            TargetChangeType::NoChange,
+            Vec::new(),
+            resume_token(state.version),
+            Some(read_time),
+        ));
+
+        self.targets.insert(
+            target_id,
+            WatchedTarget {
+                kind,
+                matching,
+                version: state.version,
+            },
+        );
+        drop(state);
+
+        self.send(responses)
+    }
+
+    fn remove_target(&mut self, target_id: i32) -> SendResult {
+        self.targets.remove(&target_id);
+        self.send(vec![target_change(
+            TargetChangeType::Remove,
+            vec![target_id],
+            Vec::new(),
+            None,
+        )])
+    }
+
+    /// Tells all targets about `change`. Without a change, i.e. when
+    /// notifications were missed, all documents are compared.
+    fn apply_change(&mut self, change: Option<ChangeNotification>) -> SendResult {
+        let state = self.firestore.inner.state.lock().unwrap();
+        let read_time = state.read_time();
+        let version = change
+            .as_ref()
+            .map(|change| change.version)
+            .unwrap_or(state.version);
+
+        let mut responses = Vec::new();
+        for (target_id, target) in &mut self.targets {
+            if target.version >= version {
+                continue;
+            }
+            target.version = version;
+
+            let names = match &change {
+                Some(change) => change.documents.iter().cloned().collect::<BTreeSet<_>>(),
+                None => state
+                    .documents
+                    .keys()
+                    .chain(target.matching.iter())
+                    .cloned()
+                    .collect(),
+            };
+            for name in names {
+                let doc = state.documents.get(&name);
+                let matches = doc.map(|doc| target.kind.matches(doc)).unwrap_or(false);
+                let was_matching = if matches {
+                    !target.matching.insert(name.clone())
+                } else {
+                    target.matching.remove(&name)
+                };
+                if matches || was_matching {
+                    responses.push(document_response(
+                        *target_id, &name, doc, matches, &read_time,
+                    ));
+                }
+            }
+        }
+        drop(state);
+
+        if !responses.is_empty() {
+            responses.push(target_change(
+                TargetChangeType::NoChange,
+                Vec::new(),
+                resume_token(version),
+                Some(read_time),
+            ));
+        }
+        self.send(responses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firestore::structured_query::FieldFilterOperator;
+    use crate::firestore::{FieldPath, FirebaseClient};
+    use pretty_assertions::assert_eq;
+
+    fn ids(docs: &[firestore::Document]) -> Vec<&str> {
+        docs.iter()
+            .map(|doc| doc.name.rsplit('/').next().unwrap())
+            .collect()
+    }
+
+    async fn client_with_items() -> (InMemoryFirestore, FirebaseClient) {
+        let db = InMemoryFirestore::new();
+        let client = FirebaseClient::with_backend(db.clone());
+        for (id, rank) in [("a", 3), ("b", 1), ("c", 2)] {
+            client
+                .update_document(format!("items/{id}"))
+                .field("rank", rank)
+                .update()
+                .await
+                .unwrap();
+        }
+        (db, client)
+    }
+
+    #[tokio::test]
+    async fn queries_filter_and_order() {
+        let (_db, client) = client_with_items().await;
+
+        let docs = client
+            .run_query()
+            .from("items")
+            .field_filter("rank", FieldFilterOperator::GreaterThan, 1)
+            .order_by("rank")
+            .descending()
+            .done()
+            .fetch()
+            .await
+            .unwrap();
+        assert_eq!(ids(&docs), vec!["a", "c"]);
+
+        client.delete_document("items/a").fetch().await.unwrap();
+        let docs = client.list_documents("items").fetch().await.unwrap();
+        assert_eq!(ids(&docs), vec!["b", "c"]);
+        assert!(client.get_document("items/a").fetch().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn lists_documents_in_order() {
+        let (_db, client) = client_with_items().await;
+
+        let docs = client
+            .list_documents("items")
+            .order_by_descending("rank")
+            .fetch()
+            .await
+            .unwrap();
+        assert_eq!(ids(&docs), vec!["a", "c", "b"]);
+
+        let docs = client
+            .list_documents("items")
+            .order_by(FieldPath::new(["rank"]))
+            .order_by(FieldPath::document_id())
+            .fetch()
+            .await
+            .unwrap();
+        assert_eq!(ids(&docs), vec!["b", "c", "a"]);
+
+        let docs = client
+            .list_documents("items")
+            .order_by("name")
+            .order_by_raw("rank desc, __name__")
+            .fetch()
+            .await
+            .unwrap();
+        assert_eq!(ids(&docs), vec!["a", "c", "b"]);
+
+        // a clause is one field name, not a field and a direction
+        let options = client.list_documents("items").order_by("rank desc");
+        assert_eq!(options.order_by.as_deref(), Some("`rank desc`"));
+    }
+
+    #[tokio::test]
+    async fn update_time_preconditions() {
+        let (db, client) = client_with_items().await;
+        let doc = client.get_document("items/a").fetch().await.unwrap();
+
+        let write = |update_time| firestore::Write {
+            current_document: Some(firestore::Precondition {
+                condition_type: Some(ConditionType::UpdateTime(update_time)),
+            }),
+            operation: Some(Operation::Update(doc.clone())),
+            ..Default::default()
+        };
+        let commit = |write| firestore::CommitRequest {
+            database: String::new(),
+            writes: vec![write],
+            transaction: Vec::new(),
+        };
+
+        let update_time = doc.update_time.clone().unwrap();
+        db.commit(commit(write(update_time.clone()))).await.unwrap();
+        let err = db.commit(commit(write(update_time))).await.unwrap_err();
+        assert!(matches!(
+            err,
+            FirestoreError::GrpcError(status) if status.code() == firestore_grpc::tonic::Code::FailedPrecondition
+        ));
+    }
+
+    #[tokio::test]
+    async fn streams_notify_about_changes() {
+        let (_db, client) = client_with_items().await;
+        let (mut stream, controller) = client.stream_builder("items").build().await.unwrap();
+
+        let initial = stream.next().await.unwrap().unwrap();
+        let mut initial_ids = initial.documents.keys().cloned().collect::<Vec<_>>();
+        initial_ids.sort();
+        assert_eq!(initial_ids.len(), 3);
+
+        client.delete_document("items/b").fetch().await.unwrap();
+        let update = stream.next().await.unwrap().unwrap();
+        assert!(matches!(
+            update.changes.as_slice(),
+            [super::super::collection::CollectionChange::Delete { id, .. }] if id.ends_with("items/b")
+        ));
+
+        controller.stop().await;
+    }
+}
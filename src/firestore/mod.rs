@@ -1,12 +1,31 @@
+pub mod admin;
+pub mod backend;
 pub mod client;
 pub mod collection;
 pub mod conversion;
 mod fetch_and_update;
+pub mod field_path;
+pub mod listen_session;
+pub mod local_query;
+pub mod memory;
+pub mod offline;
+#[cfg(feature = "rest")]
+pub mod rest;
+pub mod shared_listener;
+pub mod snapshot;
+pub mod store;
 pub mod streaming;
 pub mod structured_query;
+pub mod synced;
+pub mod values;
 
+pub use backend::{FirestoreBackend, GrpcBackend, Transport};
 pub use client::*;
 pub use conversion::{
     FromFirestoreDocument, FromFirestoreValue, IntoFirestoreDocument, IntoFirestoreDocumentValue,
+    TryIntoFirestoreDocumentValue,
 };
+pub use field_path::{DocumentExt, FieldPath, IntoFieldPath};
 pub use firestore_grpc::v1 as types;
+#[cfg(feature = "rest")]
+pub use rest::RestBackend;
@@ -0,0 +1,546 @@
+use chrono::prelude::*;
+use firestore_grpc::tonic::Code;
+use firestore_grpc::v1::{self as firestore, write::Operation, CommitRequest};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use super::collection::CachedCollection;
+use super::conversion::{FromFirestoreDocument, IntoFirestoreDocument};
+use super::store::write_atomically;
+use super::values::merge_masked_fields;
+use super::FirebaseClient;
+use crate::timeout::run_with_timeout;
+use crate::{FirestoreError, OfflineWriteError};
+
+#[derive(Debug, Clone)]
+pub struct PendingWrite {
+    pub id: u64,
+    pub write: firestore::Write,
+    pub created: DateTime<Utc>,
+}
+
+impl PendingWrite {
+    /// The name of the document this write changes.
+    pub fn document_name(&self) -> &str {
+        match &self.write.operation {
+            Some(Operation::Update(doc)) => &doc.name,
+            Some(Operation::Delete(name)) => name,
+            Some(Operation::Transform(transform)) => &transform.document,
+            None => "",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredWrite {
+    id: u64,
+    /// The protobuf encoded `firestore::Write`.
+    write: Vec<u8>,
+    created: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub enum WriteEvent {
+    /// The write was queued and is applied to local views.
+    Pending { id: u64, document: String },
+    /// The server has committed the write.
+    Acknowledged { id: u64, document: String },
+    /// The server rejected the write, local views no longer include it.
+    Rejected {
+        id: u64,
+        document: String,
+        error: String,
+    },
+}
+
+/// The outcome of [`OfflineWriteQueue::flush`].
+#[derive(Debug, Default)]
+pub struct FlushResult {
+    /// Ids of the writes the server committed.
+    pub acknowledged: Vec<u64>,
+    /// Writes the server rejected, they were removed from the queue.
+    pub rejected: Vec<RejectedWrite>,
+}
+
+#[derive(Debug)]
+pub struct RejectedWrite {
+    pub write: PendingWrite,
+    pub error: FirestoreError,
+}
+
+/// A document of a local view, with pending writes applied.
+#[derive(Debug, Clone)]
+pub struct LocalDocument<T> {
+    pub value: T,
+    /// A write that changes the document has not been acknowledged yet.
+    pub pending: bool,
+}
+
+/// Queues writes while offline and replays them in order once the server can be
+/// reached again. Pending writes are persisted so that they survive restarts
+/// and are applied optimistically to local views of cached collections.
+pub struct OfflineWriteQueue {
+    client: FirebaseClient,
+    path: Option<PathBuf>,
+    pending: Mutex<VecDeque<PendingWrite>>,
+    next_id: AtomicU64,
+    events: broadcast::Sender<WriteEvent>,
+    // only one flush at a time so that writes are sent in order
+    flush_lock: tokio::sync::Mutex<()>,
+}
+
+impl OfflineWriteQueue {
+    /// A queue that keeps pending writes in memory only.
+    pub fn new(client: FirebaseClient) -> Self {
+        let (events, _) = broadcast::channel(64);
+        Self {
+            client,
+            path: None,
+            pending: Mutex::new(VecDeque::new()),
+            next_id: AtomicU64::new(1),
+            events,
+            flush_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// A queue that persists pending writes to `path` and loads the writes
+    /// still pending from a previous run.
+    pub fn open<P: AsRef<Path>>(
+        client: FirebaseClient,
+        path: P,
+    ) -> Result<Self, OfflineWriteError> {
+        let path = path.as_ref().to_path_buf();
+        let mut queue = Self::new(client);
+
+        if path.exists() {
+            let file = std::fs::OpenOptions::new().read(true).open(&path)?;
+            let stored: Vec<StoredWrite> = serde_json::from_reader(std::io::BufReader::new(file))?;
+            let pending = stored
+                .into_iter()
+                .map(|stored| {
+                    Ok(PendingWrite {
+                        id: stored.id,
+                        write: firestore::Write::decode(stored.write.as_slice())?,
+                        created: stored.created,
+                    })
+                })
+                .collect::<Result<VecDeque<_>, OfflineWriteError>>()?;
+            tracing::debug!(
+                "loaded {} pending writes from {}",
+                pending.len(),
+                path.display()
+            );
+            let next_id = pending.back().map(|last| last.id + 1).unwrap_or(1);
+            queue.next_id = AtomicU64::new(next_id);
+            queue.pending = Mutex::new(pending);
+        }
+
+        queue.path = Some(path);
+        Ok(queue)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<WriteEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn pending(&self) -> Vec<PendingWrite> {
+        self.pending.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Queues replacing the document with `document`. The document name has to
+    /// be the full path of the document.
+    pub fn update(&self, document: firestore::Document) -> Result<u64, OfflineWriteError> {
+        self.enqueue(firestore::Write {
+            operation: Some(Operation::Update(document)),
+            ..Default::default()
+        })
+    }
+
+    /// Queues deleting the document with the full path `name`.
+    pub fn delete<S: ToString>(&self, name: S) -> Result<u64, OfflineWriteError> {
+        self.enqueue(firestore::Write {
+            operation: Some(Operation::Delete(name.to_string())),
+            ..Default::default()
+        })
+    }
+
+    pub fn enqueue(&self, write: firestore::Write) -> Result<u64, OfflineWriteError> {
+        let pending = {
+            let mut queue = self.pending.lock().unwrap();
+            let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+            let pending = PendingWrite {
+                id,
+                write,
+                created: Utc::now(),
+            };
+            queue.push_back(pending.clone());
+            self.persist(&queue)?;
+            pending
+        };
+
+        let _ = self.events.send(WriteEvent::Pending {
+            id: pending.id,
+            document: pending.document_name().to_string(),
+        });
+        Ok(pending.id)
+    }
+
+    fn persist(&self, queue: &VecDeque<PendingWrite>) -> Result<(), OfflineWriteError> {
+        let path = match &self.path {
+            None => return Ok(()),
+            Some(path) => path,
+        };
+        let stored = queue
+            .iter()
+            .map(|pending| StoredWrite {
+                id: pending.id,
+                write: pending.write.encode_to_vec(),
+                created: pending.created,
+            })
+            .collect::<Vec<_>>();
+        write_atomically(path, &serde_json::to_vec(&stored)?)?;
+        Ok(())
+    }
+
+    /// Sends pending writes in order. Stops at the first write that cannot be
+    /// sent right now, e.g. because the server cannot be reached or the auth
+    /// fails, and returns that error, the write stays queued. A write that is
+    /// unauthenticated is sent once more with a fresh token first. Writes the
+    /// server rejects are dropped, returned in [`FlushResult::rejected`] and
+    /// reported as [`WriteEvent::Rejected`].
+    pub async fn flush(&self) -> Result<FlushResult, FirestoreError> {
+        let _flushing = self.flush_lock.lock().await;
+        let database = format!("projects/{}/databases/(default)", self.client.project_id);
+        let mut result = FlushResult::default();
+
+        loop {
+            let next = self.pending.lock().unwrap().front().cloned();
+            let pending = match next {
+                None => break,
+                Some(pending) => pending,
+            };
+
+            let req = CommitRequest {
+                database: database.clone(),
+                writes: vec![pending.write.clone()],
+                transaction: Vec::new(),
+            };
+            let mut committed =
+                run_with_timeout(self.client.timeout, self.client.backend.commit(req.clone()))
+                    .await;
+            if matches!(&committed, Err(FirestoreError::GrpcError(status)) if status.code() == Code::Unauthenticated)
+            {
+                // sending it again fetches a new token
+                tracing::info!(
+                    "pending write {} is unauthenticated, retrying with a fresh token",
+                    pending.id
+                );
+                committed =
+                    run_with_timeout(self.client.timeout, self.client.backend.commit(req)).await;
+            }
+            let document = pending.document_name().to_string();
+
+            let event = match committed {
+                Ok(_) => {
+                    result.acknowledged.push(pending.id);
+                    WriteEvent::Acknowledged {
+                        id: pending.id,
+                        document,
+                    }
+                }
+                Err(err) if !is_rejection(&err) => {
+                    tracing::debug!("cannot send pending write {}: {}", pending.id, err);
+                    return Err(err);
+                }
+                Err(err) => {
+                    tracing::warn!("pending write {} was rejected: {}", pending.id, err);
+                    let event = WriteEvent::Rejected {
+                        id: pending.id,
+                        document,
+                        error: err.to_string(),
+                    };
+                    result.rejected.push(RejectedWrite {
+                        write: pending.clone(),
+                        error: err,
+                    });
+                    event
+                }
+            };
+
+            {
+                let mut queue = self.pending.lock().unwrap();
+                queue.retain(|write| write.id != pending.id);
+                if let Err(err) = self.persist(&queue) {
+                    tracing::error!("error persisting pending writes: {}", err);
+                }
+            }
+            let _ = self.events.send(event);
+        }
+
+        Ok(result)
+    }
+
+    /// Flushes pending writes every `interval` until the queue is dropped.
+    pub fn spawn_replay(self: &Arc<Self>, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let queue = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                let queue = match queue.upgrade() {
+                    None => break,
+                    Some(queue) => queue,
+                };
+                if queue.pending.lock().unwrap().is_empty() {
+                    continue;
+                }
+                if let Err(err) = queue.flush().await {
+                    tracing::debug!("replaying pending writes failed, will retry: {}", err);
+                }
+            }
+        })
+    }
+
+    /// The documents of `collection` with all pending writes of the collection
+    /// applied. Writes with an update mask only change the masked fields, for
+    /// those `T` has to serialize to the fields of the document.
+    pub fn view<T>(&self, collection: &CachedCollection<T>) -> HashMap<String, LocalDocument<T>>
+    where
+        T: FromFirestoreDocument + Serialize + Clone,
+    {
+        let mut documents = collection
+            .documents
+            .iter()
+            .map(|(name, value)| {
+                let doc = LocalDocument {
+                    value: value.clone(),
+                    pending: false,
+                };
+                (name.clone(), doc)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let prefix = format!("{}/{}/", self.client.documents_path(), collection.name);
+        for pending in self.pending.lock().unwrap().iter() {
+            let name = pending.document_name();
+            match name.strip_prefix(&prefix) {
+                // only documents directly in the collection
+                Some(id) if !id.contains('/') => {}
+                _ => continue,
+            }
+
+            match &pending.write.operation {
+                Some(Operation::Update(doc)) => {
+                    let converted = match &pending.write.update_mask {
+                        None => T::convert_doc(doc.clone()).map_err(|err| err.to_string()),
+                        Some(mask) => {
+                            let current = documents.get(name).map(|doc| &doc.value);
+                            apply_masked_update(current, doc, &mask.field_paths)
+                        }
+                    };
+                    match converted {
+                        Ok(value) => {
+                            documents.insert(
+                                name.to_string(),
+                                LocalDocument {
+                                    value,
+                                    pending: true,
+                                },
+                            );
+                        }
+                        Err(err) => {
+                            tracing::error!("cannot convert pending document {}: {}", name, err);
+                        }
+                    }
+                }
+                Some(Operation::Delete(_)) => {
+                    documents.remove(name);
+                }
+                _ => {}
+            }
+        }
+
+        documents
+    }
+}
+
+/// Applies a write with an update mask to the local value `current`, only the
+/// masked fields change.
+fn apply_masked_update<T>(
+    current: Option<&T>,
+    update: &firestore::Document,
+    field_paths: &[String],
+) -> Result<T, String>
+where
+    T: FromFirestoreDocument + Serialize,
+{
+    let mut document = match current {
+        None => firestore::Document::default(),
+        Some(value) => serde_json::to_value(value)
+            .map_err(|err| err.to_string())?
+            .into_document_from_fields()
+            .map_err(|err| err.to_string())?,
+    };
+    document.name = update.name.clone();
+    merge_masked_fields(&mut document, update, field_paths);
+    T::convert_doc(document).map_err(|err| err.to_string())
+}
+
+/// Errors that mean the write itself is wrong: the server rejects it or it
+/// cannot be encoded. Only these drop a write, sending it again would fail the
+/// same way and block the writes behind it forever. Anything else, e.g. a
+/// connection, auth or interceptor error, keeps the write queued.
+fn is_rejection(err: &FirestoreError) -> bool {
+    match err {
+        FirestoreError::GrpcError(status) => matches!(
+            status.code(),
+            Code::InvalidArgument
+                | Code::NotFound
+                | Code::AlreadyExists
+                | Code::PermissionDenied
+                | Code::FailedPrecondition
+                | Code::OutOfRange
+        ),
+        FirestoreError::SerializationError(_)
+        | FirestoreError::ConversionError(_)
+        | FirestoreError::DocumentConversionError { .. } => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{WebClientConfig, WebUserAnonAuth};
+    use crate::firestore::IntoFirestoreDocumentValue;
+    use pretty_assertions::assert_eq;
+    use serde_json::Value;
+
+    fn client() -> FirebaseClient {
+        let config: WebClientConfig = serde_json::from_value(serde_json::json!({
+            "apiKey": "key",
+            "authDomain": "test.firebaseapp.com",
+            "databaseURL": "https://test.firebaseio.com",
+            "projectId": "test",
+            "storageBucket": "test.appspot.com",
+            "messagingSenderId": "1",
+            "appId": "1",
+        }))
+        .unwrap();
+        FirebaseClient::new(Box::new(WebUserAnonAuth::new(config)))
+    }
+
+    #[test]
+    fn pending_writes_are_persisted_and_applied_to_views() {
+        let client = client();
+        let path = std::env::temp_dir().join(format!("pending-writes-{}.json", std::process::id()));
+        let name = |id: &str| format!("{}/items/{}", client.documents_path(), id);
+
+        let mut collection = CachedCollection::<Value>::new("items");
+        collection
+            .documents
+            .insert(name("a"), serde_json::json!({ "v": 1 }));
+        collection
+            .documents
+            .insert(name("b"), serde_json::json!({ "v": 2 }));
+
+        let queue = OfflineWriteQueue::open(client.clone(), &path).unwrap();
+        queue.delete(name("a")).unwrap();
+        queue
+            .update(firestore::Document {
+                name: name("c"),
+                ..Default::default()
+            })
+            .unwrap();
+        queue
+            .update(firestore::Document {
+                name: format!("{}/other/d", client.documents_path()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let reopened = OfflineWriteQueue::open(client, &path).unwrap();
+        assert_eq!(
+            reopened.pending().iter().map(|w| w.id).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+
+        let view = reopened.view(&collection);
+        let mut ids = view
+            .iter()
+            .map(|(name, doc)| (name.rsplit('/').next().unwrap(), doc.pending))
+            .collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec![("b", false), ("c", true)]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        v: i64,
+        w: Option<i64>,
+    }
+
+    impl FromFirestoreDocument for Item {
+        type Err = crate::FirestoreConversionError;
+
+        fn convert_doc(doc: firestore::Document) -> Result<Self, Self::Err> {
+            crate::firestore::conversion::convert_document_fields_to_obj(doc)
+        }
+    }
+
+    #[test]
+    fn masked_writes_only_change_masked_fields_in_views() {
+        let client = client();
+        let name = format!("{}/items/a", client.documents_path());
+        let mut collection = CachedCollection::<Item>::new("items");
+        collection
+            .documents
+            .insert(name.clone(), Item { v: 1, w: Some(1) });
+
+        let queue = OfflineWriteQueue::new(client);
+        let mut update = firestore::Document {
+            name: name.clone(),
+            ..Default::default()
+        };
+        update
+            .fields
+            .insert("w".to_string(), 2.into_document_value());
+        queue
+            .enqueue(firestore::Write {
+                operation: Some(Operation::Update(update)),
+                update_mask: Some(firestore::DocumentMask {
+                    field_paths: vec!["w".to_string()],
+                }),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let view = queue.view(&collection);
+        assert_eq!(view[&name].value, Item { v: 1, w: Some(2) });
+        assert!(view[&name].pending);
+    }
+
+    #[test]
+    fn only_rejections_drop_writes() {
+        use firestore_grpc::tonic::Status;
+
+        assert!(is_rejection(&Status::permission_denied("rules").into()));
+        assert!(is_rejection(&Status::failed_precondition("exists").into()));
+        assert!(!is_rejection(&Status::unauthenticated("expired").into()));
+        assert!(!is_rejection(&Status::unavailable("offline").into()));
+        assert!(!is_rejection(&FirestoreError::DeadlineExceeded(
+            Duration::from_secs(1)
+        )));
+        assert!(!is_rejection(&FirestoreError::InterceptorError(
+            "no app check token".into()
+        )));
+    }
+}
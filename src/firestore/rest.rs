@@ -0,0 +1,1198 @@
+//! A [`FirestoreBackend`] that talks to the Firestore REST API
+//! (`firestore.googleapis.com/v1/...`) for environments that block gRPC.
+//! Errors are mapped to the gRPC status the REST API reports so that callers
+//! see the same errors as with [`GrpcBackend`]. Listen has no REST
+//! counterpart and still uses gRPC.
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use firebase_client_auth::GoogleAuth;
+use firestore_grpc::google::rpc;
+use firestore_grpc::tonic::{Code, Status};
+use firestore_grpc::v1::{
+    self as firestore,
+    document_transform::{field_transform, FieldTransform},
+    structured_query::{self, filter::FilterType},
+};
+use reqwest::{Method, RequestBuilder};
+use serde_json::{json, Map, Value};
+use url::Url;
+
+use super::backend::{FirestoreBackend, GrpcBackend, ListenRequestStream, ListenResponseStream};
+use super::conversion::rest::{
+    document_from_rest_json, fields_to_rest_json, timestamp_from_rest_json, timestamp_to_rest_json,
+    value_from_rest_json, value_to_rest_json, values_to_rest_json,
+};
+use crate::instrumentation::FIRESTORE;
+use crate::interceptor::{
+    InterceptedRequest, InterceptedResponse, Interceptors, RequestInterceptor,
+};
+use crate::timeout::request_timeout;
+use crate::{FirestoreConversionError, FirestoreError};
+
+const URL: &str = "https://firestore.googleapis.com";
+
+#[derive(Debug)]
+pub struct RestBackend {
+    auth: GoogleAuth,
+    base_url: Url,
+    http: reqwest::Client,
+    grpc: GrpcBackend,
+}
+
+impl RestBackend {
+    pub fn new(auth: GoogleAuth) -> Self {
+        Self::with_base_url(auth, Url::parse(URL).expect("valid url"))
+    }
+
+    /// Sends requests to `base_url` instead of `https://firestore.googleapis.com`.
+    pub fn with_base_url(auth: GoogleAuth, base_url: Url) -> Self {
+        Self {
+            grpc: GrpcBackend::new(auth.box_clone()),
+            auth,
+            base_url,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Adds `interceptor` to the requests of this backend. It also applies to
+    /// listen, which uses gRPC.
+    pub fn add_interceptor<I: RequestInterceptor + 'static>(&self, interceptor: I) {
+        self.grpc.interceptors.push(interceptor);
+    }
+
+    /// `resource` is a resource name, optionally followed by a custom method
+    /// like `:commit`.
+    async fn request(
+        &self,
+        method: Method,
+        resource: &str,
+    ) -> Result<RequestBuilder, FirestoreError> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| Status::invalid_argument(format!("invalid base url {}", self.base_url)))?
+            .push("v1")
+            .extend(resource.split('/'));
+
+        tracing::debug!("firestore REST {} {}", method, url);
+
+        let req = self.http.request(method, url);
+        Ok(match self.auth.get_token().await? {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        })
+    }
+
+    async fn send(&self, req: RequestBuilder) -> Result<Value, FirestoreError> {
+        let mut req = req.build().map_err(transport_error)?;
+        let mut request =
+            InterceptedRequest::new(FIRESTORE, req.method().to_string(), req.url().to_string());
+        let interceptors = &self.grpc.interceptors;
+        interceptors
+            .on_request(&mut request)
+            .await
+            .map_err(FirestoreError::InterceptorError)?;
+        req.headers_mut().extend(request.headers.clone());
+        *req.timeout_mut() = request_timeout();
+
+        let res = self.http.execute(req).await.map_err(transport_error)?;
+        let status = res.status();
+        interceptors.on_response(&request, || InterceptedResponse {
+            status: status.as_u16().to_string(),
+            headers: res.headers().clone(),
+        });
+        let body = res.text().await.map_err(transport_error)?;
+        if !status.is_success() {
+            return Err(error_status(status, &body).into());
+        }
+        if body.is_empty() {
+            return Ok(Value::Null);
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+}
+
+#[async_trait]
+impl FirestoreBackend for RestBackend {
+    fn project_id(&self) -> Option<String> {
+        Some(self.auth.project_id().to_string())
+    }
+
+    fn interceptors(&self) -> Option<&Interceptors> {
+        self.grpc.interceptors()
+    }
+
+    async fn get_document(
+        &self,
+        req: firestore::GetDocumentRequest,
+    ) -> Result<firestore::Document, FirestoreError> {
+        use firestore::get_document_request::ConsistencySelector;
+
+        let mut query = mask_params("mask.fieldPaths", &req.mask);
+        match &req.consistency_selector {
+            Some(ConsistencySelector::Transaction(transaction)) => {
+                query.push(("transaction", BASE64.encode(transaction)));
+            }
+            Some(ConsistencySelector::ReadTime(time)) => {
+                query.push(("readTime", timestamp_param(time)?));
+            }
+            None => {}
+        }
+        let res = self
+            .send(self.request(Method::GET, &req.name).await?.query(&query))
+            .await?;
+        Ok(document_from_rest_json(res)?)
+    }
+
+    async fn list_documents(
+        &self,
+        req: firestore::ListDocumentsRequest,
+    ) -> Result<firestore::ListDocumentsResponse, FirestoreError> {
+        use firestore::list_documents_request::ConsistencySelector;
+
+        let mut query = mask_params("mask.fieldPaths", &req.mask);
+        if req.page_size > 0 {
+            query.push(("pageSize", req.page_size.to_string()));
+        }
+        if !req.page_token.is_empty() {
+            query.push(("pageToken", req.page_token.clone()));
+        }
+        if !req.order_by.is_empty() {
+            query.push(("orderBy", req.order_by.clone()));
+        }
+        if req.show_missing {
+            query.push(("showMissing", "true".to_string()));
+        }
+        match &req.consistency_selector {
+            Some(ConsistencySelector::Transaction(transaction)) => {
+                query.push(("transaction", BASE64.encode(transaction)));
+            }
+            Some(ConsistencySelector::ReadTime(time)) => {
+                query.push(("readTime", timestamp_param(time)?));
+            }
+            None => {}
+        }
+
+        let resource = format!("{}/{}", req.parent, req.collection_id);
+        let res = self
+            .send(self.request(Method::GET, &resource).await?.query(&query))
+            .await?;
+        let documents = match res.get("documents") {
+            Some(Value::Array(docs)) => docs
+                .iter()
+                .map(|doc| document_from_rest_json(doc.clone()))
+                .collect::<Result<_, _>>()?,
+            _ => Vec::new(),
+        };
+        Ok(firestore::ListDocumentsResponse {
+            documents,
+            next_page_token: string(&res, "nextPageToken"),
+        })
+    }
+
+    async fn list_collection_ids(
+        &self,
+        req: firestore::ListCollectionIdsRequest,
+    ) -> Result<firestore::ListCollectionIdsResponse, FirestoreError> {
+        use firestore::list_collection_ids_request::ConsistencySelector;
+
+        let mut body = Map::new();
+        if req.page_size > 0 {
+            body.insert("pageSize".to_string(), req.page_size.into());
+        }
+        if !req.page_token.is_empty() {
+            body.insert("pageToken".to_string(), req.page_token.clone().into());
+        }
+        if let Some(ConsistencySelector::ReadTime(time)) = &req.consistency_selector {
+            body.insert("readTime".to_string(), timestamp_to_rest_json(time)?);
+        }
+
+        let resource = format!("{}:listCollectionIds", req.parent);
+        let res = self
+            .send(self.request(Method::POST, &resource).await?.json(&body))
+            .await?;
+        let collection_ids = match res.get("collectionIds") {
+            Some(Value::Array(ids)) => ids
+                .iter()
+                .filter_map(|id| id.as_str().map(str::to_string))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(firestore::ListCollectionIdsResponse {
+            collection_ids,
+            next_page_token: string(&res, "nextPageToken"),
+        })
+    }
+
+    async fn batch_get_documents(
+        &self,
+        req: firestore::BatchGetDocumentsRequest,
+    ) -> Result<Vec<Result<firestore::BatchGetDocumentsResponse, Status>>, FirestoreError> {
+        use firestore::batch_get_documents_request::ConsistencySelector;
+        use firestore::batch_get_documents_response::Result as BatchGetResult;
+
+        let mut body = Map::new();
+        body.insert("documents".to_string(), json!(req.documents));
+        if let Some(mask) = &req.mask {
+            body.insert("mask".to_string(), encode_mask(mask));
+        }
+        match &req.consistency_selector {
+            Some(ConsistencySelector::Transaction(transaction)) => {
+                body.insert("transaction".to_string(), BASE64.encode(transaction).into());
+            }
+            Some(ConsistencySelector::NewTransaction(options)) => {
+                body.insert(
+                    "newTransaction".to_string(),
+                    encode_transaction_options(options)?,
+                );
+            }
+            Some(ConsistencySelector::ReadTime(time)) => {
+                body.insert("readTime".to_string(), timestamp_to_rest_json(time)?);
+            }
+            None => {}
+        }
+
+        let resource = format!("{}/documents:batchGet", req.database);
+        let res = self
+            .send(self.request(Method::POST, &resource).await?.json(&body))
+            .await?;
+
+        // streaming methods respond with an array of messages
+        let mut responses = Vec::new();
+        for res in array(res) {
+            if let Some(error) = res.get("error") {
+                responses.push(Err(status_from_error(
+                    error,
+                    Code::Unknown,
+                    &res.to_string(),
+                )));
+                continue;
+            }
+            let result = match (res.get("found"), res.get("missing")) {
+                (Some(found), _) => Some(BatchGetResult::Found(document_from_rest_json(
+                    found.clone(),
+                )?)),
+                (_, Some(Value::String(missing))) => Some(BatchGetResult::Missing(missing.clone())),
+                _ => None,
+            };
+            responses.push(Ok(firestore::BatchGetDocumentsResponse {
+                transaction: bytes(&res, "transaction")?,
+                read_time: timestamp(&res, "readTime")?,
+                result,
+            }));
+        }
+        Ok(responses)
+    }
+
+    async fn run_query(
+        &self,
+        req: firestore::RunQueryRequest,
+    ) -> Result<Vec<Result<firestore::RunQueryResponse, Status>>, FirestoreError> {
+        use firestore::run_query_request::{ConsistencySelector, QueryType};
+        use firestore::run_query_response::ContinuationSelector;
+
+        let mut body = Map::new();
+        if let Some(QueryType::StructuredQuery(query)) = &req.query_type {
+            body.insert("structuredQuery".to_string(), encode_query(query)?);
+        }
+        match &req.consistency_selector {
+            Some(ConsistencySelector::Transaction(transaction)) => {
+                body.insert("transaction".to_string(), BASE64.encode(transaction).into());
+            }
+            Some(ConsistencySelector::NewTransaction(options)) => {
+                body.insert(
+                    "newTransaction".to_string(),
+                    encode_transaction_options(options)?,
+                );
+            }
+            Some(ConsistencySelector::ReadTime(time)) => {
+                body.insert("readTime".to_string(), timestamp_to_rest_json(time)?);
+            }
+            None => {}
+        }
+
+        let resource = format!("{}:runQuery", req.parent);
+        let res = self
+            .send(self.request(Method::POST, &resource).await?.json(&body))
+            .await?;
+
+        let mut responses = Vec::new();
+        for res in array(res) {
+            if let Some(error) = res.get("error") {
+                responses.push(Err(status_from_error(
+                    error,
+                    Code::Unknown,
+                    &res.to_string(),
+                )));
+                continue;
+            }
+            let document = match res.get("document") {
+                Some(doc) => Some(document_from_rest_json(doc.clone())?),
+                None => None,
+            };
+            responses.push(Ok(firestore::RunQueryResponse {
+                transaction: bytes(&res, "transaction")?,
+                document,
+                read_time: timestamp(&res, "readTime")?,
+                skipped_results: res
+                    .get("skippedResults")
+                    .and_then(Value::as_i64)
+                    .unwrap_or_default() as i32,
+                continuation_selector: res
+                    .get("done")
+                    .and_then(Value::as_bool)
+                    .map(ContinuationSelector::Done),
+            }));
+        }
+        Ok(responses)
+    }
+
+    async fn update_document(
+        &self,
+        req: firestore::UpdateDocumentRequest,
+    ) -> Result<firestore::Document, FirestoreError> {
+        let document = req.document.unwrap_or_default();
+        let mut query = mask_params("updateMask.fieldPaths", &req.update_mask);
+        query.extend(mask_params("mask.fieldPaths", &req.mask));
+        query.extend(precondition_params(&req.current_document)?);
+
+        let body = json!({ "fields": fields_to_rest_json(&document.fields)? });
+        let res = self
+            .send(
+                self.request(Method::PATCH, &document.name)
+                    .await?
+                    .query(&query)
+                    .json(&body),
+            )
+            .await?;
+        Ok(document_from_rest_json(res)?)
+    }
+
+    async fn delete_document(
+        &self,
+        req: firestore::DeleteDocumentRequest,
+    ) -> Result<(), FirestoreError> {
+        let query = precondition_params(&req.current_document)?;
+        self.send(self.request(Method::DELETE, &req.name).await?.query(&query))
+            .await?;
+        Ok(())
+    }
+
+    async fn batch_write(
+        &self,
+        req: firestore::BatchWriteRequest,
+    ) -> Result<firestore::BatchWriteResponse, FirestoreError> {
+        let mut body = Map::new();
+        body.insert(
+            "writes".to_string(),
+            Value::Array(
+                req.writes
+                    .iter()
+                    .map(encode_write)
+                    .collect::<Result<_, _>>()?,
+            ),
+        );
+        if !req.labels.is_empty() {
+            body.insert("labels".to_string(), json!(req.labels));
+        }
+
+        let resource = format!("{}/documents:batchWrite", req.database);
+        let res = self
+            .send(self.request(Method::POST, &resource).await?.json(&body))
+            .await?;
+        let status = match res.get("status") {
+            Some(Value::Array(status)) => status
+                .iter()
+                .map(|status| rpc::Status {
+                    code: status
+                        .get("code")
+                        .and_then(Value::as_i64)
+                        .unwrap_or_default() as i32,
+                    message: string(status, "message"),
+                    details: Vec::new(),
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(firestore::BatchWriteResponse {
+            write_results: write_results(&res)?,
+            status,
+        })
+    }
+
+    async fn commit(
+        &self,
+        req: firestore::CommitRequest,
+    ) -> Result<firestore::CommitResponse, FirestoreError> {
+        let mut body = Map::new();
+        body.insert(
+            "writes".to_string(),
+            Value::Array(
+                req.writes
+                    .iter()
+                    .map(encode_write)
+                    .collect::<Result<_, _>>()?,
+            ),
+        );
+        if !req.transaction.is_empty() {
+            body.insert(
+                "transaction".to_string(),
+                BASE64.encode(&req.transaction).into(),
+            );
+        }
+
+        let resource = format!("{}/documents:commit", req.database);
+        let res = self
+            .send(self.request(Method::POST, &resource).await?.json(&body))
+            .await?;
+        Ok(firestore::CommitResponse {
+            write_results: write_results(&res)?,
+            commit_time: timestamp(&res, "commitTime")?,
+        })
+    }
+
+    async fn listen(
+        &self,
+        database: String,
+        requests: ListenRequestStream,
+    ) -> Result<ListenResponseStream, FirestoreError> {
+        self.grpc.listen(database, requests).await
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// errors
+
+/// Connection failures are reported as `UNAVAILABLE` like tonic does, so that
+/// retries treat both transports alike.
+fn transport_error(err: reqwest::Error) -> FirestoreError {
+    if err.is_timeout() {
+        return Status::deadline_exceeded(err.to_string()).into();
+    }
+    Status::unavailable(err.to_string()).into()
+}
+
+/// Maps an error response like
+/// `{"error": {"code": 404, "message": "...", "status": "NOT_FOUND"}}`.
+fn error_status(status: reqwest::StatusCode, body: &str) -> Status {
+    let error = serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|body| match body {
+            // streaming methods respond with an array of messages
+            Value::Array(messages) => messages.into_iter().find_map(|m| m.get("error").cloned()),
+            body => body.get("error").cloned(),
+        })
+        .unwrap_or_default();
+    status_from_error(&error, code_from_http_status(status), body)
+}
+
+/// `error` is the `error` object of a response, `code` and `message` are used
+/// for what it lacks.
+fn status_from_error(error: &Value, code: Code, message: &str) -> Status {
+    let code = match error.get("status").and_then(Value::as_str) {
+        Some(name) => code_from_name(name),
+        None => code,
+    };
+    let message = match error.get("message").and_then(Value::as_str) {
+        Some(message) => message.to_string(),
+        None => message.to_string(),
+    };
+    Status::new(code, message)
+}
+
+fn code_from_name(name: &str) -> Code {
+    match name {
+        "OK" => Code::Ok,
+        "CANCELLED" => Code::Cancelled,
+        "INVALID_ARGUMENT" => Code::InvalidArgument,
+        "DEADLINE_EXCEEDED" => Code::DeadlineExceeded,
+        "NOT_FOUND" => Code::NotFound,
+        "ALREADY_EXISTS" => Code::AlreadyExists,
+        "PERMISSION_DENIED" => Code::PermissionDenied,
+        "RESOURCE_EXHAUSTED" => Code::ResourceExhausted,
+        "FAILED_PRECONDITION" => Code::FailedPrecondition,
+        "ABORTED" => Code::Aborted,
+        "OUT_OF_RANGE" => Code::OutOfRange,
+        "UNIMPLEMENTED" => Code::Unimplemented,
+        "INTERNAL" => Code::Internal,
+        "UNAVAILABLE" => Code::Unavailable,
+        "DATA_LOSS" => Code::DataLoss,
+        "UNAUTHENTICATED" => Code::Unauthenticated,
+        _ => Code::Unknown,
+    }
+}
+
+fn code_from_http_status(status: reqwest::StatusCode) -> Code {
+    match status.as_u16() {
+        400 => Code::InvalidArgument,
+        401 => Code::Unauthenticated,
+        403 => Code::PermissionDenied,
+        404 => Code::NotFound,
+        409 => Code::Aborted,
+        412 => Code::FailedPrecondition,
+        429 => Code::ResourceExhausted,
+        499 => Code::Cancelled,
+        501 => Code::Unimplemented,
+        503 => Code::Unavailable,
+        504 => Code::DeadlineExceeded,
+        _ => Code::Unknown,
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// requests
+
+fn timestamp_param(time: &prost_types::Timestamp) -> Result<String, FirestoreConversionError> {
+    Ok(match timestamp_to_rest_json(time)? {
+        Value::String(time) => time,
+        time => time.to_string(),
+    })
+}
+
+/// `key` is e.g. `mask.fieldPaths`.
+fn mask_params(
+    key: &'static str,
+    mask: &Option<firestore::DocumentMask>,
+) -> Vec<(&'static str, String)> {
+    mask.iter()
+        .flat_map(|mask| mask.field_paths.iter())
+        .map(|path| (key, path.clone()))
+        .collect()
+}
+
+fn precondition_params(
+    precondition: &Option<firestore::Precondition>,
+) -> Result<Vec<(&'static str, String)>, FirestoreConversionError> {
+    use firestore::precondition::ConditionType;
+    Ok(
+        match precondition
+            .as_ref()
+            .and_then(|p| p.condition_type.as_ref())
+        {
+            Some(ConditionType::Exists(exists)) => {
+                vec![("currentDocument.exists", exists.to_string())]
+            }
+            Some(ConditionType::UpdateTime(time)) => {
+                vec![("currentDocument.updateTime", timestamp_param(time)?)]
+            }
+            None => Vec::new(),
+        },
+    )
+}
+
+fn encode_mask(mask: &firestore::DocumentMask) -> Value {
+    json!({ "fieldPaths": mask.field_paths })
+}
+
+fn encode_precondition(
+    precondition: &firestore::Precondition,
+) -> Result<Value, FirestoreConversionError> {
+    use firestore::precondition::ConditionType;
+    Ok(match &precondition.condition_type {
+        Some(ConditionType::Exists(exists)) => json!({ "exists": exists }),
+        Some(ConditionType::UpdateTime(time)) => {
+            json!({ "updateTime": timestamp_to_rest_json(time)? })
+        }
+        None => json!({}),
+    })
+}
+
+fn encode_transaction_options(
+    options: &firestore::TransactionOptions,
+) -> Result<Value, FirestoreConversionError> {
+    use firestore::transaction_options::{read_only::ConsistencySelector, Mode};
+    Ok(match &options.mode {
+        Some(Mode::ReadOnly(read_only)) => match &read_only.consistency_selector {
+            Some(ConsistencySelector::ReadTime(time)) => {
+                json!({ "readOnly": { "readTime": timestamp_to_rest_json(time)? } })
+            }
+            None => json!({ "readOnly": {} }),
+        },
+        Some(Mode::ReadWrite(read_write)) if !read_write.retry_transaction.is_empty() => {
+            json!({ "readWrite": { "retryTransaction": BASE64.encode(&read_write.retry_transaction) } })
+        }
+        Some(Mode::ReadWrite(_)) => json!({ "readWrite": {} }),
+        None => json!({}),
+    })
+}
+
+fn encode_write(write: &firestore::Write) -> Result<Value, FirestoreConversionError> {
+    use firestore::write::Operation;
+
+    let mut obj = Map::new();
+    match &write.operation {
+        Some(Operation::Update(doc)) => {
+            obj.insert(
+                "update".to_string(),
+                json!({ "name": doc.name, "fields": fields_to_rest_json(&doc.fields)? }),
+            );
+        }
+        Some(Operation::Delete(name)) => {
+            obj.insert("delete".to_string(), name.clone().into());
+        }
+        Some(Operation::Transform(transform)) => {
+            obj.insert(
+                "transform".to_string(),
+                json!({
+                    "document": transform.document,
+                    "fieldTransforms": transform
+                        .field_transforms
+                        .iter()
+                        .map(encode_field_transform)
+                        .collect::<Result<Vec<_>, _>>()?,
+                }),
+            );
+        }
+        None => {}
+    }
+    if let Some(mask) = &write.update_mask {
+        obj.insert("updateMask".to_string(), encode_mask(mask));
+    }
+    if !write.update_transforms.is_empty() {
+        obj.insert(
+            "updateTransforms".to_string(),
+            Value::Array(
+                write
+                    .update_transforms
+                    .iter()
+                    .map(encode_field_transform)
+                    .collect::<Result<_, _>>()?,
+            ),
+        );
+    }
+    if let Some(precondition) = &write.current_document {
+        obj.insert(
+            "currentDocument".to_string(),
+            encode_precondition(precondition)?,
+        );
+    }
+    Ok(Value::Object(obj))
+}
+
+fn encode_field_transform(transform: &FieldTransform) -> Result<Value, FirestoreConversionError> {
+    use field_transform::TransformType;
+
+    let array = |array: &firestore::ArrayValue| {
+        values_to_rest_json(&array.values).map(|values| json!({ "values": values }))
+    };
+    let (key, value) = match &transform.transform_type {
+        // REQUEST_TIME is the only server value
+        Some(TransformType::SetToServerValue(_)) => ("setToServerValue", json!("REQUEST_TIME")),
+        Some(TransformType::Increment(val)) => ("increment", value_to_rest_json(val)?),
+        Some(TransformType::Maximum(val)) => ("maximum", value_to_rest_json(val)?),
+        Some(TransformType::Minimum(val)) => ("minimum", value_to_rest_json(val)?),
+        Some(TransformType::AppendMissingElements(val)) => ("appendMissingElements", array(val)?),
+        Some(TransformType::RemoveAllFromArray(val)) => ("removeAllFromArray", array(val)?),
+        None => return Ok(json!({ "fieldPath": transform.field_path })),
+    };
+    Ok(json!({ "fieldPath": transform.field_path, key: value }))
+}
+
+fn field_reference(field: &Option<structured_query::FieldReference>) -> Value {
+    let path = field
+        .as_ref()
+        .map(|f| f.field_path.as_str())
+        .unwrap_or_default();
+    json!({ "fieldPath": path })
+}
+
+fn encode_query(query: &firestore::StructuredQuery) -> Result<Value, FirestoreConversionError> {
+    let mut obj = Map::new();
+    if let Some(select) = &query.select {
+        let fields = select
+            .fields
+            .iter()
+            .map(|field| json!({ "fieldPath": field.field_path }))
+            .collect::<Vec<_>>();
+        obj.insert("select".to_string(), json!({ "fields": fields }));
+    }
+    obj.insert(
+        "from".to_string(),
+        Value::Array(
+            query
+                .from
+                .iter()
+                .map(|from| {
+                    json!({
+                        "collectionId": from.collection_id,
+                        "allDescendants": from.all_descendants,
+                    })
+                })
+                .collect(),
+        ),
+    );
+    if let Some(filter) = &query.r#where {
+        obj.insert("where".to_string(), encode_filter(filter)?);
+    }
+    if !query.order_by.is_empty() {
+        let order_by = query
+            .order_by
+            .iter()
+            .map(|order| {
+                let direction = match structured_query::Direction::from_i32(order.direction) {
+                    Some(structured_query::Direction::Descending) => "DESCENDING",
+                    Some(structured_query::Direction::Ascending) => "ASCENDING",
+                    _ => "DIRECTION_UNSPECIFIED",
+                };
+                json!({ "field": field_reference(&order.field), "direction": direction })
+            })
+            .collect::<Vec<_>>();
+        obj.insert("orderBy".to_string(), Value::Array(order_by));
+    }
+    let cursor = |cursor: &firestore::Cursor| {
+        values_to_rest_json(&cursor.values)
+            .map(|values| json!({ "values": values, "before": cursor.before }))
+    };
+    if let Some(start_at) = &query.start_at {
+        obj.insert("startAt".to_string(), cursor(start_at)?);
+    }
+    if let Some(end_at) = &query.end_at {
+        obj.insert("endAt".to_string(), cursor(end_at)?);
+    }
+    if query.offset > 0 {
+        obj.insert("offset".to_string(), query.offset.into());
+    }
+    if let Some(limit) = query.limit {
+        obj.insert("limit".to_string(), limit.into());
+    }
+    Ok(Value::Object(obj))
+}
+
+fn encode_filter(filter: &structured_query::Filter) -> Result<Value, FirestoreConversionError> {
+    use structured_query::{composite_filter, field_filter, unary_filter};
+
+    Ok(match &filter.filter_type {
+        Some(FilterType::CompositeFilter(filter)) => {
+            let op = match composite_filter::Operator::from_i32(filter.op) {
+                Some(composite_filter::Operator::And) => "AND",
+                Some(composite_filter::Operator::Or) => "OR",
+                _ => "OPERATOR_UNSPECIFIED",
+            };
+            let filters = filter
+                .filters
+                .iter()
+                .map(encode_filter)
+                .collect::<Result<Vec<_>, _>>()?;
+            json!({ "compositeFilter": { "op": op, "filters": filters } })
+        }
+        Some(FilterType::FieldFilter(filter)) => {
+            use field_filter::Operator;
+            let op = match Operator::from_i32(filter.op) {
+                Some(Operator::LessThan) => "LESS_THAN",
+                Some(Operator::LessThanOrEqual) => "LESS_THAN_OR_EQUAL",
+                Some(Operator::GreaterThan) => "GREATER_THAN",
+                Some(Operator::GreaterThanOrEqual) => "GREATER_THAN_OR_EQUAL",
+                Some(Operator::Equal) => "EQUAL",
+                Some(Operator::NotEqual) => "NOT_EQUAL",
+                Some(Operator::ArrayContains) => "ARRAY_CONTAINS",
+                Some(Operator::In) => "IN",
+                Some(Operator::ArrayContainsAny) => "ARRAY_CONTAINS_ANY",
+                Some(Operator::NotIn) => "NOT_IN",
+                _ => "OPERATOR_UNSPECIFIED",
+            };
+            let value = match &filter.value {
+                Some(value) => value_to_rest_json(value)?,
+                None => json!({ "nullValue": null }),
+            };
+            json!({
+                "fieldFilter": { "field": field_reference(&filter.field), "op": op, "value": value }
+            })
+        }
+        Some(FilterType::UnaryFilter(filter)) => {
+            use unary_filter::{OperandType, Operator};
+            let op = match Operator::from_i32(filter.op) {
+                Some(Operator::IsNan) => "IS_NAN",
+                Some(Operator::IsNull) => "IS_NULL",
+                Some(Operator::IsNotNan) => "IS_NOT_NAN",
+                Some(Operator::IsNotNull) => "IS_NOT_NULL",
+                _ => "OPERATOR_UNSPECIFIED",
+            };
+            let field = match &filter.operand_type {
+                Some(OperandType::Field(field)) => field_reference(&Some(field.clone())),
+                None => field_reference(&None),
+            };
+            json!({ "unaryFilter": { "op": op, "field": field } })
+        }
+        None => json!({}),
+    })
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// responses
+
+fn array(val: Value) -> Vec<Value> {
+    match val {
+        Value::Array(values) => values,
+        Value::Null => Vec::new(),
+        val => vec![val],
+    }
+}
+
+fn string(val: &Value, key: &str) -> String {
+    val.get(key)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn bytes(val: &Value, key: &str) -> Result<Vec<u8>, FirestoreConversionError> {
+    match val.get(key).and_then(Value::as_str) {
+        Some(encoded) => BASE64.decode(encoded).map_err(|err| {
+            FirestoreConversionError::FromFirestoreError(format!("invalid {}: {}", key, err))
+        }),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn timestamp(
+    val: &Value,
+    key: &str,
+) -> Result<Option<prost_types::Timestamp>, FirestoreConversionError> {
+    val.get(key).map(timestamp_from_rest_json).transpose()
+}
+
+fn write_results(res: &Value) -> Result<Vec<firestore::WriteResult>, FirestoreConversionError> {
+    let results = match res.get("writeResults") {
+        Some(Value::Array(results)) => results,
+        _ => return Ok(Vec::new()),
+    };
+    results
+        .iter()
+        .map(|result| {
+            let transform_results = match result.get("transformResults") {
+                Some(Value::Array(values)) => values
+                    .iter()
+                    .map(value_from_rest_json)
+                    .collect::<Result<_, _>>()?,
+                _ => Vec::new(),
+            };
+            Ok(firestore::WriteResult {
+                update_time: timestamp(result, "updateTime")?,
+                transform_results,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firestore::client::BackendAuthorization;
+    use crate::firestore::structured_query::{FieldFilterOperator, StructuredQueryBuilder};
+    use crate::http_stub::HttpStub;
+    use pretty_assertions::assert_eq;
+
+    const DATABASE: &str = "projects/p/databases/(default)";
+
+    async fn stub_server(responses: Vec<(u16, Value)>) -> (RestBackend, HttpStub) {
+        let stub = HttpStub::start(responses).await;
+        let auth = Box::new(BackendAuthorization {
+            project_id: "p".to_string(),
+        });
+        (
+            RestBackend::with_base_url(auth, stub.base_url.clone()),
+            stub,
+        )
+    }
+
+    #[test]
+    fn encodes_queries_and_writes() {
+        let query = StructuredQueryBuilder::new()
+            .from("items")
+            .field_filter("rank", FieldFilterOperator::GreaterThan, 1)
+            .limit(2)
+            .build();
+        assert_eq!(
+            encode_query(&query).unwrap(),
+            json!({
+                "from": [{ "collectionId": "items", "allDescendants": false }],
+                "where": {
+                    "fieldFilter": {
+                        "field": { "fieldPath": "rank" },
+                        "op": "GREATER_THAN",
+                        "value": { "integerValue": "1" },
+                    }
+                },
+                "limit": 2,
+            })
+        );
+
+        let write = firestore::Write {
+            operation: Some(firestore::write::Operation::Delete("items/a".to_string())),
+            current_document: Some(firestore::Precondition {
+                condition_type: Some(firestore::precondition::ConditionType::Exists(true)),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            encode_write(&write).unwrap(),
+            json!({ "delete": "items/a", "currentDocument": { "exists": true } })
+        );
+    }
+
+    #[test]
+    fn maps_error_responses_to_status() {
+        let body = r#"{"error": {"code": 404, "message": "no doc", "status": "NOT_FOUND"}}"#;
+        let status = error_status(reqwest::StatusCode::NOT_FOUND, body);
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(status.message(), "no doc");
+
+        let status = error_status(reqwest::StatusCode::SERVICE_UNAVAILABLE, "down");
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+
+    #[test]
+    fn maps_streamed_error_responses_to_status() {
+        let body = r#"[{"error": {"code": 400, "message": "bad query", "status": "FAILED_PRECONDITION"}}]"#;
+        let status = error_status(reqwest::StatusCode::BAD_REQUEST, body);
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(status.message(), "bad query");
+    }
+
+    #[tokio::test]
+    async fn builds_urls_from_resource_names() {
+        let (backend, stub) = stub_server(vec![
+            (
+                200,
+                json!({ "name": format!("{}/documents/items/a b", DATABASE) }),
+            ),
+            (200, json!({})),
+        ])
+        .await;
+
+        backend
+            .get_document(firestore::GetDocumentRequest {
+                name: format!("{}/documents/items/a b", DATABASE),
+                mask: Some(firestore::DocumentMask {
+                    field_paths: vec!["x".to_string()],
+                }),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        backend
+            .delete_document(firestore::DeleteDocumentRequest {
+                name: format!("{}/documents/items/a", DATABASE),
+                current_document: Some(firestore::Precondition {
+                    condition_type: Some(firestore::precondition::ConditionType::Exists(true)),
+                }),
+            })
+            .await
+            .unwrap();
+
+        let requests = stub.requests();
+        assert_eq!(
+            requests[0].0,
+            "GET /v1/projects/p/databases/(default)/documents/items/a%20b?mask.fieldPaths=x"
+        );
+        assert_eq!(
+            requests[1].0,
+            "DELETE /v1/projects/p/databases/(default)/documents/items/a?currentDocument.exists=true"
+        );
+    }
+
+    #[tokio::test]
+    async fn decodes_streamed_query_responses() {
+        let (backend, stub) = stub_server(vec![(
+            200,
+            json!([
+                { "readTime": "2023-11-14T22:13:20Z", "skippedResults": 2 },
+                {
+                    "document": {
+                        "name": format!("{}/documents/items/a", DATABASE),
+                        "fields": { "rank": { "integerValue": "3" } },
+                    },
+                    "readTime": "2023-11-14T22:13:20Z",
+                },
+                { "readTime": "2023-11-14T22:13:20Z", "done": true },
+            ]),
+        )])
+        .await;
+
+        let responses = backend
+            .run_query(firestore::RunQueryRequest {
+                parent: format!("{}/documents", DATABASE),
+                query_type: Some(firestore::run_query_request::QueryType::StructuredQuery(
+                    StructuredQueryBuilder::new().from("items").build(),
+                )),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            stub.requests()[0],
+            (
+                "POST /v1/projects/p/databases/(default)/documents:runQuery".to_string(),
+                json!({
+                    "structuredQuery": {
+                        "from": [{ "collectionId": "items", "allDescendants": false }]
+                    }
+                })
+            )
+        );
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0].skipped_results, 2);
+        assert_eq!(responses[0].document, None);
+        assert_eq!(
+            responses[1].document.as_ref().unwrap().fields["rank"].value_type,
+            Some(firestore::value::ValueType::IntegerValue(3))
+        );
+        assert_eq!(
+            responses[2].continuation_selector,
+            Some(firestore::run_query_response::ContinuationSelector::Done(
+                true
+            ))
+        );
+        assert!(responses.iter().all(|res| res.read_time.is_some()));
+    }
+
+    #[tokio::test]
+    async fn decodes_batch_get_responses_and_streamed_errors() {
+        use firestore::batch_get_documents_response::Result as BatchGetResult;
+
+        let (backend, _) = stub_server(vec![(
+            200,
+            json!([
+                { "found": { "name": format!("{}/documents/items/a", DATABASE), "fields": {} } },
+                { "missing": format!("{}/documents/items/b", DATABASE) },
+                { "error": { "code": 503, "message": "try again", "status": "UNAVAILABLE" } },
+            ]),
+        )])
+        .await;
+
+        let responses = backend
+            .batch_get_documents(firestore::BatchGetDocumentsRequest {
+                database: DATABASE.to_string(),
+                documents: vec![
+                    format!("{}/documents/items/a", DATABASE),
+                    format!("{}/documents/items/b", DATABASE),
+                ],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(responses.len(), 3);
+        assert!(matches!(
+            responses[0].as_ref().unwrap().result,
+            Some(BatchGetResult::Found(_))
+        ));
+        assert_eq!(
+            responses[1].as_ref().unwrap().result,
+            Some(BatchGetResult::Missing(format!(
+                "{}/documents/items/b",
+                DATABASE
+            )))
+        );
+        let status = responses[2].as_ref().unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(status.message(), "try again");
+    }
+
+    #[tokio::test]
+    async fn decodes_commit_write_results() {
+        let (backend, stub) = stub_server(vec![(
+            200,
+            json!({
+                "writeResults": [
+                    { "updateTime": "2023-11-14T22:13:20Z" },
+                    { "transformResults": [{ "integerValue": "4" }] },
+                ],
+                "commitTime": "2023-11-14T22:13:21Z",
+            }),
+        )])
+        .await;
+
+        let res = backend
+            .commit(firestore::CommitRequest {
+                database: DATABASE.to_string(),
+                writes: vec![firestore::Write {
+                    operation: Some(firestore::write::Operation::Delete(format!(
+                        "{}/documents/items/a",
+                        DATABASE
+                    ))),
+                    ..Default::default()
+                }],
+                transaction: Vec::new(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            stub.requests()[0],
+            (
+                "POST /v1/projects/p/databases/(default)/documents:commit".to_string(),
+                json!({ "writes": [{ "delete": format!("{}/documents/items/a", DATABASE) }] })
+            )
+        );
+        assert_eq!(res.write_results.len(), 2);
+        assert_eq!(
+            res.write_results[0].update_time,
+            Some(prost_types::Timestamp {
+                seconds: 1700000000,
+                nanos: 0
+            })
+        );
+        assert_eq!(
+            res.write_results[1].transform_results[0].value_type,
+            Some(firestore::value::ValueType::IntegerValue(4))
+        );
+        assert!(res.commit_time.is_some());
+    }
+
+    #[tokio::test]
+    async fn passes_page_tokens() {
+        let (backend, stub) = stub_server(vec![
+            (
+                200,
+                json!({
+                    "documents": [{ "name": format!("{}/documents/items/a", DATABASE) }],
+                    "nextPageToken": "next",
+                }),
+            ),
+            (200, json!({ "collectionIds": ["items"] })),
+        ])
+        .await;
+
+        let res = backend
+            .list_documents(firestore::ListDocumentsRequest {
+                parent: format!("{}/documents", DATABASE),
+                collection_id: "items".to_string(),
+                page_size: 1,
+                page_token: "first".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(res.documents.len(), 1);
+        assert_eq!(res.next_page_token, "next");
+
+        let res = backend
+            .list_collection_ids(firestore::ListCollectionIdsRequest {
+                parent: format!("{}/documents", DATABASE),
+                page_token: "next".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(res.collection_ids, vec!["items".to_string()]);
+        assert_eq!(res.next_page_token, "");
+
+        let requests = stub.requests();
+        assert_eq!(
+            requests[0].0,
+            "GET /v1/projects/p/databases/(default)/documents/items?pageSize=1&pageToken=first"
+        );
+        assert_eq!(
+            requests[1],
+            (
+                "POST /v1/projects/p/databases/(default)/documents:listCollectionIds".to_string(),
+                json!({ "pageToken": "next" })
+            )
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use chrono::prelude::*;
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::{mpsc, oneshot};
+
+use super::collection::{CollectionChange, CollectionUpdate, SharedDocuments};
+use super::streaming::{CollectionStream, CollectionStreamController};
+use crate::FirestoreError;
+
+pub type SharedUpdate = Result<CollectionUpdate, Arc<FirestoreError>>;
+
+struct SharedState {
+    documents: SharedDocuments,
+    time: Option<DateTime<Utc>>,
+    resume_token: Vec<u8>,
+    /// Set once the documents reflect the state of the server.
+    synced: bool,
+    subscribers: Vec<mpsc::UnboundedSender<SharedUpdate>>,
+}
+
+impl SharedState {
+    fn apply(&mut self, update: &CollectionUpdate) {
+        for change in &update.changes {
+            match change {
+                CollectionChange::Change { id, .. } => {
+                    if let Some(doc) = update.documents.get(id) {
+                        self.documents.insert(id.clone(), doc.clone());
+                    }
+                }
+                CollectionChange::Delete { id, .. } => {
+                    self.documents.remove(id);
+                }
+            }
+        }
+        if update.time.is_some() {
+            self.time = update.time;
+        }
+        if !update.resume_token.is_empty() {
+            self.resume_token = update.resume_token.clone();
+        }
+        self.synced = true;
+    }
+
+    /// An update that adds all currently known documents.
+    fn snapshot(&self) -> CollectionUpdate {
+        let now = Utc::now();
+        CollectionUpdate {
+            changes: self
+                .documents
+                .keys()
+                .map(|id| CollectionChange::Change {
+                    id: id.clone(),
+                    time: now,
+                })
+                .collect(),
+            documents: self.documents.clone(),
+            time: self.time,
+            resume_token: self.resume_token.clone(),
+        }
+    }
+
+    fn broadcast(&mut self, update: SharedUpdate) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(update.clone()).is_ok());
+    }
+}
+
+/// Shares one collection stream between many subscribers. Created with
+/// [`super::streaming::ListenRequestBuilder::build_shared`].
+///
+/// The underlying stream is stopped once the listener and all subscriptions
+/// are dropped.
+#[derive(Clone)]
+pub struct SharedCollectionListener {
+    state: Arc<Mutex<SharedState>>,
+    _stop: Arc<oneshot::Sender<()>>,
+}
+
+impl SharedCollectionListener {
+    pub(crate) fn spawn(
+        mut stream: CollectionStream,
+        controller: CollectionStreamController,
+        documents: SharedDocuments,
+    ) -> Self {
+        let state = Arc::new(Mutex::new(SharedState {
+            documents,
+            time: None,
+            resume_token: Vec::new(),
+            synced: false,
+            subscribers: Vec::new(),
+        }));
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+
+        let task_state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = &mut stop_rx => {
+                        tracing::debug!("all subscribers of shared collection stream dropped");
+                        controller.stop().await;
+                        break;
+                    }
+
+                    item = stream.next() => {
+                        let mut state = task_state.lock().unwrap();
+                        match item {
+                            None => break,
+                            Some(Ok(update)) => {
+                                let first = !state.synced;
+                                state.apply(&update);
+                                if first {
+                                    // subscribers so far got nothing, the first
+                                    // update may be a delta on seeded documents
+                                    let snapshot = state.snapshot();
+                                    state.broadcast(Ok(snapshot));
+                                } else {
+                                    state.broadcast(Ok(update));
+                                }
+                            }
+                            Some(Err(err)) => state.broadcast(Err(Arc::new(err))),
+                        }
+                    }
+                }
+            }
+
+            // ends all subscriptions
+            task_state.lock().unwrap().subscribers.clear();
+        });
+
+        Self {
+            state,
+            _stop: Arc::new(stop_tx),
+        }
+    }
+
+    /// Subscribes to the collection. The first update contains all documents,
+    /// including seeded ones, followed by the live changes. Subscribers that
+    /// join before the stream synced get it with the first server update.
+    pub fn subscribe(&self) -> CollectionSubscription {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut state = self.state.lock().unwrap();
+        if state.synced {
+            let _ = tx.send(Ok(state.snapshot()));
+        }
+        state.subscribers.push(tx);
+
+        CollectionSubscription {
+            updates: rx,
+            _listener: self.clone(),
+        }
+    }
+
+    pub fn documents(&self) -> SharedDocuments {
+        self.state.lock().unwrap().documents.clone()
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.state.lock().unwrap().subscribers.len()
+    }
+}
+
+pub struct CollectionSubscription {
+    updates: mpsc::UnboundedReceiver<SharedUpdate>,
+    _listener: SharedCollectionListener,
+}
+
+impl Stream for CollectionSubscription {
+    type Item = SharedUpdate;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.updates.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use firestore_grpc::v1 as firestore;
+    use pretty_assertions::assert_eq;
+
+    fn update(name: &str) -> CollectionUpdate {
+        CollectionUpdate {
+            changes: vec![CollectionChange::Change {
+                id: name.to_string(),
+                time: Utc::now(),
+            }],
+            documents: SharedDocuments::from_iter([(
+                name.to_string(),
+                firestore::Document {
+                    name: name.to_string(),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        }
+    }
+
+    fn ids(update: &CollectionUpdate) -> Vec<&str> {
+        let mut ids = update
+            .documents
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn late_subscribers_get_a_snapshot_first() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let stream: CollectionStream = Box::pin(async_stream::stream! {
+            while let Some(update) = rx.recv().await {
+                yield update;
+            }
+        });
+        let (mut control_rx, controller) = CollectionStreamController::new();
+        let listener = SharedCollectionListener::spawn(stream, controller, SharedDocuments::new());
+
+        let mut early = listener.subscribe();
+        tx.send(Ok(update("a"))).unwrap();
+        assert_eq!(ids(&early.next().await.unwrap().unwrap()), vec!["a"]);
+
+        let mut late = listener.subscribe();
+        tx.send(Ok(update("b"))).unwrap();
+        assert_eq!(ids(&late.next().await.unwrap().unwrap()), vec!["a"]);
+        assert_eq!(ids(&late.next().await.unwrap().unwrap()), vec!["b"]);
+        assert_eq!(ids(&early.next().await.unwrap().unwrap()), vec!["b"]);
+
+        drop(listener);
+        drop(early);
+        drop(late);
+        assert!(matches!(
+            control_rx.recv().await,
+            Some(super::super::streaming::StreamControlMessage::Stop)
+        ));
+    }
+
+    #[tokio::test]
+    async fn early_subscribers_get_seeded_documents() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let stream: CollectionStream = Box::pin(async_stream::stream! {
+            while let Some(update) = rx.recv().await {
+                yield update;
+            }
+        });
+        let (_control_rx, controller) = CollectionStreamController::new();
+        let seeded = update("a").documents;
+        let listener = SharedCollectionListener::spawn(stream, controller, seeded);
+
+        let mut early = listener.subscribe();
+        // a resumed stream only sends what changed since the resume token
+        tx.send(Ok(update("b"))).unwrap();
+        assert_eq!(ids(&early.next().await.unwrap().unwrap()), vec!["a", "b"]);
+
+        tx.send(Ok(update("c"))).unwrap();
+        assert_eq!(ids(&early.next().await.unwrap().unwrap()), vec!["c"]);
+    }
+}
@@ -0,0 +1,311 @@
+use chrono::prelude::*;
+use firestore_grpc::v1 as firestore;
+use futures::Stream;
+use std::collections::HashMap;
+use std::pin::Pin;
+
+use super::collection::{CollectionChange, CollectionUpdate};
+use super::structured_query::StructuredQueryBuilder;
+use super::values::compare_documents;
+use crate::FirestoreError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentChangeType {
+    Added,
+    Modified,
+    Removed,
+}
+
+/// A change of a single document between two [`QuerySnapshot`]s.
+///
+/// Indexes are applied in order: `old_index` is the position of the document
+/// before and `new_index` the position after this change was applied, taking
+/// all previous changes of the snapshot into account.
+#[derive(Debug, Clone)]
+pub struct DocumentChange {
+    pub change_type: DocumentChangeType,
+    pub document: firestore::Document,
+    pub old_index: Option<usize>,
+    pub new_index: Option<usize>,
+}
+
+/// The complete, ordered result set of a query at one point in time.
+#[derive(Debug, Clone)]
+pub struct QuerySnapshot {
+    pub documents: Vec<firestore::Document>,
+    pub doc_changes: Vec<DocumentChange>,
+    /// This is the first snapshot of the stream.
+    pub is_initial: bool,
+    /// The stream was resumed with a resume token. The first snapshot then only
+    /// reports changes relative to the documents the state was seeded with.
+    pub from_resume: bool,
+    pub time: Option<DateTime<Utc>>,
+    pub resume_token: Vec<u8>,
+}
+
+impl QuerySnapshot {
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+}
+
+pub type SnapshotStream = Pin<Box<dyn Stream<Item = Result<QuerySnapshot, FirestoreError>> + Send>>;
+
+/// Maintains the full result set of a query across [`CollectionUpdate`]s and
+/// turns each of them into a [`QuerySnapshot`].
+#[derive(Debug, Clone)]
+pub struct QuerySnapshotState {
+    order_by: Vec<firestore::structured_query::Order>,
+    documents: Vec<firestore::Document>,
+    initial: bool,
+    from_resume: bool,
+}
+
+impl QuerySnapshotState {
+    pub fn new(query: &StructuredQueryBuilder) -> Self {
+        Self {
+            order_by: query.order_by.clone(),
+            documents: Vec::new(),
+            initial: true,
+            from_resume: false,
+        }
+    }
+
+    /// Seeds the state with documents that are already known, e.g. when
+    /// resuming a stream from a cache.
+    #[must_use]
+    pub fn with_documents<I>(mut self, documents: I) -> Self
+    where
+        I: IntoIterator<Item = firestore::Document>,
+    {
+        self.documents = documents.into_iter().collect();
+        let order_by = &self.order_by;
+        self.documents
+            .sort_by(|a, b| compare_documents(order_by, a, b));
+        self
+    }
+
+    #[must_use]
+    pub fn resumed(mut self, from_resume: bool) -> Self {
+        self.from_resume = from_resume;
+        self
+    }
+
+    pub fn documents(&self) -> &[firestore::Document] {
+        &self.documents
+    }
+
+    pub fn apply(&mut self, update: CollectionUpdate) -> QuerySnapshot {
+        let CollectionUpdate {
+            changes,
+            mut documents,
+            time,
+            resume_token,
+        } = update;
+
+        // only the last change of each document counts
+        let mut last_changes: Vec<(String, bool)> = Vec::new();
+        let mut positions = HashMap::new();
+        for change in changes {
+            let (id, deleted) = match change {
+                CollectionChange::Change { id, .. } => (id, false),
+                CollectionChange::Delete { id, .. } => (id, true),
+            };
+            match positions.get(&id) {
+                Some(pos) => last_changes[*pos] = (id, deleted),
+                None => {
+                    positions.insert(id.clone(), last_changes.len());
+                    last_changes.push((id, deleted));
+                }
+            }
+        }
+
+        let mut doc_changes = Vec::new();
+        for (id, deleted) in last_changes {
+            let old_index = self.documents.iter().position(|doc| doc.name == id);
+
+            if deleted {
+                if let Some(old_index) = old_index {
+                    let document = self.documents.remove(old_index);
+                    doc_changes.push(DocumentChange {
+                        change_type: DocumentChangeType::Removed,
+                        document,
+                        old_index: Some(old_index),
+                        new_index: None,
+                    });
+                }
+                continue;
+            }
+
+            let document = match documents.remove(&id) {
+                None => continue,
+                Some(document) => document,
+            };
+
+            let change_type = match old_index {
+                Some(old_index) => {
+                    let old = self.documents.remove(old_index);
+                    if old.fields == document.fields && old.update_time == document.update_time {
+                        self.documents.insert(old_index, old);
+                        continue;
+                    }
+                    DocumentChangeType::Modified
+                }
+                None => DocumentChangeType::Added,
+            };
+
+            let order_by = &self.order_by;
+            let new_index = self
+                .documents
+                .binary_search_by(|probe| compare_documents(order_by, probe, &document))
+                .unwrap_or_else(|index| index);
+            self.documents.insert(new_index, document.clone());
+
+            doc_changes.push(DocumentChange {
+                change_type,
+                document,
+                old_index,
+                new_index: Some(new_index),
+            });
+        }
+
+        let snapshot = QuerySnapshot {
+            documents: self.documents.clone(),
+            doc_changes,
+            is_initial: self.initial,
+            from_resume: self.from_resume,
+            time,
+            resume_token,
+        };
+
+        self.initial = false;
+        self.from_resume = false;
+
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use firestore::value::ValueType;
+    use pretty_assertions::assert_eq;
+
+    fn doc(name: &str, rank: i64) -> firestore::Document {
+        firestore::Document {
+            name: name.to_string(),
+            fields: HashMap::from_iter([(
+                "rank".to_string(),
+                firestore::Value {
+                    value_type: Some(ValueType::IntegerValue(rank)),
+                },
+            )]),
+            ..Default::default()
+        }
+    }
+
+    fn update(changed: Vec<firestore::Document>, deleted: &[&str]) -> CollectionUpdate {
+        let mut changes = changed
+            .iter()
+            .map(|doc| CollectionChange::Change {
+                id: doc.name.clone(),
+                time: Utc::now(),
+            })
+            .collect::<Vec<_>>();
+        changes.extend(deleted.iter().map(|id| CollectionChange::Delete {
+            id: id.to_string(),
+            time: Utc::now(),
+            last_read: None,
+        }));
+        CollectionUpdate {
+            changes,
+            documents: changed
+                .into_iter()
+                .map(|doc| (doc.name.clone(), doc))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn names(snapshot: &QuerySnapshot) -> Vec<&str> {
+        snapshot
+            .documents
+            .iter()
+            .map(|doc| doc.name.as_str())
+            .collect()
+    }
+
+    fn changes(
+        snapshot: &QuerySnapshot,
+    ) -> Vec<(&str, DocumentChangeType, Option<usize>, Option<usize>)> {
+        snapshot
+            .doc_changes
+            .iter()
+            .map(|change| {
+                (
+                    change.document.name.as_str(),
+                    change.change_type,
+                    change.old_index,
+                    change.new_index,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_documents_ordered_and_reports_indexes() {
+        use DocumentChangeType::*;
+
+        let query = StructuredQueryBuilder::new()
+            .from("items")
+            .order_by("rank")
+            .ascending()
+            .done();
+        let mut state = QuerySnapshotState::new(&query);
+
+        let snapshot = state.apply(update(vec![doc("c", 3), doc("a", 1), doc("b", 2)], &[]));
+        assert!(snapshot.is_initial);
+        assert_eq!(names(&snapshot), vec!["a", "b", "c"]);
+        assert_eq!(
+            changes(&snapshot),
+            vec![
+                ("c", Added, None, Some(0)),
+                ("a", Added, None, Some(0)),
+                ("b", Added, None, Some(1)),
+            ]
+        );
+
+        let snapshot = state.apply(update(vec![doc("a", 4), doc("b", 2)], &["c"]));
+        assert!(!snapshot.is_initial);
+        assert_eq!(names(&snapshot), vec!["b", "a"]);
+        // "b" did not change and is not reported
+        assert_eq!(
+            changes(&snapshot),
+            vec![
+                ("a", Modified, Some(0), Some(2)),
+                ("c", Removed, Some(1), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn resumed_state_reports_changes_against_initial_documents() {
+        let query = StructuredQueryBuilder::new().from("items");
+        let mut state = QuerySnapshotState::new(&query)
+            .with_documents([doc("b", 1), doc("a", 1)])
+            .resumed(true);
+
+        let snapshot = state.apply(update(Vec::new(), &["a"]));
+        assert!(snapshot.is_initial);
+        assert!(snapshot.from_resume);
+        assert_eq!(names(&snapshot), vec!["b"]);
+        assert_eq!(
+            changes(&snapshot),
+            vec![("a", DocumentChangeType::Removed, Some(0), None)]
+        );
+    }
+}
@@ -0,0 +1,418 @@
+use chrono::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::collection::CachedCollection;
+use crate::CachedCollectionError;
+
+/// Version of the persisted cache format. Bump it together with a migration
+/// step in [`migrate`].
+pub const CACHE_FORMAT_VERSION: u32 = 2;
+
+/// Persists a [`CachedCollection`].
+pub trait CollectionStore<T>: Send + Sync {
+    /// Loads the stored collection, `None` if nothing was stored yet.
+    fn load(&self) -> Result<Option<CachedCollection<T>>, CachedCollectionError>;
+
+    /// Stores `collection`. `dirty` are the ids of the documents that were
+    /// changed or removed since the last save, backends that can write
+    /// documents individually only need to write those.
+    fn save(
+        &self,
+        collection: &CachedCollection<T>,
+        dirty: &HashSet<String>,
+    ) -> Result<(), CachedCollectionError>;
+}
+
+#[derive(Serialize)]
+struct StoredCollectionRef<'a, T> {
+    version: u32,
+    name: &'a str,
+    documents: &'a HashMap<String, T>,
+    time: Option<DateTime<Utc>>,
+    resume_token: &'a Option<Vec<u8>>,
+}
+
+impl<'a, T> StoredCollectionRef<'a, T> {
+    fn new(collection: &'a CachedCollection<T>, documents: &'a HashMap<String, T>) -> Self {
+        Self {
+            version: CACHE_FORMAT_VERSION,
+            name: &collection.name,
+            documents,
+            time: collection.time,
+            resume_token: &collection.resume_token,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct StoredCollection<T> {
+    name: String,
+    documents: HashMap<String, T>,
+    time: Option<DateTime<Utc>>,
+    resume_token: Option<Vec<u8>>,
+}
+
+impl<T> StoredCollection<T> {
+    fn into_collection(self) -> CachedCollection<T> {
+        let mut collection = CachedCollection::new(self.name);
+        collection.documents = self.documents;
+        collection.time = self.time;
+        collection.resume_token = self.resume_token;
+        collection
+    }
+}
+
+/// Brings a stored collection from format `version` up to
+/// [`CACHE_FORMAT_VERSION`].
+fn migrate(
+    mut value: serde_json::Value,
+    mut version: u32,
+) -> Result<serde_json::Value, CachedCollectionError> {
+    if version > CACHE_FORMAT_VERSION {
+        return Err(CachedCollectionError::UnsupportedVersion(version));
+    }
+
+    while version < CACHE_FORMAT_VERSION {
+        tracing::debug!(
+            "migrating cached collection from format version {}",
+            version
+        );
+        if version == 1 {
+            // version 1 was the serialized CachedCollection, including the path
+            // of its cache file
+            if let Some(obj) = value.as_object_mut() {
+                obj.remove("cache_file");
+            }
+        }
+        version += 1;
+    }
+
+    Ok(value)
+}
+
+fn from_json_value<T: DeserializeOwned>(
+    value: serde_json::Value,
+) -> Result<CachedCollection<T>, CachedCollectionError> {
+    // caches written before the format was versioned don't have a version
+    let version = value
+        .get("version")
+        .and_then(|version| version.as_u64())
+        .unwrap_or(1) as u32;
+    let value = migrate(value, version)?;
+    let stored: StoredCollection<T> = serde_json::from_value(value)?;
+    Ok(stored.into_collection())
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// Stores the whole collection in one JSON file. The file is replaced
+/// atomically so that a crash while saving leaves the previous version intact.
+#[derive(Debug, Clone)]
+pub struct JsonFileStore {
+    path: PathBuf,
+}
+
+impl JsonFileStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl<T> CollectionStore<T> for JsonFileStore
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn load(&self) -> Result<Option<CachedCollection<T>>, CachedCollectionError> {
+        load_json_file(&self.path)
+    }
+
+    fn save(
+        &self,
+        collection: &CachedCollection<T>,
+        _dirty: &HashSet<String>,
+    ) -> Result<(), CachedCollectionError> {
+        save_json_file(&self.path, collection)
+    }
+}
+
+pub(crate) fn load_json_file<T: DeserializeOwned>(
+    path: &Path,
+) -> Result<Option<CachedCollection<T>>, CachedCollectionError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    tracing::debug!("loading from file {}", path.display());
+    let file = std::fs::OpenOptions::new().read(true).open(path)?;
+    let value = serde_json::from_reader(std::io::BufReader::new(file))?;
+    let mut collection = from_json_value(value)?;
+    collection.cache_file = Some(path.to_path_buf());
+    Ok(Some(collection))
+}
+
+pub(crate) fn save_json_file<T: Serialize>(
+    path: &Path,
+    collection: &CachedCollection<T>,
+) -> Result<(), CachedCollectionError> {
+    tracing::debug!("saving to file {}", path.display());
+    let json = serde_json::to_vec(&StoredCollectionRef::new(collection, &collection.documents))?;
+    write_atomically(path, &json)?;
+    Ok(())
+}
+
+/// Writes to a temporary file next to `path` first and then renames it.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_file = path.with_file_name(tmp_name);
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&tmp_file)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    std::fs::rename(&tmp_file, path)
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// Keeps the collection in memory only, e.g. for tests.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    stored: Mutex<Option<serde_json::Value>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T> CollectionStore<T> for MemoryStore
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn load(&self) -> Result<Option<CachedCollection<T>>, CachedCollectionError> {
+        match self.stored.lock().unwrap().clone() {
+            None => Ok(None),
+            Some(value) => from_json_value(value).map(Some),
+        }
+    }
+
+    fn save(
+        &self,
+        collection: &CachedCollection<T>,
+        _dirty: &HashSet<String>,
+    ) -> Result<(), CachedCollectionError> {
+        let value =
+            serde_json::to_value(StoredCollectionRef::new(collection, &collection.documents))?;
+        *self.stored.lock().unwrap() = Some(value);
+        Ok(())
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// Stores documents as individual entries of a sled database so that saving
+/// only writes the documents that changed.
+#[cfg(feature = "sled-store")]
+#[derive(Debug, Clone)]
+pub struct SledStore {
+    meta: sled::Tree,
+    documents: sled::Tree,
+}
+
+#[cfg(feature = "sled-store")]
+impl SledStore {
+    const META_KEY: &'static [u8] = b"collection";
+
+    /// Uses the trees `{collection}-meta` and `{collection}-documents` of `db`.
+    pub fn new<S: AsRef<str>>(db: &sled::Db, collection: S) -> Result<Self, CachedCollectionError> {
+        let collection = collection.as_ref();
+        Ok(Self {
+            meta: db
+                .open_tree(format!("{collection}-meta"))
+                .map_err(store_error)?,
+            documents: db
+                .open_tree(format!("{collection}-documents"))
+                .map_err(store_error)?,
+        })
+    }
+
+    pub fn open<P: AsRef<Path>, S: AsRef<str>>(
+        path: P,
+        collection: S,
+    ) -> Result<Self, CachedCollectionError> {
+        let db = sled::open(path).map_err(store_error)?;
+        Self::new(&db, collection)
+    }
+}
+
+#[cfg(feature = "sled-store")]
+fn store_error(err: sled::Error) -> CachedCollectionError {
+    CachedCollectionError::StoreError(err.to_string())
+}
+
+#[cfg(feature = "sled-store")]
+impl<T> CollectionStore<T> for SledStore
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn load(&self) -> Result<Option<CachedCollection<T>>, CachedCollectionError> {
+        let meta = match self.meta.get(Self::META_KEY).map_err(store_error)? {
+            None => return Ok(None),
+            Some(meta) => meta,
+        };
+        let mut value: serde_json::Value = serde_json::from_slice(&meta)?;
+
+        let mut documents = serde_json::Map::new();
+        for entry in self.documents.iter() {
+            let (id, doc) = entry.map_err(store_error)?;
+            documents.insert(
+                String::from_utf8_lossy(&id).to_string(),
+                serde_json::from_slice(&doc)?,
+            );
+        }
+        if let Some(obj) = value.as_object_mut() {
+            obj.insert("documents".to_string(), documents.into());
+        }
+
+        from_json_value(value).map(Some)
+    }
+
+    fn save(
+        &self,
+        collection: &CachedCollection<T>,
+        dirty: &HashSet<String>,
+    ) -> Result<(), CachedCollectionError> {
+        use sled::transaction::{ConflictableTransactionError, TransactionError};
+        use sled::Transactional;
+
+        let mut writes = Vec::with_capacity(dirty.len());
+        for id in dirty {
+            let doc = match collection.documents.get(id) {
+                Some(doc) => Some(serde_json::to_vec(doc)?),
+                None => None,
+            };
+            writes.push((id.as_bytes(), doc));
+        }
+        let no_documents = HashMap::new();
+        let meta = serde_json::to_vec(&StoredCollectionRef::new(collection, &no_documents))?;
+
+        // one transaction so that the resume token in the meta always matches
+        // the stored documents
+        (&self.meta, &self.documents)
+            .transaction(|(meta_tree, documents)| {
+                for (id, doc) in &writes {
+                    match doc {
+                        Some(doc) => documents.insert(*id, doc.as_slice())?,
+                        None => documents.remove(*id)?,
+                    };
+                }
+                meta_tree.insert(Self::META_KEY, meta.as_slice())?;
+                Ok::<_, ConflictableTransactionError<sled::Error>>(())
+            })
+            .map_err(|err| match err {
+                TransactionError::Abort(err) | TransactionError::Storage(err) => store_error(err),
+            })?;
+
+        self.documents.flush().map_err(store_error)?;
+        self.meta.flush().map_err(store_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn migrates_unversioned_caches() {
+        let legacy = json!({
+            "name": "items",
+            "cache_file": "/tmp/items-collection.json",
+            "documents": { "a": 1, "b": 2 },
+            "time": null,
+            "resume_token": [1, 2, 3],
+        });
+        let collection: CachedCollection<i32> = from_json_value(legacy).unwrap();
+        assert_eq!(collection.name, "items");
+        assert_eq!(
+            collection.documents,
+            HashMap::from_iter([("a".to_string(), 1), ("b".to_string(), 2)])
+        );
+        assert_eq!(collection.resume_token, Some(vec![1, 2, 3]));
+        assert!(collection.cache_file.is_none());
+    }
+
+    #[test]
+    fn rejects_newer_formats() {
+        let newer = json!({ "version": CACHE_FORMAT_VERSION + 1 });
+        assert!(matches!(
+            from_json_value::<i32>(newer),
+            Err(CachedCollectionError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn json_file_store_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("json-file-store-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let store = JsonFileStore::new(dir.join("items-collection.json"));
+        assert!(CollectionStore::<i32>::load(&store).unwrap().is_none());
+
+        let mut collection = CachedCollection::new("items");
+        collection.documents.insert("a".to_string(), 1);
+        store.save(&collection, &HashSet::new()).unwrap();
+
+        let loaded: CachedCollection<i32> = store.load().unwrap().unwrap();
+        assert_eq!(loaded.documents, collection.documents);
+        assert_eq!(loaded.cache_file.as_deref(), Some(store.path()));
+
+        // a truncated cache is rebuilt instead of failing the load
+        let json = std::fs::read(store.path()).unwrap();
+        std::fs::write(store.path(), &json[..json.len() / 2]).unwrap();
+        let rebuilt = CachedCollection::<i32>::load_from("items", &store).unwrap();
+        assert!(rebuilt.is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "sled-store")]
+    #[test]
+    fn sled_store_writes_dirty_documents() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let store = SledStore::new(&db, "items").unwrap();
+
+        let mut collection = CachedCollection::new("items");
+        collection.documents.insert("a".to_string(), 1);
+        collection.documents.insert("b".to_string(), 2);
+        collection.mark_all_dirty();
+        collection.save_to(&store).unwrap();
+        assert!(collection.dirty().is_empty());
+
+        collection.documents.remove("a");
+        collection.documents.insert("b".to_string(), 3);
+        let dirty = HashSet::from_iter(["a".to_string()]);
+        store.save(&collection, &dirty).unwrap();
+
+        // "b" was not marked dirty and keeps its stored value
+        let loaded: CachedCollection<i32> = store.load().unwrap().unwrap();
+        assert_eq!(loaded.documents, HashMap::from_iter([("b".to_string(), 2)]));
+    }
+}
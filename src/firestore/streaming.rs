@@ -1,5 +1,5 @@
 pub use firestore_grpc::google::firestore::v1::ListDocumentsResponse;
-use firestore_grpc::tonic::{self, metadata::MetadataValue, IntoStreamingRequest, Request};
+use firestore_grpc::tonic;
 use firestore_grpc::v1::{
     self as firestore,
     listen_response::ResponseType,
@@ -9,17 +9,23 @@ use firestore_grpc::v1::{
 };
 
 use chrono::prelude::*;
+use firebase_client_auth::TOKEN_EXPIRY_MARGIN;
 use futures::{Stream, StreamExt};
 use rand::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc;
 
+use super::backend::ListenRequestStream;
 use super::collection::*;
-use super::conversion::IntoFirestoreDocumentValue;
+use super::conversion::{FromFirestoreDocument, IntoFirestoreDocumentValue};
+use super::field_path::IntoFieldPath;
+use super::shared_listener::SharedCollectionListener;
+use super::snapshot::{QuerySnapshotState, SnapshotStream};
 use super::structured_query::{OrderBuilder, StructuredQueryBuilder};
-use crate::firestore::client::get_client;
+use crate::instrumentation::{listen_lag, listen_reconnect, FIRESTORE};
 use crate::FirestoreError;
 
 pub type UnaryFilterOperator = firestore::structured_query::unary_filter::Operator;
@@ -30,7 +36,17 @@ pub type CompositeFilterOperator = firestore::structured_query::composite_filter
 
 // "Rust" see https://github.com/googleapis/python-firestore/issues/51
 // "Rust".as_bytes().iter().rev().enumerate().map(|(i, b)| ((*b) as i32) << (8 * i)).sum();
-static GRPC_TARGET_ID: i32 = 0x52757374;
+pub(crate) const GRPC_TARGET_ID: i32 = 0x52757374;
+
+/// How long a stream authorized with a token that expires at `expires_at` can
+/// stay connected. `None` when the token is already within half of
+/// [`TOKEN_EXPIRY_MARGIN`], refreshing it is then left to the auth.
+fn reconnect_delay(expires_at: SystemTime, now: SystemTime) -> Option<Duration> {
+    expires_at
+        .checked_sub(TOKEN_EXPIRY_MARGIN / 2)?
+        .duration_since(now)
+        .ok()
+}
 
 pub struct ListenRequestBuilder {
     client: super::FirebaseClient,
@@ -42,6 +58,8 @@ pub struct ListenRequestBuilder {
     structured_query: StructuredQueryBuilder,
     restart_after_inactivity: std::time::Duration,
     disable_restart_after_inactivity: bool,
+    known_documents: HashSet<String>,
+    initial_documents: Vec<firestore::Document>,
 }
 
 impl ListenRequestBuilder {
@@ -64,6 +82,8 @@ impl ListenRequestBuilder {
             structured_query,
             restart_after_inactivity: std::time::Duration::from_secs(60 * 10),
             disable_restart_after_inactivity: false,
+            known_documents: HashSet::new(),
+            initial_documents: Vec::new(),
         }
     }
 
@@ -114,7 +134,7 @@ impl ListenRequestBuilder {
         self
     }
 
-    pub fn order_by<S: ToString>(self, field: S) -> OrderBuilder<Self> {
+    pub fn order_by<F: IntoFieldPath>(self, field: F) -> OrderBuilder<Self> {
         OrderBuilder::new(
             self,
             |me, order, start_at, end_at| {
@@ -127,16 +147,16 @@ impl ListenRequestBuilder {
     }
 
     #[must_use]
-    pub fn unary_filter<S: ToString>(mut self, field: S, op: UnaryFilterOperator) -> Self {
+    pub fn unary_filter<F: IntoFieldPath>(mut self, field: F, op: UnaryFilterOperator) -> Self {
         self.structured_query.set_unary_filter(field, op);
         self
     }
 
     #[must_use]
-    pub fn field_filter<T, S>(mut self, field: S, op: FieldFilterOperator, value: T) -> Self
+    pub fn field_filter<T, F>(mut self, field: F, op: FieldFilterOperator, value: T) -> Self
     where
         T: IntoFirestoreDocumentValue,
-        S: ToString,
+        F: IntoFieldPath,
     {
         self.structured_query.set_field_filter(field, op, value);
         self
@@ -156,16 +176,59 @@ impl ListenRequestBuilder {
         self
     }
 
+    /// The full names of the documents the caller already knows about, e.g.
+    /// from a cache that is resumed with [`Self::resume_token`]. They are
+    /// compared against the `ExistenceFilter` the server sends so that documents
+    /// deleted in the meantime can be detected.
+    #[must_use]
+    pub fn known_documents<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: ToString,
+    {
+        self.known_documents = names.into_iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// The documents the caller already has, e.g. from a cache that is resumed
+    /// with [`Self::resume_token`]. They are used as
+    /// [`Self::known_documents`] and as the starting point of
+    /// [`Self::build_snapshots`].
+    #[must_use]
+    pub fn initial_documents<I>(mut self, documents: I) -> Self
+    where
+        I: IntoIterator<Item = firestore::Document>,
+    {
+        self.initial_documents = documents.into_iter().collect();
+        self.known_documents = self
+            .initial_documents
+            .iter()
+            .map(|doc| doc.name.clone())
+            .collect();
+        self
+    }
+
     pub async fn build(
         &mut self,
+    ) -> Result<(CollectionStream<tonic::Status>, CollectionStreamController), FirestoreError> {
+        let state = CollectionStreamState::with_known_documents(self.known_documents.clone());
+        self.build_with_state(Arc::new(Mutex::new(state))).await
+    }
+
+    async fn build_with_state(
+        &self,
+        state: Arc<Mutex<CollectionStreamState>>,
     ) -> Result<(CollectionStream<tonic::Status>, CollectionStreamController), FirestoreError> {
         let (control_rx, controller) = CollectionStreamController::new();
-        let token = self.client.get_token().await?;
-        let req = self.build_req(control_rx);
-        let mut client = get_client(token).await?;
-        let res = client.listen(req).await?;
-        let inbound = res.into_inner();
-        Ok((CollectionStreamState::map_stream(inbound), controller))
+        let requests = self.build_requests(control_rx);
+        let inbound = self
+            .client
+            .backend
+            .listen(self.database.clone(), requests)
+            .await?;
+        let stream =
+            CollectionStreamState::map_stream_with_state(inbound, state, Some(controller.clone()));
+        Ok((stream, controller))
     }
 
     pub async fn build_retry(
@@ -174,18 +237,36 @@ impl ListenRequestBuilder {
     ) -> Result<(CollectionStream, CollectionStreamController), FirestoreError> {
         let (mut control_rx, controller) = CollectionStreamController::new();
 
+        // The state survives reconnects so that the known documents can be
+        // compared against existence filters of resumed streams.
+        let state = CollectionStreamState::with_known_documents(self.known_documents.clone());
+        let state = Arc::new(Mutex::new(state));
+
         let stream = async_stream::stream! {
             let mut retry_count = 0;
+            let mut reauthenticated = false;
+            let mut control_open = true;
             let disable_restart_after_inactivity = self.disable_restart_after_inactivity;
             let restart_after_inactivity = self.restart_after_inactivity;
 
+            let mut connected = false;
             'outer: loop {
+                if connected {
+                    listen_reconnect(FIRESTORE, &self.collection);
+                }
+                connected = true;
+
                 if retry_count > 0 {
                     tracing::debug!("delaying retry {}...", retry_count);
                     tokio::time::sleep(std::time::Duration::from_secs(retry_count)).await;
                 }
 
-                let (mut stream, current_controller) = match self.build().await {
+                if state.lock().unwrap().is_resetting() {
+                    tracing::debug!("stream {:?} is being reset, dropping resume token", self.collection);
+                    self.resume_token = None;
+                }
+
+                let (mut stream, current_controller) = match self.build_with_state(state.clone()).await {
                     Err(err) if retry_count < max_retry => {
                         tracing::warn!("sending streaming request for {:?} errored, retrying ({})", self.collection, err);
                         continue;
@@ -197,6 +278,20 @@ impl ListenRequestBuilder {
                     Ok(req) => req,
                 };
 
+                // Reconnect with a fresh token shortly before the current one
+                // expires, the server would otherwise close the stream.
+                let reconnect_in = self
+                    .client
+                    .token_expires_at()
+                    .await
+                    .and_then(|expires_at| reconnect_delay(expires_at, SystemTime::now()));
+                let token_expiry = async move {
+                    match reconnect_in {
+                        Some(duration) => tokio::time::sleep(duration).await,
+                        None => futures::future::pending().await,
+                    }
+                };
+                tokio::pin!(token_expiry);
 
                 // The stream sometimes seems to get "stuck", that is not even
                 // the keep alive requests in the background happening anymore.
@@ -207,6 +302,12 @@ impl ListenRequestBuilder {
 
                 'inner: loop {
                     tokio::select! {
+                        _ = &mut token_expiry => {
+                            tracing::debug!("auth token for stream {:?} is about to expire, reconnecting", self.collection);
+                            current_controller.stop().await;
+                            break 'inner;
+                        }
+
                         _ = check_interval.tick() => {
                             tracing::debug!("stream {:?} check", self.collection);
                             let last_update_duration = Utc::now() - last_update;
@@ -219,15 +320,19 @@ impl ListenRequestBuilder {
                             }
                         }
 
-                        msg = control_rx.recv() => {
+                        msg = control_rx.recv(), if control_open => {
                             match msg {
                                 Some(StreamControlMessage::Stop) => {
                                     tracing::debug!("retriable collection stream received stop message");
                                     current_controller.stop().await;
                                     break 'outer;
                                 },
-                                _ => {
+                                Some(StreamControlMessage::ResetTarget) => {
+                                    current_controller.reset_target().await;
+                                },
+                                None => {
                                     tracing::debug!("retriable collection stream for {:?}: control message channel closed", self.collection);
+                                    control_open = false;
                                 }
                             }
                         }
@@ -238,6 +343,13 @@ impl ListenRequestBuilder {
                                     tracing::debug!("retriable collection stream for {:?}: stream stopped", self.collection);
                                     break 'inner;
                                 },
+                                Some(Err(err)) if err.code() == tonic::Code::Unauthenticated && !reauthenticated => {
+                                    // reconnecting fetches a new token, this
+                                    // does not count as a retry
+                                    tracing::info!("stream {:?} is unauthenticated, reconnecting with a fresh token", self.collection);
+                                    reauthenticated = true;
+                                    break 'inner;
+                                }
                                 Some(Err(err)) => {
                                     if retry_count < max_retry {
                                         eprintln!(
@@ -252,8 +364,14 @@ impl ListenRequestBuilder {
                                 }
                                 Some(Ok(update)) => {
                                     last_update = Utc::now();
+                                    if let Some(lag) = update.time.and_then(|time| (last_update - time).to_std().ok()) {
+                                        listen_lag(FIRESTORE, lag);
+                                    }
                                     retry_count = 0;
-                                    self.resume_token = Some(update.resume_token.clone());
+                                    reauthenticated = false;
+                                    if !update.resume_token.is_empty() {
+                                        self.resume_token = Some(update.resume_token.clone());
+                                    }
                                     yield Ok(update);
                                 }
                             }
@@ -269,10 +387,79 @@ impl ListenRequestBuilder {
         Ok((Box::pin(stream), controller))
     }
 
-    fn build_req(
+    /// Like [`Self::build_retry`] but every update yields the complete, ordered
+    /// result set of the query.
+    pub async fn build_snapshots(
+        mut self,
+        max_retry: u64,
+    ) -> Result<(SnapshotStream, CollectionStreamController), FirestoreError> {
+        let initial_documents = std::mem::take(&mut self.initial_documents);
+        let mut state = QuerySnapshotState::new(&self.structured_query)
+            .with_documents(initial_documents)
+            .resumed(self.resume_token.is_some());
+
+        let (mut updates, controller) = self.build_retry(max_retry).await?;
+
+        let stream = async_stream::stream! {
+            while let Some(update) = updates.next().await {
+                yield update.map(|update| state.apply(update));
+            }
+        };
+
+        Ok((Box::pin(stream), controller))
+    }
+
+    /// Starts a [`Self::build_retry`] stream that can be shared between many
+    /// subscribers. It is stopped when the last handle is dropped.
+    pub async fn build_shared(
+        mut self,
+        max_retry: u64,
+    ) -> Result<SharedCollectionListener, FirestoreError> {
+        let documents = std::mem::take(&mut self.initial_documents)
+            .into_iter()
+            .map(|doc| (doc.name.clone(), doc))
+            .collect();
+        let (stream, controller) = self.build_retry(max_retry).await?;
+        Ok(SharedCollectionListener::spawn(
+            stream, controller, documents,
+        ))
+    }
+
+    /// Like [`Self::build_retry`] but converts the documents of each update to
+    /// `T`. Documents that fail to convert are reported as
+    /// [`FirestoreError::DocumentConversionError`] items before the update they
+    /// were part of.
+    pub async fn build_typed<T>(
+        self,
+        max_retry: u64,
+    ) -> Result<(TypedCollectionStream<T>, CollectionStreamController), FirestoreError>
+    where
+        T: FromFirestoreDocument + Send + 'static,
+    {
+        let (mut updates, controller) = self.build_retry(max_retry).await?;
+
+        let stream = async_stream::stream! {
+            while let Some(update) = updates.next().await {
+                match update {
+                    Err(err) => yield Err(err),
+                    Ok(update) => {
+                        let (update, errors) = TypedCollectionUpdate::convert(update);
+                        for err in errors {
+                            yield Err(err);
+                        }
+                        yield Ok(update);
+                    }
+                }
+            }
+        };
+
+        Ok((Box::pin(stream), controller))
+    }
+
+    fn build_requests(
         &self,
         mut control_rx: mpsc::Receiver<StreamControlMessage>,
-    ) -> Request<impl Stream<Item = firestore::ListenRequest> + Send + Sync> {
+    ) -> ListenRequestStream {
         let Self {
             collection,
             database,
@@ -284,28 +471,27 @@ impl ListenRequestBuilder {
 
         let structured_query = structured_query.clone().build();
 
-        let resume_type = resume_token.as_ref().map(|token| {
-            tracing::debug!("sending listen request with resume token");
-            firestore::target::ResumeType::ResumeToken(token.clone())
-        });
-
-        let req = firestore::ListenRequest {
+        let listen_request = |target_change| firestore::ListenRequest {
             database: database.to_string(),
             labels: HashMap::new(),
-            target_change: Some(firestore::listen_request::TargetChange::AddTarget(
-                firestore::Target {
-                    target_id: GRPC_TARGET_ID,
-                    expected_count: None,
-                    once: false,
-                    target_type: Some(TargetType::Query(QueryTarget {
-                        parent: parent.clone(),
-                        query_type: Some(QueryType::StructuredQuery(structured_query)),
-                    })),
-                    resume_type,
-                },
-            )),
+            target_change: Some(target_change),
         };
 
+        let req = listen_request(firestore::listen_request::TargetChange::AddTarget(
+            query_target(
+                GRPC_TARGET_ID,
+                parent,
+                structured_query.clone(),
+                resume_token.clone(),
+            ),
+        ));
+        let remove_req = listen_request(firestore::listen_request::TargetChange::RemoveTarget(
+            GRPC_TARGET_ID,
+        ));
+        let re_add_req = listen_request(firestore::listen_request::TargetChange::AddTarget(
+            query_target(GRPC_TARGET_ID, parent, structured_query, None),
+        ));
+
         let stream_name = format!("db={} collection={}", database, collection);
 
         #[rustfmt::skip]
@@ -316,11 +502,15 @@ impl ListenRequestBuilder {
 
             tracing::debug!("Listen request for collection {} send", stream_name);
 
-            loop {
-                if let Some(msg) = control_rx.recv().await {
-                    tracing::debug!("got control message when streaming {}: {:?}", stream_name, msg);
-                    match msg {
-                        StreamControlMessage::Stop => break,
+            while let Some(msg) = control_rx.recv().await {
+                tracing::debug!("got control message when streaming {}: {:?}", stream_name, msg);
+                match msg {
+                    StreamControlMessage::Stop => break,
+                    StreamControlMessage::ResetTarget => {
+                        // listen again without resume token, the server will
+                        // send all matching documents again
+                        yield remove_req.clone();
+                        yield re_add_req.clone();
                     }
                 }
             }
@@ -328,14 +518,30 @@ impl ListenRequestBuilder {
             tracing::debug!("Stopping stream for {}", stream_name);
         };
 
-        let mut req = tonic::Request::new(outbound).into_streaming_request();
-        let metadata = req.metadata_mut();
-        metadata.insert(
-            "google-cloud-resource-prefix",
-            MetadataValue::from_str(database).unwrap(),
-        );
+        Box::pin(outbound)
+    }
+}
 
-        req
+pub(crate) fn query_target(
+    target_id: i32,
+    parent: impl ToString,
+    structured_query: firestore::StructuredQuery,
+    resume_token: Option<Vec<u8>>,
+) -> firestore::Target {
+    let resume_type = resume_token.map(|token| {
+        tracing::debug!("sending listen request with resume token");
+        firestore::target::ResumeType::ResumeToken(token)
+    });
+
+    firestore::Target {
+        target_id,
+        expected_count: None,
+        once: false,
+        target_type: Some(TargetType::Query(QueryTarget {
+            parent: parent.to_string(),
+            query_type: Some(QueryType::StructuredQuery(structured_query)),
+        })),
+        resume_type,
     }
 }
 
@@ -344,6 +550,8 @@ impl ListenRequestBuilder {
 #[derive(Debug)]
 pub enum StreamControlMessage {
     Stop,
+    /// Remove the target and add it again without resume token.
+    ResetTarget,
 }
 
 #[derive(Clone)]
@@ -362,49 +570,153 @@ impl CollectionStreamController {
             tracing::error!("Error sending stream stop {}", err);
         }
     }
+
+    /// Re-listens to the target without resume token. Documents the server does
+    /// not send again will be reported as deleted.
+    pub async fn reset_target(&self) {
+        if let Err(err) = self
+            .control_tx
+            .send(StreamControlMessage::ResetTarget)
+            .await
+        {
+            tracing::error!("Error sending stream reset {}", err);
+        }
+    }
 }
 
 pub type CollectionStream<E = FirestoreError> =
     Pin<Box<dyn Stream<Item = Result<CollectionUpdate, E>> + Send>>;
 
+pub type TypedCollectionStream<T> =
+    Pin<Box<dyn Stream<Item = Result<TypedCollectionUpdate<T>, FirestoreError>> + Send>>;
+
 pub struct CollectionStreamState {
     pub target_ids: Arc<Mutex<Vec<i32>>>,
     pub documents: SharedDocuments,
     pub changes: Vec<CollectionChange>,
+    /// Names of all documents that currently match the target.
+    pub known_documents: HashSet<String>,
+    /// While the target is being reset: the documents known before the reset.
+    reset_documents: Option<HashSet<String>>,
+    reset_requested: bool,
 }
 
 impl CollectionStreamState {
-    pub fn map_stream(
-        inbound: tonic::Streaming<firestore::ListenResponse>,
-    ) -> CollectionStream<tonic::Status> {
-        let target_ids = Arc::new(Mutex::new(Vec::new()));
-        let documents = HashMap::new();
-        let changes = Vec::new();
-
-        let state = Arc::new(Mutex::new(Self {
-            documents,
-            changes,
-            target_ids,
-        }));
+    pub fn new() -> Self {
+        Self::with_known_documents(HashSet::new())
+    }
 
+    pub fn with_known_documents(known_documents: HashSet<String>) -> Self {
+        Self {
+            target_ids: Arc::new(Mutex::new(Vec::new())),
+            documents: HashMap::new(),
+            changes: Vec::new(),
+            known_documents,
+            reset_documents: None,
+            reset_requested: false,
+        }
+    }
+
+    pub fn map_stream<S>(inbound: S) -> CollectionStream<tonic::Status>
+    where
+        S: Stream<Item = Result<ListenResponse, tonic::Status>> + Send + 'static,
+    {
+        Self::map_stream_with_state(inbound, Arc::new(Mutex::new(Self::new())), None)
+    }
+
+    /// Like [`Self::map_stream`] but uses `state` and asks `controller` to reset
+    /// the target when the state detects that it is out of sync. Without a
+    /// controller, out of sync states are only logged.
+    pub fn map_stream_with_state<S>(
+        inbound: S,
+        state: Arc<Mutex<Self>>,
+        controller: Option<CollectionStreamController>,
+    ) -> CollectionStream<tonic::Status>
+    where
+        S: Stream<Item = Result<ListenResponse, tonic::Status>> + Send + 'static,
+    {
         Box::pin(inbound.filter_map(move |res| {
             let state = state.clone();
+            let controller = controller.clone();
             async move {
                 match res {
                     Err(err) => Some(Err(err)),
-                    Ok(res) => state.lock().unwrap().handle_listen_response(res).map(Ok),
+                    Ok(res) => {
+                        let (update, reset) = {
+                            let mut state = state.lock().unwrap();
+                            let update = state.handle_listen_response(res);
+                            let reset = state.take_reset_request();
+                            if reset && controller.is_none() {
+                                tracing::warn!(
+                                    "collection stream is out of sync but cannot be reset"
+                                );
+                                state.cancel_reset();
+                            }
+                            (update, reset)
+                        };
+                        if let (true, Some(controller)) = (reset, &controller) {
+                            controller.reset_target().await;
+                        }
+                        update.map(Ok)
+                    }
                 }
             }
         }))
     }
 
+    /// True while a reset of the target is in progress, i.e. until the server
+    /// has sent all matching documents again.
+    pub fn is_resetting(&self) -> bool {
+        self.reset_documents.is_some()
+    }
+
+    /// Returns true once after the state detected that the target needs to be
+    /// reset.
+    pub fn take_reset_request(&mut self) -> bool {
+        std::mem::take(&mut self.reset_requested)
+    }
+
+    /// Gives up on a reset that was requested but cannot be carried out.
+    pub fn cancel_reset(&mut self) {
+        if let Some(reset_documents) = self.reset_documents.take() {
+            self.known_documents.extend(reset_documents);
+        }
+        self.reset_requested = false;
+    }
+
+    fn begin_reset(&mut self) {
+        let known = std::mem::take(&mut self.known_documents);
+        match &mut self.reset_documents {
+            Some(reset_documents) => reset_documents.extend(known),
+            None => self.reset_documents = Some(known),
+        }
+    }
+
+    /// After a reset the server has sent all documents that still match.
+    /// Everything known before but not sent again was removed.
+    fn finish_reset(&mut self, read_time: Option<DateTime<Utc>>) {
+        let reset_documents = match self.reset_documents.take() {
+            None => return,
+            Some(reset_documents) => reset_documents,
+        };
+
+        for id in reset_documents {
+            if self.known_documents.contains(&id) {
+                continue;
+            }
+            tracing::trace!("document {} no longer matches after reset", id);
+            self.documents.remove(&id);
+            self.changes.push(CollectionChange::Delete {
+                id,
+                time: Utc::now(),
+                last_read: read_time,
+            });
+        }
+    }
+
     fn next_update(&mut self, change: TargetChange, force: bool) -> Option<CollectionUpdate> {
-        if self.changes.is_empty() {
-            return if force {
-                Some(CollectionUpdate::default())
-            } else {
-                None
-            };
+        if self.changes.is_empty() && !force {
+            return None;
         }
 
         Some(CollectionUpdate {
@@ -419,11 +731,11 @@ impl CollectionStreamState {
         })
     }
 
-    fn handle_listen_response(&mut self, res: ListenResponse) -> Option<CollectionUpdate> {
-        let response_type = match res.response_type {
-            None => return None,
-            Some(response_type) => response_type,
-        };
+    pub(crate) fn handle_listen_response(
+        &mut self,
+        res: ListenResponse,
+    ) -> Option<CollectionUpdate> {
+        let response_type = res.response_type?;
 
         match response_type {
             ResponseType::TargetChange(change) => {
@@ -449,10 +761,22 @@ impl CollectionStreamState {
 
                     TargetChangeType::Current => {
                         tracing::trace!("{:?}", change.target_change_type());
+                        self.finish_reset(change.read_time.as_ref().map(|t| {
+                            Utc.timestamp_opt(t.seconds, t.nanos as u32)
+                                .earliest()
+                                .expect("timestamp")
+                        }));
                         return self.next_update(change, true);
                     }
 
-                    TargetChangeType::Reset | TargetChangeType::NoChange => {
+                    TargetChangeType::Reset => {
+                        // the server will send all matching documents again
+                        tracing::trace!("{:?}", change.target_change_type());
+                        self.begin_reset();
+                        return self.next_update(change, false);
+                    }
+
+                    TargetChangeType::NoChange => {
                         tracing::trace!("{:?}", change.target_change_type());
                         return self.next_update(change, false);
                     }
@@ -466,6 +790,7 @@ impl CollectionStreamState {
                         id: document.name.clone(),
                         time: Utc::now(),
                     });
+                    self.known_documents.insert(document.name.clone());
                     self.documents.insert(document.name.clone(), document);
                 }
             }
@@ -490,6 +815,7 @@ impl CollectionStreamState {
                             .expect("timestamp")
                     }),
                 });
+                self.known_documents.remove(&document);
                 self.documents.remove(&document);
             }
 
@@ -497,10 +823,129 @@ impl CollectionStreamState {
                 count,
                 ..
             }) => {
-                tracing::debug!("received ExistenceFilter message, count={}. TODO handle it/update the collection", count);
+                let known = self.known_documents.len();
+                if self.is_resetting() || count as usize == known {
+                    tracing::debug!("received ExistenceFilter message, count={}", count);
+                } else {
+                    tracing::info!(
+                        "ExistenceFilter mismatch, server has {} documents, we know {}. Resetting target.",
+                        count,
+                        known
+                    );
+                    self.begin_reset();
+                    self.reset_requested = true;
+                }
             }
         };
 
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn streams_reconnect_before_their_token_expires() {
+        let now = SystemTime::now();
+        let expires_at = now + TOKEN_EXPIRY_MARGIN;
+        assert_eq!(
+            reconnect_delay(expires_at, now),
+            Some(TOKEN_EXPIRY_MARGIN / 2)
+        );
+        assert_eq!(reconnect_delay(expires_at, expires_at), None);
+        assert_eq!(reconnect_delay(now, now), None);
+    }
+
+    fn doc_change(name: &str) -> ListenResponse {
+        ListenResponse {
+            response_type: Some(ResponseType::DocumentChange(firestore::DocumentChange {
+                document: Some(firestore::Document {
+                    name: name.to_string(),
+                    ..Default::default()
+                }),
+                target_ids: vec![GRPC_TARGET_ID],
+                removed_target_ids: Vec::new(),
+            })),
+        }
+    }
+
+    fn existence_filter(count: i32) -> ListenResponse {
+        ListenResponse {
+            response_type: Some(ResponseType::Filter(firestore::ExistenceFilter {
+                target_id: GRPC_TARGET_ID,
+                count,
+                ..Default::default()
+            })),
+        }
+    }
+
+    fn target_change(change_type: TargetChangeType) -> ListenResponse {
+        ListenResponse {
+            response_type: Some(ResponseType::TargetChange(TargetChange {
+                target_change_type: change_type.into(),
+                target_ids: vec![GRPC_TARGET_ID],
+                ..Default::default()
+            })),
+        }
+    }
+
+    fn deleted_ids(update: &CollectionUpdate) -> Vec<&str> {
+        let mut ids = update
+            .changes
+            .iter()
+            .filter_map(|change| match change {
+                CollectionChange::Delete { id, .. } => Some(id.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn matching_existence_filter_is_ignored() {
+        let known = HashSet::from_iter(["a".to_string(), "b".to_string()]);
+        let mut state = CollectionStreamState::with_known_documents(known);
+        assert!(state.handle_listen_response(existence_filter(2)).is_none());
+        assert!(!state.take_reset_request());
+        assert!(!state.is_resetting());
+    }
+
+    #[test]
+    fn existence_filter_mismatch_resets_and_removes_missing_documents() {
+        let known = HashSet::from_iter(["a", "b", "c"].map(String::from));
+        let mut state = CollectionStreamState::with_known_documents(known);
+
+        assert!(state.handle_listen_response(existence_filter(1)).is_none());
+        assert!(state.take_reset_request());
+        assert!(!state.take_reset_request());
+        assert!(state.is_resetting());
+
+        // after re-listening the server only sends "b"
+        state.handle_listen_response(target_change(TargetChangeType::Remove));
+        state.handle_listen_response(target_change(TargetChangeType::Add));
+        state.handle_listen_response(doc_change("b"));
+        let update = state
+            .handle_listen_response(target_change(TargetChangeType::Current))
+            .expect("update");
+
+        assert!(!state.is_resetting());
+        assert_eq!(deleted_ids(&update), vec!["a", "c"]);
+        assert_eq!(update.documents.keys().collect::<Vec<_>>(), vec!["b"]);
+        assert_eq!(state.known_documents, HashSet::from_iter(["b".to_string()]));
+    }
+
+    #[test]
+    fn cancelled_reset_keeps_known_documents() {
+        let known = HashSet::from_iter(["a".to_string()]);
+        let mut state = CollectionStreamState::with_known_documents(known.clone());
+        state.handle_listen_response(existence_filter(0));
+        assert!(state.take_reset_request());
+        state.cancel_reset();
+        assert!(!state.is_resetting());
+        assert_eq!(state.known_documents, known);
+    }
+}
@@ -1,4 +1,5 @@
 use super::conversion::IntoFirestoreDocumentValue;
+use super::field_path::IntoFieldPath;
 use firestore_grpc::v1::{self as firestore, structured_query::CollectionSelector};
 
 pub use prost_types::Timestamp;
@@ -50,7 +51,7 @@ impl StructuredQueryBuilder {
     }
 
     #[must_use]
-    pub fn order_by<S: ToString>(self, field: S) -> OrderBuilder<Self> {
+    pub fn order_by<F: IntoFieldPath>(self, field: F) -> OrderBuilder<Self> {
         OrderBuilder::new(
             self,
             |me, order, start_at, end_at| {
@@ -63,29 +64,29 @@ impl StructuredQueryBuilder {
     }
 
     #[must_use]
-    pub fn unary_filter<S: ToString>(mut self, field: S, op: UnaryFilterOperator) -> Self {
+    pub fn unary_filter<F: IntoFieldPath>(mut self, field: F, op: UnaryFilterOperator) -> Self {
         self.filter = Some(unary_filter(field, op));
         self
     }
 
-    pub fn set_unary_filter<S: ToString>(&mut self, field: S, op: UnaryFilterOperator) {
+    pub fn set_unary_filter<F: IntoFieldPath>(&mut self, field: F, op: UnaryFilterOperator) {
         self.filter = Some(unary_filter(field, op));
     }
 
     #[must_use]
-    pub fn field_filter<T, S>(mut self, field: S, op: FieldFilterOperator, value: T) -> Self
+    pub fn field_filter<T, F>(mut self, field: F, op: FieldFilterOperator, value: T) -> Self
     where
         T: IntoFirestoreDocumentValue,
-        S: ToString,
+        F: IntoFieldPath,
     {
         self.filter = Some(field_filter(field, op, value));
         self
     }
 
-    pub fn set_field_filter<T, S>(&mut self, field: S, op: FieldFilterOperator, value: T)
+    pub fn set_field_filter<T, F>(&mut self, field: F, op: FieldFilterOperator, value: T)
     where
         T: IntoFirestoreDocumentValue,
-        S: ToString,
+        F: IntoFieldPath,
     {
         self.filter = Some(field_filter(field, op, value));
     }
@@ -129,11 +130,31 @@ impl StructuredQueryBuilder {
     }
 }
 
-pub fn unary_filter<S: ToString>(field: S, op: UnaryFilterOperator) -> Filter {
+/// The inverse of [`StructuredQueryBuilder::build`]. Projections and
+/// `all_descendants` of collection selectors are dropped.
+impl From<firestore::StructuredQuery> for StructuredQueryBuilder {
+    fn from(query: firestore::StructuredQuery) -> Self {
+        Self {
+            order_by: query.order_by,
+            filter: query.r#where,
+            limit: query.limit,
+            offset: query.offset,
+            start_at: query.start_at,
+            end_at: query.end_at,
+            from_collections: query
+                .from
+                .into_iter()
+                .map(|selector| selector.collection_id)
+                .collect(),
+        }
+    }
+}
+
+pub fn unary_filter<F: IntoFieldPath>(field: F, op: UnaryFilterOperator) -> Filter {
     use firestore::structured_query::*;
 
     let field = FieldReference {
-        field_path: field.to_string(),
+        field_path: field.into_field_path().to_string(),
     };
 
     let operand_type = unary_filter::OperandType::Field(field);
@@ -145,15 +166,15 @@ pub fn unary_filter<S: ToString>(field: S, op: UnaryFilterOperator) -> Filter {
     }
 }
 
-pub fn field_filter<T, S>(field: S, op: FieldFilterOperator, value: T) -> Filter
+pub fn field_filter<T, F>(field: F, op: FieldFilterOperator, value: T) -> Filter
 where
     T: IntoFirestoreDocumentValue,
-    S: ToString,
+    F: IntoFieldPath,
 {
     use firestore::structured_query::*;
 
     let field = FieldReference {
-        field_path: field.to_string(),
+        field_path: field.into_field_path().to_string(),
     };
     let value = value.into_document_value();
 
@@ -192,13 +213,13 @@ pub struct OrderBuilder<T> {
 }
 
 impl<T> OrderBuilder<T> {
-    pub fn new<S: ToString>(install_target: T, install: OrderInstaller<T>, field: S) -> Self {
+    pub fn new<F: IntoFieldPath>(install_target: T, install: OrderInstaller<T>, field: F) -> Self {
         Self {
             install_target,
             install,
             order: firestore::structured_query::Order {
                 field: Some(firestore::structured_query::FieldReference {
-                    field_path: field.to_string(),
+                    field_path: field.into_field_path().to_string(),
                 }),
                 ..Default::default()
             },
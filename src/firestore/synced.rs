@@ -0,0 +1,217 @@
+use futures::StreamExt;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot, RwLock, RwLockReadGuard};
+use tokio::task::JoinHandle;
+
+use super::collection::{CachedCollection, CollectionChange};
+use super::conversion::FromFirestoreDocument;
+use super::store::{CollectionStore, MemoryStore};
+use super::FirebaseClient;
+use crate::CachedCollectionError;
+
+pub struct SyncedCollectionBuilder<T> {
+    client: FirebaseClient,
+    collection: String,
+    store: Arc<dyn CollectionStore<T>>,
+    save_debounce: Duration,
+    max_retry: u64,
+}
+
+impl<T> SyncedCollectionBuilder<T>
+where
+    T: FromFirestoreDocument + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    /// Where the collection is persisted. Defaults to a [`MemoryStore`].
+    #[must_use]
+    pub fn store<S: CollectionStore<T> + 'static>(mut self, store: S) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+
+    /// Saves happen at most once per `duration`.
+    #[must_use]
+    pub fn save_debounce(mut self, duration: Duration) -> Self {
+        self.save_debounce = duration;
+        self
+    }
+
+    #[must_use]
+    pub fn max_retry(mut self, max_retry: u64) -> Self {
+        self.max_retry = max_retry;
+        self
+    }
+
+    /// Loads the stored collection, fills it if it has never been synced and
+    /// starts streaming changes in the background.
+    pub async fn start(self) -> Result<SyncedCollection<T>, CachedCollectionError> {
+        let Self {
+            client,
+            collection: name,
+            store,
+            save_debounce,
+            max_retry,
+        } = self;
+
+        let mut collection = CachedCollection::load_from(&name, store.as_ref())?;
+
+        let mut builder = client.stream_builder(&name);
+        match &collection.resume_token {
+            Some(resume_token) => {
+                tracing::debug!("resuming synced collection {}", name);
+                builder = builder
+                    .resume_token(resume_token.clone())
+                    .known_documents(collection.documents.keys());
+            }
+            None => {
+                tracing::debug!("filling synced collection {}", name);
+                collection.fill(&client).await?;
+                collection.save_to(store.as_ref())?;
+            }
+        }
+
+        let (mut stream, controller) = builder.build_retry(max_retry).await?;
+
+        let collection = Arc::new(RwLock::new(collection));
+        let (changes_tx, _) = broadcast::channel(64);
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
+
+        let task_collection = collection.clone();
+        let task_changes = changes_tx.clone();
+        let task = tokio::spawn(async move {
+            let mut save_at: Option<tokio::time::Instant> = None;
+
+            loop {
+                let save_timer = async move {
+                    match save_at {
+                        Some(save_at) => tokio::time::sleep_until(save_at).await,
+                        None => futures::future::pending().await,
+                    }
+                };
+
+                tokio::select! {
+                    _ = &mut shutdown_rx => break,
+
+                    _ = save_timer => {
+                        save_at = None;
+                        if let Err(err) = save(&task_collection, &store).await {
+                            tracing::error!("error saving synced collection {}: {}", name, err);
+                        }
+                    }
+
+                    item = stream.next() => {
+                        let update = match item {
+                            None => break,
+                            Some(Err(err)) => {
+                                tracing::error!("error in synced collection {}: {}", name, err);
+                                continue;
+                            }
+                            Some(Ok(update)) => update,
+                        };
+
+                        let changes = update.changes.clone();
+                        task_collection.write().await.update_from(update);
+                        if save_at.is_none() {
+                            save_at = Some(tokio::time::Instant::now() + save_debounce);
+                        }
+                        // no receivers is fine
+                        let _ = task_changes.send(Arc::new(changes));
+                    }
+                }
+            }
+
+            controller.stop().await;
+            tracing::debug!("stopped synced collection {}", name);
+
+            if task_collection.read().await.dirty().is_empty() {
+                Ok(())
+            } else {
+                save(&task_collection, &store).await
+            }
+        });
+
+        Ok(SyncedCollection {
+            collection,
+            changes: changes_tx,
+            shutdown: Some(shutdown_tx),
+            task: Some(task),
+        })
+    }
+}
+
+/// Saves the dirty documents of `collection` on the blocking thread pool. Only
+/// a read lock is held while saving, readers are not blocked by the disk IO.
+async fn save<T>(
+    collection: &Arc<RwLock<CachedCollection<T>>>,
+    store: &Arc<dyn CollectionStore<T>>,
+) -> Result<(), CachedCollectionError>
+where
+    T: Send + Sync + 'static,
+{
+    let dirty = collection.write().await.take_dirty();
+    let snapshot = collection.clone().read_owned().await;
+    let store = store.clone();
+    let saved = dirty.clone();
+    let result = tokio::task::spawn_blocking(move || store.save(&snapshot, &saved))
+        .await
+        .unwrap_or_else(|err| Err(CachedCollectionError::StoreError(err.to_string())));
+    if result.is_err() {
+        // retried with the next save
+        collection.write().await.mark_dirty(dirty);
+    }
+    result
+}
+
+/// A [`CachedCollection`] that keeps itself up to date with a collection
+/// stream and persists itself to a [`CollectionStore`].
+///
+/// Dropping it stops the stream as well, use [`SyncedCollection::shutdown`] to
+/// wait for the final save.
+pub struct SyncedCollection<T> {
+    collection: Arc<RwLock<CachedCollection<T>>>,
+    changes: broadcast::Sender<Arc<Vec<CollectionChange>>>,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<Result<(), CachedCollectionError>>>,
+}
+
+impl<T> SyncedCollection<T>
+where
+    T: FromFirestoreDocument + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    pub fn builder<S: ToString>(
+        client: FirebaseClient,
+        collection: S,
+    ) -> SyncedCollectionBuilder<T> {
+        SyncedCollectionBuilder {
+            client,
+            collection: collection.to_string(),
+            store: Arc::new(MemoryStore::new()),
+            save_debounce: Duration::from_secs(5),
+            max_retry: 10,
+        }
+    }
+
+    pub async fn read(&self) -> RwLockReadGuard<'_, CachedCollection<T>> {
+        self.collection.read().await
+    }
+
+    /// Receives the changes of every update applied to the collection.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Vec<CollectionChange>>> {
+        self.changes.subscribe()
+    }
+
+    /// Stops streaming and saves outstanding changes.
+    pub async fn shutdown(mut self) -> Result<(), CachedCollectionError> {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        match self.task.take() {
+            None => Ok(()),
+            Some(task) => task.await.unwrap_or_else(|err| {
+                tracing::error!("synced collection task failed: {}", err);
+                Ok(())
+            }),
+        }
+    }
+}
@@ -0,0 +1,523 @@
+//! Ordering and equality of Firestore values like the backend implements them.
+
+use firestore_grpc::v1::{self as firestore, value::ValueType};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+/// The position of a value type in Firestore's cross type ordering.
+pub(crate) fn type_order(value: &firestore::Value) -> u8 {
+    match &value.value_type {
+        None | Some(ValueType::NullValue(_)) => 0,
+        Some(ValueType::BooleanValue(_)) => 1,
+        Some(ValueType::IntegerValue(_)) | Some(ValueType::DoubleValue(_)) => 2,
+        Some(ValueType::TimestampValue(_)) => 3,
+        Some(ValueType::StringValue(_)) => 4,
+        Some(ValueType::BytesValue(_)) => 5,
+        Some(ValueType::ReferenceValue(_)) => 6,
+        Some(ValueType::GeoPointValue(_)) => 7,
+        Some(ValueType::ArrayValue(_)) => 8,
+        Some(ValueType::MapValue(_)) => 9,
+    }
+}
+
+/// The name of the value type, e.g. `"integer"`, used in conversion errors.
+pub fn value_type_name(value: &firestore::Value) -> &'static str {
+    match &value.value_type {
+        None | Some(ValueType::NullValue(_)) => "null",
+        Some(ValueType::BooleanValue(_)) => "boolean",
+        Some(ValueType::IntegerValue(_)) => "integer",
+        Some(ValueType::DoubleValue(_)) => "double",
+        Some(ValueType::TimestampValue(_)) => "timestamp",
+        Some(ValueType::StringValue(_)) => "string",
+        Some(ValueType::BytesValue(_)) => "bytes",
+        Some(ValueType::ReferenceValue(_)) => "reference",
+        Some(ValueType::GeoPointValue(_)) => "geo point",
+        Some(ValueType::ArrayValue(_)) => "array",
+        Some(ValueType::MapValue(_)) => "map",
+    }
+}
+
+/// Compares two values the way the Firestore backend orders them.
+pub fn compare_values(a: &firestore::Value, b: &firestore::Value) -> Ordering {
+    let (a_type, b_type) = match (&a.value_type, &b.value_type) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return type_order(a).cmp(&type_order(b)),
+    };
+
+    match (a_type, b_type) {
+        (ValueType::BooleanValue(a), ValueType::BooleanValue(b)) => a.cmp(b),
+        (ValueType::IntegerValue(a), ValueType::IntegerValue(b)) => a.cmp(b),
+        (ValueType::DoubleValue(a), ValueType::DoubleValue(b)) => compare_doubles(*a, *b),
+        (ValueType::IntegerValue(a), ValueType::DoubleValue(b)) => compare_int_double(*a, *b),
+        (ValueType::DoubleValue(a), ValueType::IntegerValue(b)) => {
+            compare_int_double(*b, *a).reverse()
+        }
+        (ValueType::TimestampValue(a), ValueType::TimestampValue(b)) => {
+            (a.seconds, a.nanos).cmp(&(b.seconds, b.nanos))
+        }
+        (ValueType::StringValue(a), ValueType::StringValue(b)) => a.cmp(b),
+        (ValueType::BytesValue(a), ValueType::BytesValue(b)) => a.cmp(b),
+        (ValueType::ReferenceValue(a), ValueType::ReferenceValue(b)) => compare_references(a, b),
+        (ValueType::GeoPointValue(a), ValueType::GeoPointValue(b)) => {
+            compare_doubles(a.latitude, b.latitude)
+                .then_with(|| compare_doubles(a.longitude, b.longitude))
+        }
+        (ValueType::ArrayValue(a), ValueType::ArrayValue(b)) => {
+            for (a, b) in a.values.iter().zip(b.values.iter()) {
+                let ord = compare_values(a, b);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            a.values.len().cmp(&b.values.len())
+        }
+        (ValueType::MapValue(a), ValueType::MapValue(b)) => compare_maps(&a.fields, &b.fields),
+        _ => type_order(a).cmp(&type_order(b)),
+    }
+}
+
+/// Canonical equality: integers and doubles are equal when they are
+/// mathematically equal, NaN equals NaN and map key order does not matter.
+pub fn values_equal(a: &firestore::Value, b: &firestore::Value) -> bool {
+    type_order(a) == type_order(b) && compare_values(a, b) == Ordering::Equal
+}
+
+/// A value that implements `Ord` and `Eq` with the semantics of
+/// [`compare_values`] and [`values_equal`], e.g. for sorting or using values
+/// as keys of a `BTreeMap`.
+#[derive(Debug, Clone)]
+pub struct OrderedValue(pub firestore::Value);
+
+impl PartialEq for OrderedValue {
+    fn eq(&self, other: &Self) -> bool {
+        values_equal(&self.0, &other.0)
+    }
+}
+
+impl Eq for OrderedValue {}
+
+impl PartialOrd for OrderedValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrderedValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_values(&self.0, &other.0)
+    }
+}
+
+impl From<firestore::Value> for OrderedValue {
+    fn from(value: firestore::Value) -> Self {
+        Self(value)
+    }
+}
+
+/// NaN sorts before all other numbers and equals itself, -0.0 equals 0.0.
+fn compare_doubles(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        (false, false) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+    }
+}
+
+fn compare_int_double(int: i64, double: f64) -> Ordering {
+    // 2^63, the first double that is out of i64 range
+    const I64_LIMIT: f64 = 9_223_372_036_854_775_808.0;
+
+    if double.is_nan() || double < -I64_LIMIT {
+        return Ordering::Greater;
+    }
+    if double >= I64_LIMIT {
+        return Ordering::Less;
+    }
+
+    let truncated = double.trunc();
+    match int.cmp(&(truncated as i64)) {
+        Ordering::Equal => compare_doubles(0.0, double - truncated),
+        ord => ord,
+    }
+}
+
+/// References are ordered by their path segments.
+fn compare_references(a: &str, b: &str) -> Ordering {
+    a.split('/').cmp(b.split('/'))
+}
+
+fn compare_maps(
+    a: &HashMap<String, firestore::Value>,
+    b: &HashMap<String, firestore::Value>,
+) -> Ordering {
+    let mut a_entries = a.iter().collect::<Vec<_>>();
+    let mut b_entries = b.iter().collect::<Vec<_>>();
+    a_entries.sort_by_key(|(key, _)| *key);
+    b_entries.sort_by_key(|(key, _)| *key);
+
+    for ((a_key, a_val), (b_key, b_val)) in a_entries.iter().zip(b_entries.iter()) {
+        let ord = a_key.cmp(b_key).then_with(|| compare_values(a_val, b_val));
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    a_entries.len().cmp(&b_entries.len())
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// Splits a field path like ``a.b.`c.d` `` into its segments.
+pub(crate) fn split_field_path(path: &str) -> Vec<String> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut chars = path.chars();
+    let mut quoted = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '`' => quoted = !quoted,
+            '\\' if quoted => {
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+            }
+            '.' if !quoted => segments.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    segments.push(current);
+    segments
+}
+
+/// Looks up the value at `path` in `doc`. `__name__` refers to the document
+/// name.
+pub fn document_field(doc: &firestore::Document, path: &str) -> Option<firestore::Value> {
+    if path == "__name__" {
+        return Some(firestore::Value {
+            value_type: Some(ValueType::ReferenceValue(doc.name.clone())),
+        });
+    }
+
+    let mut segments = split_field_path(path).into_iter();
+    let mut value = doc.fields.get(&segments.next()?)?;
+    for segment in segments {
+        value = match &value.value_type {
+            Some(ValueType::MapValue(map)) => map.fields.get(&segment)?,
+            _ => return None,
+        };
+    }
+    Some(value.clone())
+}
+
+/// Sets the field at the path `segments` to `value` or removes it if `value` is
+/// `None`. Missing parent maps are created.
+pub(crate) fn set_field(
+    fields: &mut HashMap<String, firestore::Value>,
+    segments: &[String],
+    value: Option<firestore::Value>,
+) {
+    let (first, rest) = match segments.split_first() {
+        None => return,
+        Some(split) => split,
+    };
+
+    if rest.is_empty() {
+        match value {
+            Some(value) => fields.insert(first.clone(), value),
+            None => fields.remove(first),
+        };
+        return;
+    }
+
+    if value.is_none() && !fields.contains_key(first) {
+        return;
+    }
+    let entry = fields.entry(first.clone()).or_default();
+    if !matches!(entry.value_type, Some(ValueType::MapValue(_))) {
+        entry.value_type = Some(ValueType::MapValue(Default::default()));
+    }
+    if let Some(ValueType::MapValue(map)) = &mut entry.value_type {
+        set_field(&mut map.fields, rest, value);
+    }
+}
+
+/// Copies the fields at `field_paths` from `update` to `document`, like a
+/// write with an update mask. Paths missing in `update` are removed.
+pub(crate) fn merge_masked_fields(
+    document: &mut firestore::Document,
+    update: &firestore::Document,
+    field_paths: &[String],
+) {
+    for path in field_paths {
+        let value = document_field(update, path);
+        set_field(&mut document.fields, &split_field_path(path), value);
+    }
+}
+
+/// Orders documents by the `order_by` clauses of a query and then by name, like
+/// the server orders query results.
+pub fn compare_documents(
+    order_by: &[firestore::structured_query::Order],
+    a: &firestore::Document,
+    b: &firestore::Document,
+) -> Ordering {
+    use firestore::structured_query::Direction;
+
+    let mut last_direction = Direction::Ascending;
+
+    for order in order_by {
+        let path = match &order.field {
+            Some(field) => &field.field_path,
+            None => continue,
+        };
+        let direction = match order.direction() {
+            Direction::Descending => Direction::Descending,
+            _ => Direction::Ascending,
+        };
+        last_direction = direction;
+
+        if path == "__name__" {
+            break;
+        }
+
+        let ord = match (document_field(a, path), document_field(b, path)) {
+            (Some(a), Some(b)) => compare_values(&a, &b),
+            (a, b) => a.is_some().cmp(&b.is_some()),
+        };
+        let ord = match direction {
+            Direction::Descending => ord.reverse(),
+            _ => ord,
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+
+    let ord = compare_references(&a.name, &b.name);
+    match last_direction {
+        Direction::Descending => ord.reverse(),
+        _ => ord,
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// The fields that differ between two versions of a document, as field paths.
+/// Changes inside of maps are reported with the path of the nested field.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DocumentDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl DocumentDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// All paths that differ, e.g. for an update mask.
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths = self
+            .added
+            .iter()
+            .chain(&self.removed)
+            .chain(&self.changed)
+            .cloned()
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+    }
+}
+
+pub fn diff_documents(old: &firestore::Document, new: &firestore::Document) -> DocumentDiff {
+    let mut diff = DocumentDiff::default();
+    diff_fields(&[], &old.fields, &new.fields, &mut diff);
+    diff
+}
+
+fn diff_fields(
+    parent: &[&str],
+    old: &HashMap<String, firestore::Value>,
+    new: &HashMap<String, firestore::Value>,
+    diff: &mut DocumentDiff,
+) {
+    let keys = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+
+    for key in keys {
+        let mut path = parent.to_vec();
+        path.push(key);
+
+        match (old.get(key), new.get(key)) {
+            (None, Some(_)) => diff.added.push(join_field_path(&path)),
+            (Some(_), None) => diff.removed.push(join_field_path(&path)),
+            (Some(old), Some(new)) => match (&old.value_type, &new.value_type) {
+                (Some(ValueType::MapValue(old)), Some(ValueType::MapValue(new))) => {
+                    diff_fields(&path, &old.fields, &new.fields, diff)
+                }
+                _ if !values_equal(old, new) || !same_number_type(old, new) => {
+                    diff.changed.push(join_field_path(&path))
+                }
+                _ => {}
+            },
+            (None, None) => {}
+        }
+    }
+}
+
+/// 1 and 1.0 are equal but a change from one to the other is still a change of
+/// the stored value.
+fn same_number_type(a: &firestore::Value, b: &firestore::Value) -> bool {
+    !matches!(
+        (&a.value_type, &b.value_type),
+        (
+            Some(ValueType::IntegerValue(_)),
+            Some(ValueType::DoubleValue(_))
+        ) | (
+            Some(ValueType::DoubleValue(_)),
+            Some(ValueType::IntegerValue(_))
+        )
+    )
+}
+
+/// Joins segments to a field path, quoting segments that are not simple
+/// identifiers.
+pub(crate) fn join_field_path(segments: &[&str]) -> String {
+    segments
+        .iter()
+        .map(|segment| {
+            let simple = segment
+                .chars()
+                .next()
+                .map(|c| c.is_ascii_alphabetic() || c == '_')
+                .unwrap_or(false)
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if simple {
+                segment.to_string()
+            } else {
+                format!("`{}`", segment.replace('\\', "\\\\").replace('`', "\\`"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn value(value_type: ValueType) -> firestore::Value {
+        firestore::Value {
+            value_type: Some(value_type),
+        }
+    }
+
+    #[test]
+    fn orders_values_across_types() {
+        let mut values = vec![
+            value(ValueType::StringValue("a".to_string())),
+            value(ValueType::DoubleValue(1.5)),
+            value(ValueType::IntegerValue(1)),
+            value(ValueType::DoubleValue(f64::NAN)),
+            value(ValueType::BooleanValue(true)),
+            firestore::Value { value_type: None },
+            value(ValueType::ReferenceValue("a/b/c".to_string())),
+            value(ValueType::ReferenceValue("a/b".to_string())),
+        ];
+        values.sort_by(compare_values);
+
+        let expected = vec![
+            firestore::Value { value_type: None },
+            value(ValueType::BooleanValue(true)),
+            value(ValueType::DoubleValue(f64::NAN)),
+            value(ValueType::IntegerValue(1)),
+            value(ValueType::DoubleValue(1.5)),
+            value(ValueType::StringValue("a".to_string())),
+            value(ValueType::ReferenceValue("a/b".to_string())),
+            value(ValueType::ReferenceValue("a/b/c".to_string())),
+        ];
+        assert_eq!(format!("{values:?}"), format!("{expected:?}"));
+        assert_eq!(
+            compare_values(
+                &value(ValueType::IntegerValue(2)),
+                &value(ValueType::DoubleValue(2.0))
+            ),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn splits_quoted_field_paths() {
+        assert_eq!(split_field_path("a.b"), vec!["a", "b"]);
+        assert_eq!(split_field_path("a.`b.c`.d"), vec!["a", "b.c", "d"]);
+        assert_eq!(split_field_path("`a\\`b`"), vec!["a`b"]);
+    }
+
+    #[test]
+    fn canonical_equality() {
+        let int = value(ValueType::IntegerValue(1));
+        let double = value(ValueType::DoubleValue(1.0));
+        let nan = value(ValueType::DoubleValue(f64::NAN));
+        assert!(values_equal(&int, &double));
+        assert!(values_equal(&nan, &nan));
+        assert!(!values_equal(
+            &int,
+            &value(ValueType::StringValue("1".to_string()))
+        ));
+        assert_eq!(
+            OrderedValue(value(ValueType::DoubleValue(0.0))),
+            OrderedValue(value(ValueType::DoubleValue(-0.0)))
+        );
+    }
+
+    #[test]
+    fn diffs_documents_by_field_path() {
+        let map = |fields: Vec<(&str, firestore::Value)>| {
+            value(ValueType::MapValue(firestore::MapValue {
+                fields: fields
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), value))
+                    .collect(),
+            }))
+        };
+        let doc = |fields: Vec<(&str, firestore::Value)>| firestore::Document {
+            fields: fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+            ..Default::default()
+        };
+
+        let old = doc(vec![
+            ("a", value(ValueType::IntegerValue(1))),
+            ("b", value(ValueType::IntegerValue(2))),
+            ("m", map(vec![("x", value(ValueType::BooleanValue(true)))])),
+        ]);
+        let new = doc(vec![
+            ("a", value(ValueType::DoubleValue(1.0))),
+            ("c", value(ValueType::IntegerValue(3))),
+            (
+                "m",
+                map(vec![
+                    ("x", value(ValueType::BooleanValue(true))),
+                    ("y.z", value(ValueType::BooleanValue(false))),
+                ]),
+            ),
+        ]);
+
+        let diff = diff_documents(&old, &new);
+        assert_eq!(
+            diff,
+            DocumentDiff {
+                added: vec!["c".to_string(), "m.`y.z`".to_string()],
+                removed: vec!["b".to_string()],
+                changed: vec!["a".to_string()],
+            }
+        );
+        assert!(diff_documents(&old, &old).is_empty());
+    }
+}
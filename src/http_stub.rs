@@ -0,0 +1,97 @@
+//! A minimal HTTP server for tests of the REST clients.
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use url::Url;
+
+/// A request the stub received: e.g. `GET /v1/projects/...?pageSize=2` and
+/// the JSON body, `null` if there is none.
+pub(crate) type StubRequest = (String, Value);
+
+pub(crate) struct HttpStub {
+    pub base_url: Url,
+    requests: Arc<Mutex<Vec<(StubRequest, HeaderMap)>>>,
+}
+
+impl HttpStub {
+    /// Answers one connection per response in `responses`, in order, and
+    /// records the requests.
+    pub async fn start(responses: Vec<(u16, Value)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            for (status, body) in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut socket).await;
+                recorded.lock().unwrap().push(request);
+
+                let body = body.to_string();
+                let response = format!(
+                    "HTTP/1.1 {} Stub\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        Self { base_url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<StubRequest> {
+        let requests = self.requests.lock().unwrap();
+        requests
+            .iter()
+            .map(|(request, _)| request.clone())
+            .collect()
+    }
+
+    /// The header `name` of the `index`th request.
+    pub fn header(&self, index: usize, name: &str) -> Option<String> {
+        let requests = self.requests.lock().unwrap();
+        let (_, headers) = requests.get(index)?;
+        Some(headers.get(name)?.to_str().ok()?.to_string())
+    }
+}
+
+async fn read_request(socket: &mut TcpStream) -> (StubRequest, HeaderMap) {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+    let head_len = loop {
+        let n = socket.read(&mut chunk).await.unwrap();
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+    let head = String::from_utf8_lossy(&buf[..head_len]).to_string();
+    let headers = head
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| {
+            let name = HeaderName::from_bytes(name.as_bytes()).unwrap();
+            (name, HeaderValue::from_str(value.trim()).unwrap())
+        })
+        .collect::<HeaderMap>();
+    let content_length = headers
+        .get("content-length")
+        .map(|len| len.to_str().unwrap().parse::<usize>().unwrap())
+        .unwrap_or_default();
+    while buf.len() < head_len + content_length {
+        let n = socket.read(&mut chunk).await.unwrap();
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    let line = head.lines().next().unwrap_or_default();
+    let line = line.trim_end_matches(" HTTP/1.1").to_string();
+    let body = serde_json::from_slice(&buf[head_len..]).unwrap_or(Value::Null);
+    ((line, body), headers)
+}
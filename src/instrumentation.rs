@@ -0,0 +1,247 @@
+//! Every Firestore and realtime DB request runs in a `firebase.request` span
+//! with the fields `service`, `operation`, `path`, `documents`, `latency_ms`
+//! and `status`. With the `metrics` feature the requests are also recorded
+//! with the [`metrics`](https://docs.rs/metrics) crate:
+//!
+//! - `firebase_client_requests_total` (counter; `service`, `operation`,
+//!   `status`)
+//! - `firebase_client_request_duration_seconds` (histogram; `service`,
+//!   `operation`)
+//! - `firebase_client_request_documents` (histogram; `service`, `operation`)
+//! - `firebase_client_listen_reconnects_total` (counter; `service`)
+//! - `firebase_client_listen_lag_seconds` (histogram; `service`), the time
+//!   between the read time of a listen update and its arrival. Firestore
+//!   only, realtime DB events have no read time.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::{field::Empty, Instrument};
+
+use crate::{FirestoreError, RealtimeDBError};
+
+pub(crate) const FIRESTORE: &str = "firestore";
+pub(crate) const RDB: &str = "rdb";
+
+/// The status label of a finished request.
+pub(crate) trait RequestStatus {
+    fn status(&self) -> String;
+}
+
+impl RequestStatus for FirestoreError {
+    fn status(&self) -> String {
+        match self {
+            FirestoreError::GrpcError(status) => format!("{:?}", status.code()),
+            FirestoreError::AuthenticationError(_) => "AuthenticationError".to_string(),
+            FirestoreError::InvalidMetadata(_) => "InvalidMetadata".to_string(),
+            FirestoreError::ConnectionError(_) => "ConnectionError".to_string(),
+            FirestoreError::SerializationError(_) => "SerializationError".to_string(),
+            FirestoreError::InterceptorError(_) => "InterceptorError".to_string(),
+            FirestoreError::DeadlineExceeded(_) => "DeadlineExceeded".to_string(),
+            FirestoreError::ConversionError(_) | FirestoreError::DocumentConversionError { .. } => {
+                "ConversionError".to_string()
+            }
+        }
+    }
+}
+
+impl RequestStatus for RealtimeDBError {
+    fn status(&self) -> String {
+        match self {
+            RealtimeDBError::RequestFailure(status, _) => status.as_u16().to_string(),
+            RealtimeDBError::RequestError(err) => match err.status() {
+                Some(status) => status.as_u16().to_string(),
+                None => "RequestError".to_string(),
+            },
+            RealtimeDBError::AuthenticationError(_) => "AuthenticationError".to_string(),
+            RealtimeDBError::SerializationError(_) => "SerializationError".to_string(),
+            RealtimeDBError::UrlError(_) => "UrlError".to_string(),
+            RealtimeDBError::InterceptorError(_) => "InterceptorError".to_string(),
+            RealtimeDBError::DeadlineExceeded(_) => "DeadlineExceeded".to_string(),
+            RealtimeDBError::Other(_) => "Other".to_string(),
+        }
+    }
+}
+
+/// Runs `request` in a span and records its outcome. `documents` counts the
+/// documents of a successful response.
+pub(crate) async fn instrument<T, E, F>(
+    service: &'static str,
+    operation: &'static str,
+    path: &str,
+    documents: impl FnOnce(&T) -> usize,
+    request: F,
+) -> Result<T, E>
+where
+    E: RequestStatus,
+    F: Future<Output = Result<T, E>>,
+{
+    let span = tracing::info_span!(
+        "firebase.request",
+        service,
+        operation,
+        path,
+        documents = Empty,
+        latency_ms = Empty,
+        status = Empty,
+    );
+    let start = Instant::now();
+    let result = request.instrument(span.clone()).await;
+    let latency = start.elapsed();
+
+    let (status, count) = match &result {
+        Ok(res) => ("OK".to_string(), Some(documents(res))),
+        Err(err) => (err.status(), None),
+    };
+    span.record("latency_ms", latency.as_millis() as u64);
+    span.record("status", status.as_str());
+    if let Some(count) = count {
+        span.record("documents", count);
+    }
+    span.in_scope(|| {
+        tracing::debug!(
+            "{} {} {}: {} in {:?}",
+            service,
+            operation,
+            path,
+            status,
+            latency
+        )
+    });
+
+    record_request(service, operation, &status, latency, count);
+    result
+}
+
+#[cfg(feature = "metrics")]
+fn record_request(
+    service: &'static str,
+    operation: &'static str,
+    status: &str,
+    latency: Duration,
+    documents: Option<usize>,
+) {
+    metrics::counter!(
+        "firebase_client_requests_total",
+        "service" => service,
+        "operation" => operation,
+        "status" => status.to_string(),
+    )
+    .increment(1);
+    metrics::histogram!(
+        "firebase_client_request_duration_seconds",
+        "service" => service,
+        "operation" => operation,
+    )
+    .record(latency.as_secs_f64());
+    if let Some(documents) = documents {
+        metrics::histogram!(
+            "firebase_client_request_documents",
+            "service" => service,
+            "operation" => operation,
+        )
+        .record(documents as f64);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+fn record_request(
+    _service: &'static str,
+    _operation: &'static str,
+    _status: &str,
+    _latency: Duration,
+    _documents: Option<usize>,
+) {
+}
+
+/// A listen stream of `service` on `path` reconnects.
+pub(crate) fn listen_reconnect(service: &'static str, path: &str) {
+    tracing::debug!("{} listen on {} reconnects", service, path);
+    #[cfg(feature = "metrics")]
+    metrics::counter!("firebase_client_listen_reconnects_total", "service" => service).increment(1);
+}
+
+/// A listen update of `service` arrived `lag` after its read time.
+pub(crate) fn listen_lag(service: &'static str, lag: Duration) {
+    tracing::trace!("{} listen lag {:?}", service, lag);
+    #[cfg(feature = "metrics")]
+    metrics::histogram!("firebase_client_listen_lag_seconds", "service" => service)
+        .record(lag.as_secs_f64());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::Layer;
+
+    type Fields = Arc<Mutex<HashMap<String, String>>>;
+
+    /// Collects the fields of all `firebase.request` spans.
+    struct CaptureFields(Fields);
+
+    impl Visit for CaptureFields {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            let value = format!("{:?}", value);
+            self.0
+                .lock()
+                .unwrap()
+                .insert(field.name().to_string(), value);
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            let value = value.to_string();
+            self.0
+                .lock()
+                .unwrap()
+                .insert(field.name().to_string(), value);
+        }
+    }
+
+    impl<S: tracing::Subscriber> Layer<S> for CaptureFields {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+            if attrs.metadata().name() == "firebase.request" {
+                attrs.record(&mut CaptureFields(self.0.clone()));
+            }
+        }
+
+        fn on_record(&self, _id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+            values.record(&mut CaptureFields(self.0.clone()));
+        }
+    }
+
+    async fn captured<T, E: RequestStatus>(result: Result<T, E>) -> HashMap<String, String> {
+        let fields = Fields::default();
+        let subscriber = tracing_subscriber::registry().with(CaptureFields(fields.clone()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let _ = instrument(FIRESTORE, "get_document", "items/a", |_| 1, async {
+            result
+        })
+        .await;
+
+        let fields = fields.lock().unwrap().clone();
+        fields
+    }
+
+    #[tokio::test]
+    async fn records_request_span_fields() {
+        let fields = captured(Ok::<_, FirestoreError>(())).await;
+        assert_eq!(fields["service"], "firestore");
+        assert_eq!(fields["operation"], "get_document");
+        assert_eq!(fields["path"], "items/a");
+        assert_eq!(fields["status"], "OK");
+        assert_eq!(fields["documents"], "1");
+        assert!(fields["latency_ms"].parse::<u64>().is_ok());
+
+        let err = FirestoreError::from(firestore_grpc::tonic::Status::not_found("items/a"));
+        let fields = captured(Err::<(), _>(err)).await;
+        assert_eq!(fields["status"], "NotFound");
+        assert!(!fields.contains_key("documents"));
+    }
+}
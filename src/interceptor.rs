@@ -0,0 +1,115 @@
+//! Hooks into the requests of
+//! [`FirebaseClient`](crate::firestore::FirebaseClient) and
+//! [`RdbClient`](crate::rdb::RdbClient), e.g. to add headers like
+//! `x-goog-request-params`, `x-goog-user-project` or correlation ids, or to
+//! audit responses.
+
+use async_trait::async_trait;
+pub use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+pub type InterceptorError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug)]
+pub struct InterceptedRequest {
+    /// `firestore` or `rdb`.
+    pub service: &'static str,
+    /// The gRPC method, e.g. `google.firestore.v1.Firestore/GetDocument`, or
+    /// the HTTP method.
+    pub method: String,
+    /// The resource name of gRPC requests, the URL of HTTP requests.
+    pub resource: String,
+    /// Headers to add to the request, as gRPC metadata or HTTP headers. The
+    /// authorization is added separately and is not part of it.
+    pub headers: HeaderMap,
+}
+
+impl InterceptedRequest {
+    pub(crate) fn new(service: &'static str, method: String, resource: String) -> Self {
+        Self {
+            service,
+            method,
+            resource,
+            headers: HeaderMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InterceptedResponse {
+    /// The gRPC code, e.g. `Ok` or `NotFound`, or the HTTP status, e.g. `404`.
+    pub status: String,
+    /// The response headers or gRPC metadata.
+    pub headers: HeaderMap,
+}
+
+#[async_trait]
+pub trait RequestInterceptor: Send + Sync + fmt::Debug {
+    /// Runs before every request. An error fails the request.
+    async fn on_request(&self, request: &mut InterceptedRequest) -> Result<(), InterceptorError> {
+        let _ = request;
+        Ok(())
+    }
+
+    /// Runs when the response, or for streams its headers, arrived.
+    fn on_response(&self, request: &InterceptedRequest, response: &InterceptedResponse) {
+        let _ = (request, response);
+    }
+}
+
+/// The interceptors of a client, in the order they were added. Clones share
+/// the interceptors, i.e. adding one affects all clones.
+#[derive(Clone, Default)]
+pub struct Interceptors {
+    interceptors: Arc<RwLock<Vec<Arc<dyn RequestInterceptor>>>>,
+}
+
+impl fmt::Debug for Interceptors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.all().iter()).finish()
+    }
+}
+
+impl Interceptors {
+    pub fn push<I: RequestInterceptor + 'static>(&self, interceptor: I) {
+        self.interceptors
+            .write()
+            .unwrap()
+            .push(Arc::new(interceptor));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.interceptors.read().unwrap().is_empty()
+    }
+
+    fn all(&self) -> Vec<Arc<dyn RequestInterceptor>> {
+        self.interceptors.read().unwrap().clone()
+    }
+
+    pub(crate) async fn on_request(
+        &self,
+        request: &mut InterceptedRequest,
+    ) -> Result<(), InterceptorError> {
+        for interceptor in self.all() {
+            interceptor.on_request(request).await?;
+        }
+        Ok(())
+    }
+
+    /// `response` is only built when there are interceptors.
+    pub(crate) fn on_response(
+        &self,
+        request: &InterceptedRequest,
+        response: impl FnOnce() -> InterceptedResponse,
+    ) {
+        let interceptors = self.all();
+        if interceptors.is_empty() {
+            return;
+        }
+        let response = response();
+        for interceptor in interceptors {
+            interceptor.on_response(request, &response);
+        }
+    }
+}
@@ -1,8 +1,14 @@
 #![allow(clippy::new_without_default)]
 
+pub mod app_check;
 pub mod firestore;
+#[cfg(test)]
+mod http_stub;
+mod instrumentation;
+pub mod interceptor;
 pub mod rdb;
 mod result;
+pub mod timeout;
 pub use firebase_client_admin_auth as admin_auth;
 pub use firebase_client_auth as auth;
 
@@ -1,12 +1,22 @@
 use firebase_client_auth::{scopes, GoogleAuth, GoogleServiceAccount, ServiceAccountAuthorization};
 use reqwest::{Body, Method, Response};
+use std::time::Duration;
 use url::Url;
 
+use crate::app_check::{AppCheckInterceptor, AppCheckProvider};
+use crate::instrumentation::{instrument, RDB};
+use crate::interceptor::{
+    InterceptedRequest, InterceptedResponse, Interceptors, RequestInterceptor,
+};
+use crate::timeout::{run_with_timeout, DEFAULT_TIMEOUT};
 use crate::RealtimeDBError;
 
 pub struct RdbClient {
     pub auth: GoogleAuth,
     shallow: bool,
+    timeout: Option<Duration>,
+    base_url: Option<Url>,
+    pub(crate) interceptors: Interceptors,
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -27,6 +37,9 @@ impl RdbClient {
         RdbClient {
             auth,
             shallow: false,
+            timeout: Some(DEFAULT_TIMEOUT),
+            base_url: None,
+            interceptors: Interceptors::default(),
         }
     }
 
@@ -35,10 +48,48 @@ impl RdbClient {
         self
     }
 
+    /// The timeout of requests, [`DEFAULT_TIMEOUT`] by default. `None` waits
+    /// forever. Listen streams have no timeout.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Adds `interceptor` to the requests and listen streams of this client.
+    pub fn add_interceptor<I: RequestInterceptor + 'static>(&self, interceptor: I) {
+        self.interceptors.push(interceptor);
+    }
+
+    /// Attaches the App Check token of `provider` to all requests and listen
+    /// streams, see [`AppCheckInterceptor`].
+    pub fn add_app_check<P: AppCheckProvider + 'static>(&self, provider: P) {
+        self.add_interceptor(AppCheckInterceptor::new(provider))
+    }
+
+    /// Sends requests to `base_url` instead of
+    /// `https://<project id>.firebaseio.com/`, e.g. to the emulator. Paths are
+    /// joined to it, so it should end with a `/`.
+    #[must_use]
+    pub fn base_url(mut self, base_url: Url) -> Self {
+        self.base_url = Some(base_url);
+        self
+    }
+
     pub fn project_id(&self) -> &str {
         self.auth.project_id()
     }
 
+    pub(crate) fn database_url(&self) -> Result<Url, RealtimeDBError> {
+        match &self.base_url {
+            Some(base_url) => Ok(base_url.clone()),
+            None => Ok(Url::parse(&format!(
+                "https://{}.firebaseio.com/",
+                self.project_id()
+            ))?),
+        }
+    }
+
     async fn make_request<T: Into<Body>, S: AsRef<str>>(
         &mut self,
         method: Method,
@@ -46,8 +97,7 @@ impl RdbClient {
         body: Option<T>,
         params: Option<&[&str]>,
     ) -> Result<Response, RealtimeDBError> {
-        let url = format!("https://{}.firebaseio.com/", self.project_id());
-        let mut url = Url::parse(&url)?.join(path.as_ref())?;
+        let mut url = self.database_url()?.join(path.as_ref())?;
         if let Some(params) = params {
             for query in params {
                 url.set_query(Some(query));
@@ -56,7 +106,18 @@ impl RdbClient {
 
         tracing::debug!("rdb request {}", url);
 
-        let client = reqwest::Client::new().request(method, url);
+        let mut request = InterceptedRequest::new(RDB, method.to_string(), url.to_string());
+        self.interceptors
+            .on_request(&mut request)
+            .await
+            .map_err(RealtimeDBError::InterceptorError)?;
+
+        let mut client = reqwest::Client::new()
+            .request(method, url)
+            .headers(request.headers.clone());
+        if let Some(timeout) = self.timeout {
+            client = client.timeout(timeout);
+        }
         let mut client = if let Some(token) = self.auth.get_token().await? {
             client.bearer_auth(token)
         } else {
@@ -66,7 +127,13 @@ impl RdbClient {
         if let Some(body) = body {
             client = client.body(body);
         }
-        self.check_status(path, client.send().await?).await
+        let res = client.send().await?;
+        self.interceptors
+            .on_response(&request, || InterceptedResponse {
+                status: res.status().as_u16().to_string(),
+                headers: res.headers().clone(),
+            });
+        self.check_status(path, res).await
     }
 
     async fn check_status<S: AsRef<str>>(
@@ -97,10 +164,19 @@ impl RdbClient {
         } else {
             None
         };
-        let res = self
-            .make_request::<&str, _>(Method::GET, path, None, params)
-            .await?;
-        Ok(res.json::<T>().await?)
+        instrument(
+            RDB,
+            "get",
+            path,
+            |_| 1,
+            run_with_timeout(self.timeout, async {
+                let res = self
+                    .make_request::<&str, _>(Method::GET, path, None, params)
+                    .await?;
+                Ok(res.json::<T>().await?)
+            }),
+        )
+        .await
     }
 
     pub async fn put_path<T: serde::ser::Serialize>(
@@ -109,9 +185,18 @@ impl RdbClient {
         value: &T,
     ) -> Result<(), RealtimeDBError> {
         let data = serde_json::to_string(value)?;
-        self.make_request(Method::PUT, path, Some(data), None)
-            .await?;
-        Ok(())
+        instrument(
+            RDB,
+            "put",
+            path,
+            |_| 1,
+            run_with_timeout(self.timeout, async {
+                self.make_request(Method::PUT, path, Some(data), None)
+                    .await?;
+                Ok(())
+            }),
+        )
+        .await
     }
 
     pub async fn patch_path<T: serde::ser::Serialize>(
@@ -120,9 +205,18 @@ impl RdbClient {
         value: &T,
     ) -> Result<(), RealtimeDBError> {
         let data = serde_json::to_string(value)?;
-        self.make_request(Method::PATCH, path, Some(data), None)
-            .await?;
-        Ok(())
+        instrument(
+            RDB,
+            "patch",
+            path,
+            |_| 1,
+            run_with_timeout(self.timeout, async {
+                self.make_request(Method::PATCH, path, Some(data), None)
+                    .await?;
+                Ok(())
+            }),
+        )
+        .await
     }
 
     pub async fn post_path<T: serde::ser::Serialize>(
@@ -131,15 +225,135 @@ impl RdbClient {
         value: &T,
     ) -> Result<PostResult, RealtimeDBError> {
         let data = serde_json::to_string(value)?;
-        let res = self
-            .make_request(Method::POST, path, Some(data), None)
-            .await?;
-        Ok(res.json::<PostResult>().await?)
+        instrument(
+            RDB,
+            "post",
+            path,
+            |_| 1,
+            run_with_timeout(self.timeout, async {
+                let res = self
+                    .make_request(Method::POST, path, Some(data), None)
+                    .await?;
+                Ok(res.json::<PostResult>().await?)
+            }),
+        )
+        .await
     }
 
     pub async fn delete_path(&mut self, path: &str) -> Result<(), RealtimeDBError> {
-        self.make_request::<&str, _>(Method::DELETE, path, None, None)
-            .await?;
-        Ok(())
+        instrument(
+            RDB,
+            "delete",
+            path,
+            |_| 1,
+            run_with_timeout(self.timeout, async {
+                self.make_request::<&str, _>(Method::DELETE, path, None, None)
+                    .await?;
+                Ok(())
+            }),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_stub::HttpStub;
+    use crate::interceptor::InterceptorError;
+    use crate::rdb::listener::Listener;
+    use async_trait::async_trait;
+    use pretty_assertions::assert_eq;
+    use reqwest::header::HeaderValue;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Clone)]
+    struct TestAuthorization;
+
+    #[async_trait]
+    impl firebase_client_auth::Authorization for TestAuthorization {
+        fn project_id(&self) -> &str {
+            "p"
+        }
+
+        async fn get_token(
+            &self,
+        ) -> Result<Option<String>, firebase_client_auth::error::GCloudAuthError> {
+            Ok(Some("test-token".to_string()))
+        }
+
+        fn box_clone(&self) -> GoogleAuth {
+            Box::new(self.clone())
+        }
+    }
+
+    /// Adds a correlation id and records the response statuses.
+    #[derive(Debug, Default)]
+    struct CorrelationId {
+        statuses: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl RequestInterceptor for CorrelationId {
+        async fn on_request(
+            &self,
+            request: &mut InterceptedRequest,
+        ) -> Result<(), InterceptorError> {
+            request
+                .headers
+                .insert("x-correlation-id", HeaderValue::from_static("abc"));
+            Ok(())
+        }
+
+        fn on_response(&self, request: &InterceptedRequest, response: &InterceptedResponse) {
+            let status = format!("{} {}", request.method, response.status);
+            self.statuses.lock().unwrap().push(status);
+        }
+    }
+
+    fn client(stub: &HttpStub, interceptor: CorrelationId) -> RdbClient {
+        let client = RdbClient::new(Box::new(TestAuthorization)).base_url(stub.base_url.clone());
+        client.add_interceptor(interceptor);
+        client
+    }
+
+    #[tokio::test]
+    async fn interceptors_add_headers_and_see_responses() {
+        let stub = HttpStub::start(vec![(200, json!({ "rank": 1 }))]).await;
+        let interceptor = CorrelationId::default();
+        let statuses = interceptor.statuses.clone();
+        let mut client = client(&stub, interceptor);
+
+        let value: serde_json::Value = client.get_path("items/a.json").await.unwrap();
+
+        assert_eq!(value, json!({ "rank": 1 }));
+        assert_eq!(stub.requests()[0].0, "GET /items/a.json");
+        assert_eq!(stub.header(0, "x-correlation-id").as_deref(), Some("abc"));
+        assert_eq!(
+            stub.header(0, "authorization").as_deref(),
+            Some("Bearer test-token")
+        );
+        assert_eq!(*statuses.lock().unwrap(), vec!["GET 200"]);
+    }
+
+    #[tokio::test]
+    async fn listeners_send_the_interceptor_headers() {
+        let stub = HttpStub::start(vec![(200, json!(null))]).await;
+        let client = client(&stub, CorrelationId::default());
+
+        let _updates = Listener::new(client).run("items");
+        let started = std::time::Instant::now();
+        while stub.requests().is_empty() {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(stub.requests()[0].0, "GET /items.json");
+        assert_eq!(stub.header(0, "x-correlation-id").as_deref(), Some("abc"));
+        assert_eq!(
+            stub.header(0, "accept").as_deref(),
+            Some("text/event-stream")
+        );
     }
 }
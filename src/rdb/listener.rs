@@ -1,9 +1,12 @@
+use crate::instrumentation::{listen_reconnect, RDB};
+use crate::interceptor::InterceptedRequest;
 use crate::RealtimeDBError;
 
 use super::{listener_updates::*, RdbClient};
 use es::Client;
 use eventsource_client as es;
 use futures::{Stream, TryStreamExt};
+use reqwest::header::HeaderMap;
 use serde_json::Value;
 use std::{ops::DerefMut, sync::Arc, time::Duration};
 use tokio::{
@@ -39,11 +42,10 @@ impl Listener {
         self,
         path: S,
     ) -> mpsc::Receiver<(Vec<String>, Arc<Mutex<ObservedValue>>)> {
-        let url = format!(
-            "https://{}.firebaseio.com/{}.json",
-            self.rdb_client.project_id(),
-            path.as_ref()
-        );
+        let url = self
+            .rdb_client
+            .database_url()
+            .and_then(|base_url| Ok(base_url.join(&format!("{}.json", path.as_ref()))?));
         let rdb_client = self.rdb_client;
 
         pub fn apply_action(
@@ -61,20 +63,33 @@ impl Listener {
         let (change_tx, change_rx) = mpsc::channel(5);
 
         tokio::spawn(async move {
+            let url = url?;
             let value = Arc::new(Mutex::new(ObservedValue::new()));
 
+            let mut connected = false;
             'outer: loop {
+                if connected {
+                    listen_reconnect(RDB, url.as_str());
+                }
+                connected = true;
+
                 let (tx, mut rx) = mpsc::channel(32);
                 let (_tx2, rx2) = mpsc::channel(1);
 
-                let url = Url::parse(&url)?;
                 let auth = rdb_client
                     .auth
                     .get_token()
                     .await?
                     .map(|t| format!("Bearer {t}"));
 
-                let task = start_http_connection(url, auth, tx, rx2);
+                let mut request = InterceptedRequest::new(RDB, "GET".to_string(), url.to_string());
+                rdb_client
+                    .interceptors
+                    .on_request(&mut request)
+                    .await
+                    .map_err(RealtimeDBError::InterceptorError)?;
+
+                let task = start_http_connection(url.clone(), auth, request.headers, tx, rx2);
 
                 while let Some(evt) = rx.recv().await {
                     if evt.event_type == "auth_revoked" {
@@ -155,16 +170,23 @@ impl Listener {
 fn start_http_connection(
     url: Url,
     auth: Option<String>,
+    headers: HeaderMap,
     tx: mpsc::Sender<es::Event>,
     mut rx: mpsc::Receiver<RdbControlMessage>,
 ) -> JoinHandle<Result<(), es::Error>> {
     tokio::spawn(async move {
         let client = es::ClientBuilder::for_url(url.as_ref())?;
-        let client = if let Some(auth) = auth {
+        let mut client = if let Some(auth) = auth {
             client.header("Authorization", &auth)?
         } else {
             client
         };
+        for (name, value) in &headers {
+            match value.to_str() {
+                Ok(value) => client = client.header(name.as_str(), value)?,
+                Err(_) => tracing::warn!("ignoring non-ASCII header {}", name),
+            }
+        }
         let client = client
             .header("Accept", "text/event-stream")?
             .reconnect(
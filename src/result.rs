@@ -5,8 +5,9 @@ pub enum FirestoreError {
     #[error("Authentication error: {0}")]
     AuthenticationError(#[from] crate::auth::error::GCloudAuthError),
 
+    /// Boxed, `Status` is large and would bloat every `Result`.
     #[error("GRPC error: {0}")]
-    GrpcError(#[from] firestore_grpc::tonic::Status),
+    GrpcError(Box<firestore_grpc::tonic::Status>),
 
     #[error("Invalid GRPC metadata: {0}")]
     InvalidMetadata(#[from] firestore_grpc::tonic::metadata::errors::InvalidMetadataValue),
@@ -19,6 +20,21 @@ pub enum FirestoreError {
 
     #[error("Conversion error: {0}")]
     ConversionError(#[from] FirestoreConversionError),
+
+    #[error("Cannot convert document {document}: {message}")]
+    DocumentConversionError { document: String, message: String },
+
+    #[error("Request interceptor error: {0}")]
+    InterceptorError(crate::interceptor::InterceptorError),
+
+    #[error("Deadline of {0:?} exceeded")]
+    DeadlineExceeded(std::time::Duration),
+}
+
+impl From<firestore_grpc::tonic::Status> for FirestoreError {
+    fn from(status: firestore_grpc::tonic::Status) -> Self {
+        FirestoreError::GrpcError(Box::new(status))
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -38,6 +54,12 @@ pub enum RealtimeDBError {
     #[error("URL error: {0}")]
     UrlError(#[from] url::ParseError),
 
+    #[error("Request interceptor error: {0}")]
+    InterceptorError(crate::interceptor::InterceptorError),
+
+    #[error("Deadline of {0:?} exceeded")]
+    DeadlineExceeded(std::time::Duration),
+
     #[error("error: {0}")]
     Other(&'static str),
 }
@@ -49,6 +71,58 @@ pub enum FirestoreConversionError {
 
     #[error("FromFirestoreDocument conversion error: {0}")]
     FromFirestoreError(String),
+
+    #[error("Expected {expected} value, got {actual} value")]
+    UnexpectedValueType {
+        expected: &'static str,
+        actual: &'static str,
+    },
+
+    #[error("Value {value} does not fit into {target}")]
+    OutOfRange { value: String, target: &'static str },
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FirestoreAdminError {
+    #[error("Authentication error: {0}")]
+    AuthenticationError(#[from] crate::auth::error::GCloudAuthError),
+
+    #[error("Serialization error: {0}")]
+    SerializationError(#[from] serde_json::Error),
+
+    #[error("Request error: {0}")]
+    RequestError(#[from] reqwest::Error),
+
+    #[error("URL error: {0}")]
+    UrlError(#[from] url::ParseError),
+
+    #[error("Request failure, status: {0}, message: {1}")]
+    RequestFailure(reqwest::StatusCode, String),
+
+    #[error("Operation {name} failed with code {code}: {message}")]
+    OperationFailed {
+        name: String,
+        code: i32,
+        message: String,
+    },
+
+    #[error("Operation {0} did not finish in time")]
+    OperationTimeout(String),
+
+    #[error("Deadline of {0:?} exceeded")]
+    DeadlineExceeded(std::time::Duration),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum OfflineWriteError {
+    #[error("Error accessing pending writes: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Error serializing pending writes: {0}")]
+    SerializationError(#[from] serde_json::Error),
+
+    #[error("Error decoding pending write: {0}")]
+    DecodeError(#[from] prost::DecodeError),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -64,4 +138,31 @@ pub enum CachedCollectionError {
 
     #[error("Firestore error")]
     FirestoreError(#[from] FirestoreError),
+
+    #[error("Cache format version {0} is not supported")]
+    UnsupportedVersion(u32),
+
+    #[error("Cache store error: {0}")]
+    StoreError(String),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AppCheckError {
+    #[error("Serialization error: {0}")]
+    SerializationError(#[from] serde_json::Error),
+
+    #[error("Request error: {0}")]
+    RequestError(#[from] reqwest::Error),
+
+    #[error("URL error: {0}")]
+    UrlError(#[from] url::ParseError),
+
+    #[error("Request failure, status: {0}, message: {1}")]
+    RequestFailure(reqwest::StatusCode, String),
+
+    #[error("Invalid token ttl: {0}")]
+    InvalidTtl(String),
+
+    #[error("Custom token error: {0}")]
+    CustomTokenError(String),
 }
@@ -0,0 +1,122 @@
+//! The request timeouts of the Firestore, realtime DB and admin clients.
+
+use firestore_grpc::tonic::Code;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::{FirestoreAdminError, FirestoreError, RealtimeDBError};
+
+/// The request timeout of new clients.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+tokio::task_local! {
+    static REQUEST_TIMEOUT: Duration;
+}
+
+/// The timeout of the current request, if any. Backends send it along as the
+/// deadline of the request.
+pub fn request_timeout() -> Option<Duration> {
+    REQUEST_TIMEOUT.try_with(|timeout| *timeout).ok()
+}
+
+/// The errors of requests that can time out.
+pub(crate) trait TimeoutError {
+    fn deadline_exceeded(timeout: Duration) -> Self;
+
+    /// A timeout reported by the server or the HTTP client.
+    fn is_timeout(&self) -> bool;
+}
+
+impl TimeoutError for FirestoreError {
+    fn deadline_exceeded(timeout: Duration) -> Self {
+        FirestoreError::DeadlineExceeded(timeout)
+    }
+
+    fn is_timeout(&self) -> bool {
+        matches!(self, FirestoreError::GrpcError(status) if status.code() == Code::DeadlineExceeded)
+    }
+}
+
+impl TimeoutError for RealtimeDBError {
+    fn deadline_exceeded(timeout: Duration) -> Self {
+        RealtimeDBError::DeadlineExceeded(timeout)
+    }
+
+    fn is_timeout(&self) -> bool {
+        matches!(self, RealtimeDBError::RequestError(err) if err.is_timeout())
+    }
+}
+
+impl TimeoutError for FirestoreAdminError {
+    fn deadline_exceeded(timeout: Duration) -> Self {
+        FirestoreAdminError::DeadlineExceeded(timeout)
+    }
+
+    fn is_timeout(&self) -> bool {
+        matches!(self, FirestoreAdminError::RequestError(err) if err.is_timeout())
+    }
+}
+
+/// Runs `request` with `timeout`, see [`request_timeout`]. Timeouts, local
+/// ones or reported by the server, fail with a `DeadlineExceeded` error.
+pub(crate) async fn run_with_timeout<T, E, F>(timeout: Option<Duration>, request: F) -> Result<T, E>
+where
+    E: TimeoutError,
+    F: Future<Output = Result<T, E>>,
+{
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return request.await,
+    };
+    match tokio::time::timeout(timeout, REQUEST_TIMEOUT.scope(timeout, request)).await {
+        Err(_) => Err(E::deadline_exceeded(timeout)),
+        Ok(Err(err)) if err.is_timeout() => Err(E::deadline_exceeded(timeout)),
+        Ok(res) => res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use firestore_grpc::tonic::Status;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn timeouts_fail_with_deadline_exceeded() {
+        let timeout = Duration::from_millis(10);
+        let seen = run_with_timeout(Some(timeout), async {
+            Ok::<_, FirestoreError>(request_timeout())
+        })
+        .await;
+        assert_eq!(seen.unwrap(), Some(timeout));
+
+        let err = run_with_timeout(
+            Some(timeout),
+            std::future::pending::<Result<(), FirestoreError>>(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, FirestoreError::DeadlineExceeded(t) if t == timeout));
+
+        let err = run_with_timeout(Some(timeout), async {
+            Err::<(), FirestoreError>(Status::deadline_exceeded("slow").into())
+        })
+        .await
+        .unwrap_err();
+        assert!(matches!(err, FirestoreError::DeadlineExceeded(_)));
+        assert_eq!(
+            run_with_timeout(None, async { Ok::<_, FirestoreError>(request_timeout()) })
+                .await
+                .unwrap(),
+            None
+        );
+
+        let err = run_with_timeout(
+            Some(timeout),
+            std::future::pending::<Result<(), FirestoreAdminError>>(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, FirestoreAdminError::DeadlineExceeded(t) if t == timeout));
+    }
+}